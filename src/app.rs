@@ -1,6 +1,6 @@
 use crate::config::Config;
 use crate::git::{FilterOptions, Repository};
-use crate::simple_ui::SimpleApp;
+use crate::ui;
 use crossterm::{
     event::{DisableMouseCapture, EnableMouseCapture},
     execute,
@@ -38,9 +38,9 @@ impl App {
         let backend = CrosstermBackend::new(stdout);
         let mut terminal = Terminal::new(backend)?;
 
-        // Create and run the simple UI
-        let mut ui_app = SimpleApp::new(self.repo.clone(), self.config.clone(), self.filter.clone(), commits);
-        let result = ui_app.run();
+        // Create and run the TUI
+        let mut ui_app = ui::App::new(&self.repo, self.config.clone(), self.filter.clone(), commits);
+        let result = ui_app.run(&mut terminal);
 
         // Restore terminal
         disable_raw_mode()?;
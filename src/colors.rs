@@ -0,0 +1,139 @@
+//! Turns the resolved theme into terminal styles.
+
+use crate::theme::Theme;
+use ratatui::style::{Color, Modifier, Style};
+use std::str::FromStr;
+
+/// Kind of reference a badge stands for, as far as coloring goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefKind {
+    Head,
+    Local,
+    Remote,
+    Tag,
+    Other,
+}
+
+impl RefKind {
+    pub fn of(name: &str) -> Self {
+        if name == "HEAD" {
+            RefKind::Head
+        } else if name.starts_with("refs/heads/") {
+            RefKind::Local
+        } else if name.starts_with("refs/remotes/") {
+            RefKind::Remote
+        } else if name.starts_with("refs/tags/") {
+            RefKind::Tag
+        } else {
+            RefKind::Other
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Palette {
+    theme: Theme,
+    enabled: bool,
+}
+
+impl Palette {
+    /// Wraps `theme`; with `enabled` unset every style comes back without
+    /// colors.
+    pub fn new(theme: Theme, enabled: bool) -> Self {
+        Self { theme, enabled }
+    }
+
+    /// Style for lane color `color`; lanes cycle through the palette.
+    pub fn lane(&self, color: usize) -> Style {
+        self.fg(self.theme.lanes[color % self.theme.lanes.len()])
+    }
+
+    /// Style for the HEAD commit and the checked-out branch.
+    pub fn head(&self) -> Style {
+        self.fg(self.theme.head).add_modifier(Modifier::BOLD)
+    }
+
+    /// Style for the row under the cursor.
+    pub fn selection(&self) -> Style {
+        if self.enabled {
+            Style::default().bg(self.theme.selection)
+        } else {
+            Style::default().add_modifier(Modifier::REVERSED)
+        }
+    }
+
+    /// Style for secondary text such as fold summaries.
+    pub fn muted(&self) -> Style {
+        self.fg(self.theme.muted)
+    }
+
+    /// Style for error messages.
+    pub fn error(&self) -> Style {
+        self.fg(self.theme.error).add_modifier(Modifier::BOLD)
+    }
+
+    /// Style for a ref badge, picked by the kind of ref.
+    pub fn reference(&self, kind: RefKind) -> Style {
+        match kind {
+            RefKind::Head => self.head(),
+            RefKind::Local => self.fg(self.theme.branch),
+            RefKind::Remote => self.fg(self.theme.remote),
+            RefKind::Tag => self.fg(self.theme.tag),
+            RefKind::Other => Style::default(),
+        }
+    }
+
+    fn fg(&self, color: Color) -> Style {
+        if self.enabled {
+            Style::default().fg(color)
+        } else {
+            Style::default()
+        }
+    }
+}
+
+/// Parses a color name (`blue`, `light_red`), a 256-color index (`208`) or
+/// an RGB hex value (`#ff8800`).
+pub fn parse_color(name: &str) -> Option<Color> {
+    Color::from_str(name.trim()).ok()
+}
+
+/// Whether `NO_COLOR` asks for colorless output (any non-empty value).
+pub fn no_color_env() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::theme::{Background, ColorDepth};
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("blue"), Some(Color::Blue));
+        assert_eq!(parse_color("light_red"), Some(Color::LightRed));
+        assert_eq!(parse_color("208"), Some(Color::Indexed(208)));
+        assert_eq!(parse_color("#ff8800"), Some(Color::Rgb(0xff, 0x88, 0x00)));
+        assert_eq!(parse_color("not-a-color"), None);
+    }
+
+    #[test]
+    fn test_lanes_cycle_and_no_color() {
+        let mut config = Config::default();
+        config.colors.graph1 = Some("blue".to_string());
+        config.colors.graph2 = Some("magenta".to_string());
+        config.colors.lanes = vec!["green".to_string()];
+        let theme = Theme::resolve(&config, Background::Dark, ColorDepth::TrueColor);
+
+        let palette = Palette::new(theme.clone(), true);
+        assert_eq!(palette.lane(0).fg, Some(Color::Blue));
+        assert_eq!(palette.lane(2).fg, Some(Color::Green));
+        assert_eq!(palette.lane(3).fg, Some(Color::Blue));
+
+        let plain = Palette::new(theme, false);
+        assert_eq!(plain.lane(1).fg, None);
+        assert_eq!(plain.reference(RefKind::of("refs/tags/v1")).fg, None);
+        assert!(plain.selection().add_modifier.contains(Modifier::REVERSED));
+    }
+}
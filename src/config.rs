@@ -1,30 +1,145 @@
+use crate::glyphs::GlyphSet;
+use crate::layers::{user_config_path, Layers};
+use crate::revwalk::RefSelection;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Settings read from `config.yml`. Keys are camelCase as documented; the
+/// snake_case spellings older versions wrote are still accepted.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default, rename_all = "camelCase")]
 pub struct Config {
+    /// `light`, `dark`, `auto` or the name of a theme under `themes`.
     pub style: String,
     pub unicode: bool,
+    /// Glyph set for the graph; falls back to `unicode` when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub glyphs: Option<String>,
+    #[serde(alias = "no_color")]
     pub no_color: bool,
+    /// strftime-style format for commit dates.
+    #[serde(alias = "date_format")]
     pub date_format: String,
+    #[serde(alias = "confirm_dangerous")]
     pub confirm_dangerous: bool,
+    /// `auto`, `always` or `never`.
     pub paging: String,
     pub colors: Colors,
+    pub themes: BTreeMap<String, ThemeConfig>,
     pub git: GitConfig,
+    pub graph: GraphConfig,
+    pub keys: KeysConfig,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Color overrides applied on top of the active theme; unset entries keep
+/// the theme's color.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
 pub struct Colors {
-    pub graph1: String,
-    pub graph2: String,
-    pub head: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub graph1: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub graph2: Option<String>,
+    /// Further lane colors, cycled after `graph1` and `graph2`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lanes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub head: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selection: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub muted: Option<String>,
+    /// Failed operations in the status bar.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A user-defined theme: colors layered over a built-in `light` or `dark`
+/// base (the detected one when unset).
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ThemeConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<String>,
+    #[serde(flatten)]
+    pub colors: Colors,
+}
+
+/// Key bindings: a preset plus per-action overrides, e.g. `move_down: [j, down]`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct KeysConfig {
+    /// `default`, `vim` or `emacs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    #[serde(flatten)]
+    pub bindings: BTreeMap<String, KeyList>,
+}
+
+/// One key binding or a list of them.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum KeyList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyList {
+    pub fn to_vec(&self) -> Vec<String> {
+        match self {
+            KeyList::One(key) => vec![key.clone()],
+            KeyList::Many(keys) => keys.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default, rename_all = "camelCase")]
 pub struct GitConfig {
+    /// Revision or range shown when `--range` is not given.
+    #[serde(alias = "default_range")]
     pub default_range: String,
+    /// `git log` ref options (`--all`, `--branches=<glob>`, `--exclude=<glob>`, ...)
+    /// added to `refs`.
+    #[serde(alias = "extra_args")]
     pub extra_args: Vec<String>,
+    /// Refs the history walk starts from.
+    #[serde(skip_serializing_if = "RefSelection::is_empty")]
+    pub refs: RefSelection,
+}
+
+impl GitConfig {
+    /// `refs` with the options in `extra_args` added.
+    pub fn ref_selection(&self) -> Result<RefSelection, String> {
+        Ok(self.refs.merged(&RefSelection::parse_args(&self.extra_args)?))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GraphConfig {
+    /// Widest the lane area may get, in columns (0 = half the terminal).
+    #[serde(alias = "max_width")]
+    pub max_width: usize,
+    /// Hide lanes that are idle across the visible rows.
+    #[serde(alias = "compress_lanes")]
+    pub compress_lanes: bool,
+}
+
+impl Default for GraphConfig {
+    fn default() -> Self {
+        Self {
+            max_width: 40,
+            compress_lanes: true,
+        }
+    }
 }
 
 impl Default for Config {
@@ -32,34 +147,34 @@ impl Default for Config {
         Self {
             style: "auto".to_string(),
             unicode: false,
+            glyphs: None,
             no_color: false,
             date_format: "%Y-%m-%d %H:%M".to_string(),
             confirm_dangerous: true,
             paging: "auto".to_string(),
-            colors: Colors {
-                graph1: "blue".to_string(),
-                graph2: "magenta".to_string(),
-                head: "cyan".to_string(),
-            },
-            git: GitConfig {
-                default_range: String::new(),
-                extra_args: Vec::new(),
-            },
+            colors: Colors::default(),
+            themes: BTreeMap::new(),
+            git: GitConfig::default(),
+            graph: GraphConfig::default(),
+            keys: KeysConfig::default(),
         }
     }
 }
 
 impl Config {
-    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
-        let config_path = get_config_path()?;
-        
-        if !config_path.exists() {
-            return Ok(Self::default());
+    /// The glyph set to draw the graph with.
+    pub fn glyph_set(&self) -> GlyphSet {
+        match self.glyphs.as_deref().map(str::parse) {
+            Some(Ok(set)) => set,
+            _ if self.unicode => GlyphSet::Unicode,
+            _ => GlyphSet::Ascii,
         }
+    }
 
-        let content = fs::read_to_string(config_path)?;
-        let config: Config = serde_yaml::from_str(&content)?;
-        Ok(config)
+    /// Loads the effective config for the current directory, merging every
+    /// layer (see [`Layers`]).
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        Layers::discover(Path::new(".")).config()
     }
 
     pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
@@ -75,7 +190,7 @@ impl Config {
     }
 }
 
-fn get_config_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let home = std::env::var("HOME")?;
-    Ok(PathBuf::from(home).join(".config").join("gittree").join("config.yml"))
+/// Where the user config file lives; see [`user_config_path`].
+pub fn get_config_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    user_config_path().ok_or_else(|| "neither XDG_CONFIG_HOME nor HOME is set".into())
 }
\ No newline at end of file
@@ -0,0 +1,81 @@
+//! Commit messages edited in the user's editor, the one git would open.
+
+use std::path::Path;
+use std::process::Command;
+
+/// Has `message` edited at `file` with `help` shown below it as comments,
+/// and returns the message cleaned up as git would; empty when the user
+/// cleared it. The terminal must be handed over first.
+pub fn edit(
+    workdir: &Path,
+    file: &Path,
+    message: &str,
+    help: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut text = format!("{}\n\n", message.trim_end());
+    for line in help.lines() {
+        text.push_str(format!("# {}", line).trim_end());
+        text.push('\n');
+    }
+    std::fs::write(file, text)?;
+
+    // `git var` picks GIT_EDITOR, core.editor, VISUAL, EDITOR or vi, and git
+    // runs it through the shell, so it may carry arguments.
+    let output = Command::new("git")
+        .args(["var", "GIT_EDITOR"])
+        .current_dir(workdir)
+        .output()?;
+    let editor = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let editor = if editor.is_empty() {
+        "vi".to_string()
+    } else {
+        editor
+    };
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(file)
+        .current_dir(workdir)
+        .status()?;
+    if !status.success() {
+        return Err(format!("the editor ({}) failed: {}", editor, status).into());
+    }
+    Ok(cleanup(&std::fs::read_to_string(file)?))
+}
+
+/// `text` as git's default cleanup leaves it: comment lines and trailing
+/// whitespace dropped, runs of blank lines collapsed to one and blank lines
+/// at either end removed.
+pub fn cleanup(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(str::trim_end)
+    {
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cleanup() {
+        let text = "\n\nSubject  \n\n\n# a comment\nBody\n#\n\n";
+        assert_eq!(cleanup(text), "Subject\n\nBody");
+        assert_eq!(cleanup("# only comments\n\n"), "");
+    }
+}
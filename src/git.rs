@@ -1,9 +1,16 @@
+use crate::graph::Cell;
+use crate::journal::{Entry, Journal, Snapshot};
+use crate::rebase::Plan;
+use crate::refs::{self, Decoration, Ref, Sort, Tracking};
+use crate::revwalk::{self, RefSelection};
+use crate::tags::{self, Tag};
 use chrono::{DateTime, Utc};
 use git2::{Repository as Git2Repository, Oid};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::Arc;
+use std::rc::Rc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Commit {
@@ -14,239 +21,372 @@ pub struct Commit {
     pub email: String,
     pub date: DateTime<Utc>,
     pub parents: Vec<String>,
+    /// Full names of the refs on this commit, `HEAD` included.
     pub refs: Vec<String>,
+    /// `refs` as shown next to the commit.
+    #[serde(default)]
+    pub decorations: Vec<Decoration>,
     pub lane: usize,
-    pub graph: Vec<GraphLine>,
+    pub graph: Vec<Cell>,
     pub files: Vec<String>,
     pub stats: HashMap<String, i32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GraphLine {
-    pub line_type: GraphLineType,
-    pub lane: usize,
-    pub merge: bool,
+/// Hash of the synthetic commit standing for uncommitted changes.
+pub const WORK_TREE: &str = "work-tree";
+
+impl Commit {
+    /// A row for the uncommitted changes in the working tree, drawn as a
+    /// child of HEAD.
+    pub fn work_tree(head: &str, status: &StatusCounts) -> Self {
+        Commit {
+            hash: WORK_TREE.to_string(),
+            short_hash: String::new(),
+            message: format!(
+                "Uncommitted changes ({} staged, {} unstaged, {} untracked)",
+                status.staged, status.unstaged, status.untracked
+            ),
+            author: String::new(),
+            email: String::new(),
+            date: Utc::now(),
+            parents: vec![head.to_string()],
+            refs: Vec::new(),
+            decorations: Vec::new(),
+            lane: 0,
+            graph: Vec::new(),
+            files: Vec::new(),
+            stats: HashMap::new(),
+        }
+    }
+
+    pub fn is_work_tree(&self) -> bool {
+        self.hash == WORK_TREE
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum GraphLineType {
-    None,
-    Vertical,
-    Horizontal,
-    Corner,
-    Merge,
+/// A changed file in the working tree, with `git status --short` letters
+/// for its staged and unstaged changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: String,
+    pub staged: Option<char>,
+    pub unstaged: Option<char>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct FilterOptions {
     pub author: Option<String>,
     pub path: Option<String>,
     pub since: Option<DateTime<Utc>>,
     pub until: Option<DateTime<Utc>>,
     pub range: Option<String>,
+    /// Refs the walk starts from besides `range`; HEAD when both are empty.
+    pub refs: RefSelection,
     pub max_commits: Option<usize>,
 }
 
-#[derive(Clone)]
-pub struct Repository {
-    repo: Arc<Git2Repository>,
-    path: String,
-}
-
-impl Repository {
-    pub fn new(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let repo = Git2Repository::open(path)?;
-        Ok(Self {
-            repo: Arc::new(repo),
-            path: path.to_string(),
-        })
-    }
-
-    pub fn get_commits(&self, filter: &FilterOptions) -> Result<Vec<Commit>, Box<dyn std::error::Error>> {
-        // Build git log command
-        let mut args = vec![
-            "log".to_string(),
-            "--graph".to_string(),
-            "--decorate=full".to_string(),
-            "--date-order".to_string(),
-            "--pretty=format:%H|%h|%an|%ae|%ad|%s|%P".to_string(),
-            "--date=iso".to_string(),
-        ];
-
-        // Add filters
-        if let Some(author) = &filter.author {
-            args.extend(vec!["--author".to_string(), author.clone()]);
+impl FilterOptions {
+    /// Short human-readable list of the active filters, empty when none are set.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(author) = &self.author {
+            parts.push(format!("author: {}", author));
         }
-        if let Some(path) = &filter.path {
-            args.extend(vec!["--".to_string(), path.clone()]);
+        if let Some(path) = &self.path {
+            parts.push(format!("path: {}", path));
         }
-        if let Some(since) = &filter.since {
-            args.extend(vec!["--since".to_string(), since.format("%Y-%m-%d").to_string()]);
+        if let Some(since) = &self.since {
+            parts.push(format!("since: {}", since.format("%Y-%m-%d")));
         }
-        if let Some(until) = &filter.until {
-            args.extend(vec!["--until".to_string(), until.format("%Y-%m-%d").to_string()]);
+        if let Some(until) = &self.until {
+            parts.push(format!("until: {}", until.format("%Y-%m-%d")));
         }
-        if let Some(range) = &filter.range {
-            args.push(range.clone());
+        if let Some(range) = &self.range {
+            parts.push(format!("range: {}", range));
         }
-        if let Some(max_commits) = &filter.max_commits {
-            args.extend(vec!["-n".to_string(), max_commits.to_string()]);
+        if !self.refs.is_empty() {
+            parts.push(format!("refs: {}", self.refs.describe()));
         }
+        parts.join(", ")
+    }
+}
 
-        // Execute git log
-        let output = Command::new("git")
-            .args(&args)
-            .current_dir(&self.path)
-            .output()?;
+/// A file both sides of a cherry-pick, revert or merge changed, with which
+/// sides still have it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub path: String,
+    pub ancestor: bool,
+    pub ours: bool,
+    pub theirs: bool,
+}
 
-        if !output.status.success() {
-            return Err(format!("git log failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+impl Conflict {
+    /// How the sides disagree, in `git status` words.
+    pub fn describe(&self) -> &'static str {
+        match (self.ours, self.theirs) {
+            (true, true) if self.ancestor => "both modified",
+            (true, true) => "both added",
+            (false, true) => "deleted by us",
+            (true, false) => "deleted by them",
+            (false, false) => "both deleted",
         }
+    }
+}
 
-        let output_str = String::from_utf8(output.stdout)?;
-        let commits = self.parse_git_log(&output_str)?;
+/// How a cherry-pick, revert, merge or rebase went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Applied {
+    /// The hash of the new commit, or of HEAD after a fast-forward or
+    /// rebase.
+    Committed(String),
+    Conflicts(Vec<Conflict>),
+}
 
-        // Generate graph
-        self.generate_graph(&mut commits.clone())?;
+/// How the selected commit is brought into the current branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeMode {
+    FastForward,
+    NoFastForward,
+    Squash,
+    Rebase,
+}
 
-        // Add refs
-        self.add_refs(&mut commits.clone())?;
+impl MergeMode {
+    pub const ALL: [MergeMode; 4] = [
+        MergeMode::FastForward,
+        MergeMode::NoFastForward,
+        MergeMode::Squash,
+        MergeMode::Rebase,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MergeMode::FastForward => "ff-only",
+            MergeMode::NoFastForward => "no-ff",
+            MergeMode::Squash => "squash",
+            MergeMode::Rebase => "rebase",
+        }
+    }
 
-        Ok(commits)
+    pub fn description(self) -> &'static str {
+        match self {
+            MergeMode::FastForward => "Move the branch forward; refuse if it has diverged",
+            MergeMode::NoFastForward => "Always create a merge commit",
+            MergeMode::Squash => "Stage the combined changes without committing",
+            MergeMode::Rebase => "Replay the branch's own commits on top of it",
+        }
     }
+}
 
-    fn parse_git_log(&self, output: &str) -> Result<Vec<Commit>, Box<dyn std::error::Error>> {
-        let mut commits = Vec::new();
+/// What merging a commit, or rebasing onto it, would do; worked out in
+/// memory without touching the repository.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergePreview {
+    /// Commits the merge brings in.
+    pub incoming: usize,
+    /// Commits of HEAD a rebase replays.
+    pub replayed: usize,
+    /// Files a merge would leave conflicted.
+    pub conflicts: Vec<Conflict>,
+    /// Where a rebase would stop, if anywhere.
+    pub rebase_stop: Option<RebaseStop>,
+}
 
-        for line in output.lines() {
-            let line = line.trim();
-            if line.is_empty() {
-                continue;
-            }
+/// The first commit a rebase can't replay cleanly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebaseStop {
+    /// Short hash and subject.
+    pub commit: String,
+    pub conflicts: Vec<Conflict>,
+}
 
-            let parts: Vec<&str> = line.split('|').collect();
-            if parts.len() < 7 {
-                continue;
-            }
+impl MergePreview {
+    pub fn fast_forward(&self) -> bool {
+        self.incoming > 0 && self.replayed == 0
+    }
+}
 
-            let graph_str = parts[0];
-            let hash = parts[1].to_string();
-            let short_hash = parts[2].to_string();
-            let author = parts[3].to_string();
-            let email = parts[4].to_string();
-            let date_str = parts[5];
-            let message = parts[6].to_string();
-            let parents_str = if parts.len() > 7 { parts[7] } else { "" };
-
-            // Parse date
-            let date = chrono::DateTime::parse_from_rfc3339(date_str)
-                .or_else(|_| chrono::DateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S %z"))
-                .map(|dt| dt.with_timezone(&Utc))
-                .unwrap_or_else(|_| Utc::now());
-
-            // Parse parents
-            let parents: Vec<String> = if parents_str.is_empty() {
-                Vec::new()
-            } else {
-                parents_str.split_whitespace().map(|s| s.to_string()).collect()
-            };
+/// What a reset does besides moving the branch, as in `git reset --<mode>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetMode {
+    /// Keep the index and working tree; the undone commits' changes stay staged.
+    Soft,
+    /// Reset the index; the changes stay in the working tree, unstaged.
+    Mixed,
+    /// Reset the index and working tree, discarding uncommitted changes.
+    Hard,
+    /// Like hard, but keeps uncommitted changes and refuses when a file they
+    /// touch differs between HEAD and the target.
+    Keep,
+}
 
-            // Parse graph characters
-            let graph = self.parse_graph_line(graph_str);
-
-            let commit = Commit {
-                hash,
-                short_hash,
-                message,
-                author,
-                email,
-                date,
-                parents,
-                refs: Vec::new(),
-                lane: 0,
-                graph,
-                files: Vec::new(),
-                stats: HashMap::new(),
-            };
+impl ResetMode {
+    pub const ALL: [ResetMode; 4] = [ResetMode::Soft, ResetMode::Mixed, ResetMode::Hard, ResetMode::Keep];
 
-            commits.push(commit);
+    pub fn name(self) -> &'static str {
+        match self {
+            ResetMode::Soft => "soft",
+            ResetMode::Mixed => "mixed",
+            ResetMode::Hard => "hard",
+            ResetMode::Keep => "keep",
+        }
+    }
+    /// One line on what the mode does, for the reset menu.
+    pub fn description(self) -> &'static str {
+        match self {
+            ResetMode::Soft => "keep the index and files; the undone changes stay staged",
+            ResetMode::Mixed => "reset the index, keep the files; changes become unstaged",
+            ResetMode::Hard => "reset the index and files, discarding uncommitted changes",
+            ResetMode::Keep => "reset the files that differ, keeping uncommitted changes",
         }
-
-        Ok(commits)
     }
+}
 
-    fn parse_graph_line(&self, graph_str: &str) -> Vec<GraphLine> {
-        let mut lines = Vec::new();
+/// What resetting HEAD to a commit would leave behind.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResetPreview {
+    /// Short hash and subject of the commits no ref would reach any more,
+    /// newest first.
+    pub lost: Vec<(String, String)>,
+    /// Tracked files with uncommitted changes.
+    pub changes: Vec<FileChange>,
+    /// Files that differ between HEAD and the target.
+    pub differing: Vec<String>,
+}
 
-        for (i, ch) in graph_str.chars().enumerate() {
-            let line_type = match ch {
-                ' ' => GraphLineType::None,
-                '|' | '*' => GraphLineType::Vertical,
-                '-' | '_' => GraphLineType::Horizontal,
-                '/' | '\\' => GraphLineType::Corner,
-                '+' => GraphLineType::Merge,
-                _ => GraphLineType::None,
-            };
+impl ResetPreview {
+    /// Changed files a keep reset would have to overwrite.
+    pub fn keep_blockers(&self) -> Vec<&str> {
+        self.changes
+            .iter()
+            .map(|change| change.path.as_str())
+            .filter(|path| self.differing.iter().any(|differing| differing == path))
+            .collect()
+    }
+}
 
-            let merge = ch == '+';
+/// What a hard reset saved before discarding anything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Backup {
+    /// The ref keeping the old HEAD reachable, when commits would be lost.
+    pub reference: Option<String>,
+    /// Whether uncommitted changes were stashed.
+    pub stashed: bool,
+}
 
-            lines.push(GraphLine {
-                line_type,
-                lane: i,
-                merge,
-            });
-        }
+/// Changed files in the working tree, by where the change is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatusCounts {
+    pub staged: usize,
+    pub unstaged: usize,
+    pub untracked: usize,
+}
 
-        lines
+impl StatusCounts {
+    pub fn is_clean(&self) -> bool {
+        *self == Self::default()
     }
+}
 
-    fn generate_graph(&self, commits: &mut [Commit]) -> Result<(), Box<dyn std::error::Error>> {
-        if commits.is_empty() {
-            return Ok(());
-        }
+#[derive(Clone)]
+pub struct Repository {
+    repo: Rc<Git2Repository>,
+    path: String,
+}
 
-        // Simple lane assignment based on graph characters
-        for commit in commits.iter_mut() {
-            if !commit.graph.is_empty() {
-                // Find the lane with a vertical line or merge
-                for (i, line) in commit.graph.iter().enumerate() {
-                    match line.line_type {
-                        GraphLineType::Vertical | GraphLineType::Merge => {
-                            commit.lane = i;
-                            break;
-                        }
-                        _ => {}
-                    }
-                }
-            }
-        }
+impl Repository {
+    pub fn new(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let repo = Git2Repository::open(path)?;
+        Ok(Self {
+            repo: Rc::new(repo),
+            path: path.to_string(),
+        })
+    }
 
-        Ok(())
+    /// Commits selected by `filter`, newest first, with their refs attached.
+    pub fn get_commits(&self, filter: &FilterOptions) -> Result<Vec<Commit>, Box<dyn std::error::Error>> {
+        let mut commits = revwalk::commits(&self.repo, filter)?;
+        self.add_refs(&mut commits)?;
+        Ok(commits)
     }
 
     fn add_refs(&self, commits: &mut [Commit]) -> Result<(), Box<dyn std::error::Error>> {
         let mut ref_map: HashMap<String, Vec<String>> = HashMap::new();
+        let mut symbolic = HashMap::new();
 
-        // Get all refs
-        for reference in self.repo.references()? {
-            let reference = reference?;
-            if let Some(target) = reference.target() {
-                let hash = target.to_string();
-                let name = reference.name().unwrap_or("").to_string();
-                ref_map.entry(hash).or_insert_with(Vec::new).push(name);
+        // Get all refs, HEAD included; annotated tags and symbolic refs are
+        // followed to the commit they end up at.
+        let head = self.repo.find_reference("HEAD").ok();
+        for reference in head.into_iter().chain(self.repo.references()?.filter_map(Result::ok)) {
+            let Some(name) = reference.name().map(str::to_string) else {
+                continue;
+            };
+            if let Some(target) = reference.symbolic_target() {
+                symbolic.insert(name.clone(), target.to_string());
+            }
+            if let Ok(commit) = reference.peel_to_commit() {
+                ref_map.entry(commit.id().to_string()).or_default().push(name);
             }
         }
 
+        let tracking = self.tracking()?;
+
         // Add refs to commits
         for commit in commits.iter_mut() {
             if let Some(refs) = ref_map.get(&commit.hash) {
                 commit.refs = refs.clone();
+                commit.decorations = refs::decorate(refs, &symbolic);
+                for decoration in &mut commit.decorations {
+                    if let Some(branch) = tracking.iter().find(|t| t.branch == decoration.name) {
+                        decoration.tracking = branch.counts();
+                    }
+                }
             }
         }
 
         Ok(())
     }
 
+    /// Full names of every branch, remote-tracking branch and tag that
+    /// points at a commit, in that order and sorted by name within each kind.
+    pub fn reference_names(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut names = Vec::new();
+        for reference in self.repo.references()? {
+            let reference = reference?;
+            if reference.peel_to_commit().is_err() {
+                continue;
+            }
+            if let Some(name) = reference.name() {
+                names.push(name.to_string());
+            }
+        }
+        let kind = |name: &str| {
+            ["refs/heads/", "refs/remotes/", "refs/tags/"]
+                .iter()
+                .position(|prefix| name.starts_with(prefix))
+                .unwrap_or(3)
+        };
+        names.sort_by(|a, b| kind(a).cmp(&kind(b)).then_with(|| a.cmp(b)));
+        Ok(names)
+    }
+
+    /// Branches, remotes, tags and stashes, grouped and sorted by `sort`.
+    pub fn refs(&self, sort: Sort) -> Result<Vec<Ref>, Box<dyn std::error::Error>> {
+        Ok(refs::load(&self.repo, sort)?)
+    }
+
+    /// Ahead/behind counts of every local branch against its upstream.
+    pub fn tracking(&self) -> Result<Vec<Tracking>, Box<dyn std::error::Error>> {
+        Ok(refs::tracking(&self.repo)?)
+    }
+
+    /// The commit's own parents. The graph's skip commits a filter hides.
+    pub fn parents(&self, hash: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let commit = self.repo.find_commit(Oid::from_str(hash)?)?;
+        Ok(commit.parent_ids().map(|id| id.to_string()).collect())
+    }
+
     pub fn get_commit_details(&self, hash: &str) -> Result<Commit, Box<dyn std::error::Error>> {
         let oid = Oid::from_str(hash)?;
         let commit = self.repo.find_commit(oid)?;
@@ -291,15 +431,16 @@ impl Repository {
         
         Ok(Commit {
             hash: commit.id().to_string(),
-            short_hash: commit.id().to_string()[..8].to_string(),
+            short_hash: commit.id().to_string()[..7].to_string(),
             message: commit.message().unwrap_or("").to_string(),
             author: author_name,
             email: author_email,
             date: DateTime::from_timestamp(author_when.seconds(), 0)
-                .unwrap_or_else(|| Utc::now())
+                .unwrap_or_else(Utc::now)
                 .with_timezone(&Utc),
             parents,
             refs: Vec::new(),
+            decorations: Vec::new(),
             lane: 0,
             graph: Vec::new(),
             files,
@@ -307,61 +448,871 @@ impl Repository {
         })
     }
 
-    pub fn checkout(&self, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let output = Command::new("git")
-            .args(&["checkout", hash])
-            .current_dir(&self.path)
-            .output()?;
+    /// Checks out a local branch by name, or a commit (any revision git
+    /// understands), detaching HEAD. Uncommitted changes are carried over,
+    /// refusing when the checkout would overwrite them.
+    pub fn checkout(&self, target: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let shown = match Oid::from_str(target) {
+            Ok(_) if target.len() == 40 => &target[..7],
+            _ => target,
+        };
+        self.record(format!("checkout {}", shown), || {
+            let branch = self.repo.find_branch(target, git2::BranchType::Local).ok();
+            let commit = match &branch {
+                Some(branch) => branch.get().peel_to_commit()?,
+                None => self
+                    .repo
+                    .revparse_single(target)
+                    .and_then(|object| object.peel_to_commit())
+                    .map_err(|_| format!("no branch or commit named {}", target))?,
+            };
+            self.move_files(commit.id())?;
+            match branch.as_ref().and_then(|branch| branch.get().name()) {
+                Some(name) => self.repo.set_head(name)?,
+                None => self.repo.set_head_detached(commit.id())?,
+            }
+            Ok(())
+        })
+    }
 
-        if !output.status.success() {
-            return Err(format!("git checkout failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+    /// The local branch [`track`](Self::track) would create for the
+    /// remote-tracking branch `upstream` (`origin/topic`), or `None` when a
+    /// local branch of that name already exists.
+    pub fn track_name(&self, upstream: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let reference = format!("refs/remotes/{}", upstream);
+        let remote = self.repo.branch_remote_name(&reference)?;
+        let name = upstream
+            .strip_prefix(remote.as_str().unwrap_or(""))
+            .and_then(|name| name.strip_prefix('/'))
+            .ok_or_else(|| format!("{} isn't a remote branch", upstream))?;
+        let exists = self.repo.find_branch(name, git2::BranchType::Local).is_ok();
+        Ok((!exists).then(|| name.to_string()))
+    }
+
+    /// Creates a local branch tracking `upstream` (`origin/topic`) and checks
+    /// it out, like `git checkout --track`; returns the new branch's name.
+    pub fn track(&self, upstream: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let name = self
+            .track_name(upstream)?
+            .ok_or_else(|| format!("a local branch for {} already exists", upstream))?;
+        self.record(format!("checkout --track {}", upstream), || {
+            let commit = self.repo.find_branch(upstream, git2::BranchType::Remote)?.get().peel_to_commit()?;
+            // The files first, so that a refusal leaves no branch behind.
+            self.move_files(commit.id())?;
+            let mut branch = self.repo.branch(&name, &commit, false)?;
+            branch.set_upstream(Some(upstream))?;
+            self.repo.set_head(branch.get().name().ok_or("the branch name isn't UTF-8")?)?;
+            Ok(())
+        })?;
+        Ok(name)
+    }
+
+    /// Files with uncommitted changes that checking out `hash` would
+    /// overwrite.
+    pub fn checkout_blockers(&self, hash: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let head = self.repo.head()?.peel_to_tree()?;
+        let target = self.repo.find_commit(Oid::from_str(hash)?)?.tree()?;
+        self.blockers(&head, &target)
+    }
+
+    /// Stashes the uncommitted changes, untracked files too when one is in
+    /// the way of checking out `hash`.
+    pub fn stash_for(&self, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let untracked = self
+            .checkout_blockers(hash)?
+            .iter()
+            .any(|path| self.repo.status_file(Path::new(path)).is_ok_and(|status| status.is_wt_new()));
+        let flags = untracked.then_some(git2::StashFlags::INCLUDE_UNTRACKED);
+        self.stash(&format!("gittree: before checkout of {}", &hash[..7.min(hash.len())]), flags)
+    }
+
+    fn stash(&self, message: &str, flags: Option<git2::StashFlags>) -> Result<(), Box<dyn std::error::Error>> {
+        // Stashing needs a mutable handle; it also cleans the tree.
+        let mut repo = Git2Repository::open(self.repo.path())?;
+        let signature = repo.signature()?;
+        repo.stash_save(&signature, message, flags)?;
+        Ok(())
+    }
+
+    /// What resetting HEAD to `hash` would lose or have to overwrite.
+    pub fn reset_preview(&self, hash: &str) -> Result<ResetPreview, Box<dyn std::error::Error>> {
+        let target = self.repo.find_commit(Oid::from_str(hash)?)?;
+        let head_ref = self.repo.head()?;
+        let head = head_ref.peel_to_commit()?;
+
+        // Commits only HEAD reaches: everything above the target that no
+        // other ref (branch, tag, remote, stash or backup) still points into.
+        let mut walk = self.repo.revwalk()?;
+        walk.set_sorting(git2::Sort::TOPOLOGICAL)?;
+        walk.push(head.id())?;
+        walk.hide(target.id())?;
+        for reference in self.repo.references()? {
+            let reference = reference?;
+            // A stash's commits sit on top of HEAD, so it would hide it all.
+            if reference.name() == head_ref.name() || reference.name() == Some("refs/stash") {
+                continue;
+            }
+            if let Ok(commit) = reference.peel_to_commit() {
+                walk.hide(commit.id())?;
+            }
+        }
+        let mut lost = Vec::new();
+        for oid in walk {
+            let commit = self.repo.find_commit(oid?)?;
+            lost.push((commit.id().to_string()[..7].to_string(), commit.summary().unwrap_or("").to_string()));
+        }
+
+        let changes = self
+            .changed_files()?
+            .into_iter()
+            .filter(|change| change.unstaged != Some('?'))
+            .collect();
+        let diff = self.repo.diff_tree_to_tree(Some(&head.tree()?), Some(&target.tree()?), None)?;
+        let differing = diff
+            .deltas()
+            .filter_map(|delta| delta.new_file().path().or(delta.old_file().path()))
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        Ok(ResetPreview { lost, changes, differing })
+    }
+
+    /// Resets the checked-out branch (or detached HEAD) to `hash`. Before a
+    /// hard reset, commits that would be lost are kept under
+    /// `refs/gittree/backup/` and uncommitted changes are stashed.
+    pub fn reset(&self, hash: &str, mode: ResetMode) -> Result<Backup, Box<dyn std::error::Error>> {
+        let description = format!("reset --{} to {}", mode.name(), &hash[..7]);
+        self.record(description, || self.reset_now(hash, mode))
+    }
+
+    fn reset_now(&self, hash: &str, mode: ResetMode) -> Result<Backup, Box<dyn std::error::Error>> {
+        let target = self.repo.find_object(Oid::from_str(hash)?, Some(git2::ObjectType::Commit))?;
+        let preview = self.reset_preview(hash)?;
+        let mut backup = Backup::default();
+        match mode {
+            ResetMode::Soft => self.repo.reset(&target, git2::ResetType::Soft, None)?,
+            ResetMode::Mixed => self.repo.reset(&target, git2::ResetType::Mixed, None)?,
+            ResetMode::Hard => {
+                if !preview.lost.is_empty() {
+                    let head = self.repo.head()?.peel_to_commit()?;
+                    let name = format!("refs/gittree/backup/{}", &head.id().to_string()[..7]);
+                    self.repo
+                        .reference(&name, head.id(), true, &format!("gittree: before reset --hard to {}", &hash[..7]))?;
+                    backup.reference = Some(name);
+                }
+                if !preview.changes.is_empty() {
+                    self.stash(&format!("gittree: before reset --hard to {}", &hash[..7]), None)?;
+                    backup.stashed = true;
+                }
+                self.repo.reset(&target, git2::ResetType::Hard, None)?;
+            }
+            ResetMode::Keep => {
+                self.move_files(target.id())?;
+                self.repo.reset(&target, git2::ResetType::Soft, None)?;
+            }
+        }
+        Ok(backup)
+    }
+
+    /// Applies `hash` on top of HEAD and commits it with its original
+    /// author and message. `mainline` is the parent (from 1) a merge commit
+    /// is diffed against; 0 for other commits. Conflicts leave the
+    /// cherry-pick in progress for [`continue_operation`](Self::continue_operation)
+    /// or [`abort_operation`](Self::abort_operation).
+    pub fn cherry_pick(&self, hash: &str, mainline: u32) -> Result<Applied, Box<dyn std::error::Error>> {
+        let commit = self.picked_commit(hash, mainline)?;
+        let mut options = git2::CherrypickOptions::new();
+        options.mainline(mainline);
+        self.repo.cherrypick(&commit, Some(&mut options))?;
+        self.finish_pick()
+    }
+
+    /// Commits the inverse of `hash` on top of HEAD, with git's
+    /// `Revert "..."` message. `mainline` and conflicts are handled as in
+    /// [`cherry_pick`](Self::cherry_pick).
+    pub fn revert(&self, hash: &str, mainline: u32) -> Result<Applied, Box<dyn std::error::Error>> {
+        let commit = self.picked_commit(hash, mainline)?;
+        let mut options = git2::RevertOptions::new();
+        options.mainline(mainline);
+        self.repo.revert(&commit, Some(&mut options))?;
+        self.finish_pick()
+    }
+
+    /// The commit to cherry-pick or revert, once the repository is ready.
+    fn picked_commit(&self, hash: &str, mainline: u32) -> Result<git2::Commit<'_>, Box<dyn std::error::Error>> {
+        if let Some(operation) = self.operation() {
+            return Err(format!("a {} is already in progress", operation).into());
+        }
+        if self.status_counts()?.staged > 0 {
+            return Err("commit or unstage the staged changes first".into());
+        }
+        let commit = self.repo.find_commit(Oid::from_str(hash)?)?;
+        let parents = commit.parent_count() as u32;
+        if parents > 1 && !(1..=parents).contains(&mainline) {
+            return Err(format!("{} is a merge; choose a parent from 1 to {}", &hash[..7], parents).into());
+        }
+        if parents <= 1 && mainline != 0 {
+            return Err(format!("{} is not a merge", &hash[..7]).into());
+        }
+        Ok(commit)
+    }
+
+    /// Commits what a cherry-pick or revert left in the index, unless it
+    /// conflicted.
+    fn finish_pick(&self) -> Result<Applied, Box<dyn std::error::Error>> {
+        let conflicts = self.conflicts()?;
+        if !conflicts.is_empty() {
+            return Ok(Applied::Conflicts(conflicts));
         }
+        self.continue_operation().map(Applied::Committed)
+    }
+
+    /// The index as it is on disk: the `git` commands run here change it
+    /// behind libgit2's cached copy.
+    fn index(&self) -> Result<git2::Index, git2::Error> {
+        let mut index = self.repo.index()?;
+        index.read(false)?;
+        Ok(index)
+    }
+
+    /// The conflicted files of the operation in progress.
+    pub fn conflicts(&self) -> Result<Vec<Conflict>, Box<dyn std::error::Error>> {
+        Ok(index_conflicts(&self.index()?)?)
+    }
 
+    /// Resolves the conflict in `path` by taking our side (HEAD) or theirs
+    /// (the commit being applied) as a whole; a side that deleted the file
+    /// deletes it.
+    pub fn take_side(&self, path: &str, ours: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let mut index = self.index()?;
+        let mut conflict = None;
+        for entry in index.conflicts()? {
+            let entry = entry?;
+            let entry_path = [&entry.our, &entry.their, &entry.ancestor]
+                .into_iter()
+                .flatten()
+                .next()
+                .map(|side| side.path.clone());
+            if entry_path.as_deref() == Some(path.as_bytes()) {
+                conflict = Some(entry);
+                break;
+            }
+        }
+        let conflict = conflict.ok_or_else(|| format!("{} is not conflicted", path))?;
+        let entry = if ours { conflict.our } else { conflict.their };
+        let file = self.workdir()?.join(path);
+        match entry {
+            Some(entry) => {
+                let blob = self.repo.find_blob(entry.id)?;
+                std::fs::write(&file, blob.content())?;
+                index.add_path(Path::new(path))?;
+            }
+            None => {
+                let _ = std::fs::remove_file(&file);
+                index.remove_path(Path::new(path))?;
+            }
+        }
+        index.write()?;
         Ok(())
     }
 
-    pub fn reset_hard(&self, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let output = Command::new("git")
-            .args(&["reset", "--hard", hash])
-            .current_dir(&self.path)
-            .output()?;
+    /// Commits the resolved cherry-pick or revert in progress, returning
+    /// the new commit's hash. A cherry-pick keeps the picked commit's
+    /// author.
+    pub fn continue_operation(&self) -> Result<String, Box<dyn std::error::Error>> {
+        self.refuse_git_rebase()?;
+        let (description, name) = match self.repo.state() {
+            git2::RepositoryState::CherryPick => ("cherry-pick", "CHERRY_PICK_HEAD"),
+            git2::RepositoryState::Revert => ("revert", "REVERT_HEAD"),
+            git2::RepositoryState::Merge => ("merge", "MERGE_HEAD"),
+            git2::RepositoryState::RebaseMerge => return self.continue_rebase(),
+            _ => return Err(self.unsupported_operation()),
+        };
+        let applied = self.state_commit(name)?.id().to_string();
+        self.record(format!("{} {}", description, &applied[..7]), || self.commit_operation())
+    }
 
-        if !output.status.success() {
-            return Err(format!("git reset --hard failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+    /// Commits the resolved step of the rebase in progress and applies the
+    /// rest; returns the new HEAD.
+    fn continue_rebase(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let mut rebase = self.open_rebase()?;
+        let conflicts = self.conflicts()?.len();
+        if conflicts > 0 {
+            return Err(format!("resolve the {} conflicted files first", conflicts).into());
+        }
+        self.record_rest(|| {
+            commit_rebase_step(&mut rebase, &self.repo.signature()?)?;
+            self.finish_rebase(&mut rebase)
+        })
+    }
+
+    fn finish_rebase(&self, rebase: &mut git2::Rebase) -> Result<String, Box<dyn std::error::Error>> {
+        match self.run_rebase(rebase)? {
+            Applied::Committed(hash) => Ok(hash),
+            Applied::Conflicts(conflicts) => {
+                let paths: Vec<&str> = conflicts.iter().map(|conflict| conflict.path.as_str()).collect();
+                Err(format!("the rebase stopped again with conflicts in {}", paths.join(", ")).into())
+            }
+        }
+    }
+
+    /// Drops the commit being applied: gives up a cherry-pick, revert or
+    /// merge, and moves a rebase on to its next commit.
+    pub fn skip_operation(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.refuse_git_rebase()?;
+        if self.repo.state() != git2::RepositoryState::RebaseMerge {
+            return self.abort_operation();
+        }
+        let mut rebase = self.open_rebase()?;
+        self.record_rest(|| {
+            // A rebase starts from a clean tree, so only its own changes go.
+            // A hard reset would end the rebase too.
+            let mut index = self.index()?;
+            index.read_tree(&self.repo.head()?.peel_to_tree()?)?;
+            index.write()?;
+            self.repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
+            self.finish_rebase(&mut rebase).map(|_| ())
+        })
+    }
+
+    fn commit_operation(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let picked = match self.repo.state() {
+            git2::RepositoryState::CherryPick => Some(self.state_commit("CHERRY_PICK_HEAD")?),
+            _ => None,
+        };
+        let merged = match self.repo.state() {
+            git2::RepositoryState::Merge => Some(self.state_commit("MERGE_HEAD")?),
+            _ => None,
+        };
+        let conflicts = self.conflicts()?.len();
+        if conflicts > 0 {
+            return Err(format!("resolve the {} conflicted files first", conflicts).into());
+        }
+
+        let mut index = self.index()?;
+        let tree = self.repo.find_tree(index.write_tree()?)?;
+        let head = self.repo.head()?.peel_to_commit()?;
+        if tree.id() == head.tree_id() && merged.is_none() {
+            self.repo.cleanup_state()?;
+            return Err("nothing to commit; the changes are already on HEAD".into());
+        }
+        let signature = self.repo.signature()?;
+        let author = match &picked {
+            Some(picked) => picked.author(),
+            None => signature.clone(),
+        };
+        // MERGE_MSG lists the conflicts in comment lines, which git strips.
+        let message = git2::message_prettify(self.repo.message()?, Some(b'#'))?;
+        let mut parents = vec![&head];
+        parents.extend(merged.as_ref());
+        let id = self
+            .repo
+            .commit(Some("HEAD"), &author, &signature, &message, &tree, &parents)?;
+        self.repo.cleanup_state()?;
+        Ok(id.to_string())
+    }
+
+    /// Gives up the cherry-pick, revert or merge in progress: the files it
+    /// touched go back to HEAD, other local changes are kept. A rebase goes
+    /// back to where it started.
+    pub fn abort_operation(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.refuse_git_rebase()?;
+        match self.repo.state() {
+            git2::RepositoryState::CherryPick | git2::RepositoryState::Revert | git2::RepositoryState::Merge => {}
+            git2::RepositoryState::RebaseMerge => {
+                let mut rebase = self.open_rebase()?;
+                return self.record_rest(|| Ok(rebase.abort()?));
+            }
+            _ => return Err(self.unsupported_operation()),
+        }
+        let head = self.repo.head()?.peel_to_tree()?;
+        let mut index = self.index()?;
+        let mut paths: Vec<String> = self.conflicts()?.into_iter().map(|conflict| conflict.path).collect();
+        let diff = self.repo.diff_tree_to_index(Some(&head), Some(&index), None)?;
+        for delta in diff.deltas() {
+            if let Some(path) = delta.new_file().path().or(delta.old_file().path()) {
+                paths.push(path.to_string_lossy().to_string());
+            }
         }
 
+        index.read_tree(&head)?;
+        index.write()?;
+        let workdir = self.workdir()?;
+        for path in &paths {
+            if head.get_path(Path::new(path)).is_err() {
+                let _ = std::fs::remove_file(workdir.join(path));
+            }
+        }
+        if !paths.is_empty() {
+            let mut checkout = git2::build::CheckoutBuilder::new();
+            checkout.force();
+            for path in &paths {
+                checkout.path(path);
+            }
+            self.repo.checkout_head(Some(&mut checkout))?;
+        }
+        self.repo.cleanup_state()?;
         Ok(())
     }
 
-    pub fn cherry_pick(&self, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let output = Command::new("git")
-            .args(&["cherry-pick", hash])
-            .current_dir(&self.path)
-            .output()?;
+    /// Where the checked-out files are.
+    pub fn workdir(&self) -> Result<&Path, Box<dyn std::error::Error>> {
+        Ok(self.repo.workdir().ok_or("the repository has no working tree")?)
+    }
 
-        if !output.status.success() {
-            return Err(format!("git cherry-pick failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+    /// Checks out `target`'s files over HEAD's while keeping uncommitted
+    /// changes, as `git reset --keep` does; refuses when a changed file
+    /// differs between the two.
+    fn move_files(&self, target: Oid) -> Result<(), Box<dyn std::error::Error>> {
+        let head = self.repo.head()?.peel_to_commit()?;
+        if head.id() == target {
+            return Ok(());
         }
+        let target = self.repo.find_commit(target)?;
+        self.move_files_to(&head.tree()?, &target.tree()?)
+    }
 
+    /// Files with uncommitted changes, untracked ones included, that
+    /// differ between `current` and `target`.
+    fn blockers(&self, current: &git2::Tree, target: &git2::Tree) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let diff = self.repo.diff_tree_to_tree(Some(current), Some(target), None)?;
+        let differing: Vec<PathBuf> = diff
+            .deltas()
+            .filter_map(|delta| delta.new_file().path().or(delta.old_file().path()))
+            .map(Path::to_path_buf)
+            .collect();
+        Ok(self
+            .changed_files()?
+            .into_iter()
+            .map(|change| change.path)
+            .filter(|path| differing.iter().any(|differing| differing == Path::new(path)))
+            .collect())
+    }
+
+    /// Checks out `target` over `current`, refusing to overwrite local
+    /// changes to the files that differ.
+    fn move_files_to(&self, current: &git2::Tree, target: &git2::Tree) -> Result<(), Box<dyn std::error::Error>> {
+        let blockers = self.blockers(current, target)?;
+        if !blockers.is_empty() {
+            return Err(format!(
+                "local changes in {} would be overwritten; commit or stash them first",
+                blockers.join(", ")
+            )
+            .into());
+        }
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout.safe();
+        self.repo.checkout_tree(target.as_object(), Some(&mut checkout))?;
         Ok(())
     }
 
-    pub fn revert(&self, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
+    fn journal_path(&self) -> PathBuf {
+        self.repo.path().join("gittree").join("journal.json")
+    }
+
+    /// Where HEAD and the local branches and tags point now.
+    pub fn snapshot(&self) -> Result<Snapshot, Box<dyn std::error::Error>> {
+        let head = self.repo.find_reference("HEAD")?;
+        let (head, detached) = match head.symbolic_target() {
+            Some(branch) => (branch.to_string(), false),
+            None => (head.target().ok_or("HEAD points nowhere")?.to_string(), true),
+        };
+        let mut refs = std::collections::BTreeMap::new();
+        for reference in self.repo.references_glob("refs/heads/*")?.chain(self.repo.references_glob("refs/tags/*")?) {
+            let reference = reference?;
+            if let (Some(name), Some(target)) = (reference.name(), reference.target()) {
+                refs.insert(name.to_string(), target.to_string());
+            }
+        }
+        Ok(Snapshot { head, detached, refs })
+    }
+
+    /// Runs a mutating operation and, when it moved HEAD or a branch or
+    /// tag, adds it to the journal.
+    fn record<T>(
+        &self,
+        description: String,
+        operation: impl FnOnce() -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        let before = self.snapshot().ok();
+        let result = operation();
+        if let (Some(before), Ok(after)) = (before, self.snapshot()) {
+            if before != after {
+                let path = self.journal_path();
+                let mut journal = Journal::load(&path);
+                journal.push(Entry {
+                    description,
+                    before,
+                    after,
+                });
+                // The operation is done either way; a journal that can't be
+                // written only loses the undo.
+                let _ = journal.save(&path);
+            }
+        }
+        result
+    }
+
+    /// Runs the rest of an operation that stopped, such as a rebase at a
+    /// conflict, so that the journal entry it started ends where the
+    /// operation does; an operation that ends where it began is dropped.
+    fn record_rest<T>(
+        &self,
+        operation: impl FnOnce() -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        let before = self.snapshot().ok();
+        let result = operation();
+        if let (Some(before), Ok(after)) = (before, self.snapshot()) {
+            let path = self.journal_path();
+            let mut journal = Journal::load(&path);
+            if journal.undone == 0 && journal.entries.last().is_some_and(|entry| entry.after == before) {
+                if journal.entries.last().is_some_and(|entry| entry.before == after) {
+                    journal.entries.pop();
+                } else if let Some(entry) = journal.entries.last_mut() {
+                    entry.after = after;
+                }
+                let _ = journal.save(&path);
+            }
+        }
+        result
+    }
+
+    /// Undoes the newest journal entry. When something outside gittree
+    /// moved HEAD since, the last move in HEAD's reflog is undone instead.
+    /// Returns what was undone.
+    pub fn undo(&self) -> Result<String, Box<dyn std::error::Error>> {
+        if let Some(operation) = self.operation() {
+            return Err(format!("finish or abort the {} first", operation).into());
+        }
+        let current = self.snapshot()?;
+        let path = self.journal_path();
+        let mut journal = Journal::load(&path);
+        let Some(entry) = journal.to_undo(&current).cloned() else {
+            return self.undo_from_reflog(&mut journal, current);
+        };
+        self.restore(&entry.before, &format!("gittree: undo {}", entry.description))?;
+        journal.undo();
+        journal.save(&path)?;
+        Ok(format!("Undid {}", entry.description))
+    }
+
+    /// Redoes the newest undone journal entry.
+    pub fn redo(&self) -> Result<String, Box<dyn std::error::Error>> {
+        if let Some(operation) = self.operation() {
+            return Err(format!("finish or abort the {} first", operation).into());
+        }
+        let current = self.snapshot()?;
+        let path = self.journal_path();
+        let mut journal = Journal::load(&path);
+        let entry = journal.to_redo(&current).cloned().ok_or("nothing to redo")?;
+        self.restore(&entry.after, &format!("gittree: redo {}", entry.description))?;
+        journal.redo();
+        journal.save(&path)?;
+        Ok(format!("Redid {}", entry.description))
+    }
+
+    /// Moves HEAD back along its reflog by one step and journals that as an
+    /// undone entry, so it can be redone.
+    fn undo_from_reflog(&self, journal: &mut Journal, current: Snapshot) -> Result<String, Box<dyn std::error::Error>> {
+        let reflog = self.repo.reflog("HEAD")?;
+        let entry = reflog.get(0).ok_or("nothing to undo")?;
+        let message = entry.message().unwrap_or("").to_string();
+        let target = entry.id_old();
+        if target.is_zero() || message.starts_with("gittree:") {
+            return Err("nothing more to undo; see git reflog".into());
+        }
+
+        self.move_files(target)?;
+        let note = format!("gittree: undo {}", message);
+        // A checkout goes back to the branch it came from, other moves
+        // take the checked-out branch (or detached HEAD) back.
+        let from = message
+            .strip_prefix("checkout: moving from ")
+            .and_then(|rest| rest.split(" to ").next())
+            .map(|name| format!("refs/heads/{}", name))
+            .filter(|branch| self.repo.refname_to_id(branch).ok() == Some(target));
+        match from {
+            Some(branch) => {
+                self.repo.reference_symbolic("HEAD", &branch, true, &note)?;
+            }
+            None if current.detached || message.starts_with("checkout:") => {
+                self.repo.reference("HEAD", target, true, &note)?;
+            }
+            None => {
+                self.repo.reference(&current.head, target, true, &note)?;
+            }
+        }
+
+        journal.push(Entry {
+            description: message.clone(),
+            before: self.snapshot()?,
+            after: current,
+        });
+        journal.undo();
+        journal.save(&self.journal_path())?;
+        Ok(format!("Undid \"{}\" from the reflog", message))
+    }
+
+    /// Puts HEAD, the branches and the tags back where `snapshot` has them,
+    /// moving the files as a keep reset would.
+    fn restore(&self, snapshot: &Snapshot, message: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let target = snapshot.head_commit().ok_or("there is nothing before the first commit to go back to")?;
+        self.move_files(Oid::from_str(target)?)?;
+
+        let current = self.snapshot()?;
+        for (name, id) in &snapshot.refs {
+            if current.refs.get(name) != Some(id) {
+                self.repo.reference(name, Oid::from_str(id)?, true, message)?;
+            }
+        }
+        if snapshot.detached {
+            self.repo.reference("HEAD", Oid::from_str(&snapshot.head)?, true, message)?;
+        } else {
+            self.repo.reference_symbolic("HEAD", &snapshot.head, true, message)?;
+        }
+        for name in current.refs.keys().filter(|name| !snapshot.refs.contains_key(*name)) {
+            self.repo.find_reference(name)?.delete()?;
+        }
+        Ok(())
+    }
+
+    /// The commit a state file such as `CHERRY_PICK_HEAD` points at.
+    fn state_commit(&self, name: &str) -> Result<git2::Commit<'_>, Box<dyn std::error::Error>> {
+        let hash = std::fs::read_to_string(self.repo.path().join(name))?;
+        Ok(self.repo.find_commit(Oid::from_str(hash.trim())?)?)
+    }
+
+    /// Fails while a rebase started by `rebase` is stopped. git keeps its
+    /// todo in `.git/rebase-merge`, and libgit2 can't carry on from an
+    /// `edit` or `exec` step there.
+    fn refuse_git_rebase(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.repo.path().join("rebase-merge").join("git-rebase-todo").exists() {
+            return Err("the rebase is git's own; finish it in a shell with git rebase --continue or --abort".into());
+        }
+        Ok(())
+    }
+
+    fn unsupported_operation(&self) -> Box<dyn std::error::Error> {
+        match self.operation() {
+            Some(operation) => format!("finish the {} with git {} --continue or --abort", operation, operation).into(),
+            None => "no cherry-pick, revert, merge or rebase in progress".into(),
+        }
+    }
+
+    /// A rebase plan picking every commit from `base` up to HEAD.
+    pub fn rebase_plan(&self, base: &str) -> Result<Plan, Box<dyn std::error::Error>> {
+        Plan::new(&self.repo, base)
+    }
+
+    /// Runs `git rebase -i` with `plan` as its todo list. Squash messages are
+    /// taken as git combines them; an `edit` step or a conflict leaves the
+    /// rebase stopped for the user to continue in a shell, so the todo and reworded
+    /// messages stay under `.git/gittree-rebase` until it is done.
+    pub fn rebase(&self, plan: &Plan) -> Result<(), Box<dyn std::error::Error>> {
+        self.record(format!("rebase onto {}", &plan.base[..7]), || self.rebase_now(plan))
+    }
+
+    fn rebase_now(&self, plan: &Plan) -> Result<(), Box<dyn std::error::Error>> {
+        plan.validate()?;
+        let dir = self.repo.path().join("gittree-rebase");
+        let _ = std::fs::remove_dir_all(&dir);
+        let todo = plan.write(&dir)?;
         let output = Command::new("git")
-            .args(&["revert", hash])
+            .args(["rebase", "-i", &plan.base])
+            .env("GIT_SEQUENCE_EDITOR", format!("cp '{}'", todo.display().to_string().replace('\'', r"'\''")))
+            .env("GIT_EDITOR", "true")
             .current_dir(&self.path)
             .output()?;
+        if self.repo.state() == git2::RepositoryState::Clean {
+            let _ = std::fs::remove_dir_all(&dir);
+        }
 
         if !output.status.success() {
-            return Err(format!("git revert failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+            return Err(format!("git rebase failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+        }
+
+        Ok(())
+    }
+
+    /// What merging `hash` into HEAD, or rebasing HEAD onto it, would do.
+    pub fn merge_preview(&self, hash: &str) -> Result<MergePreview, Box<dyn std::error::Error>> {
+        let head = self.repo.head()?.peel_to_commit()?;
+        let target = self.repo.find_commit(Oid::from_str(hash)?)?;
+        let (incoming, replayed) = self.repo.graph_ahead_behind(target.id(), head.id())?;
+        let mut preview = MergePreview {
+            incoming,
+            replayed,
+            ..MergePreview::default()
+        };
+        if incoming == 0 {
+            return Ok(preview);
+        }
+        preview.conflicts = index_conflicts(&self.repo.merge_commits(&head, &target, None)?)?;
+        if replayed > 0 {
+            preview.rebase_stop = self.predict_rebase(&target)?;
+        }
+        Ok(preview)
+    }
+
+    /// Rebases HEAD onto `onto` in memory and returns the first commit that
+    /// would conflict, with its conflicts.
+    fn predict_rebase(&self, onto: &git2::Commit) -> Result<Option<RebaseStop>, Box<dyn std::error::Error>> {
+        let branch = self.repo.reference_to_annotated_commit(&self.repo.head()?)?;
+        let onto = self.repo.find_annotated_commit(onto.id())?;
+        let mut options = git2::RebaseOptions::new();
+        options.inmemory(true);
+        let mut rebase = self.repo.rebase(Some(&branch), Some(&onto), None, Some(&mut options))?;
+        // Nothing is written, so any identity will do.
+        let signature = self
+            .repo
+            .signature()
+            .or_else(|_| git2::Signature::now("gittree", "gittree@localhost"))?;
+        while let Some(operation) = rebase.next() {
+            let commit = self.repo.find_commit(operation?.id())?;
+            let conflicts = index_conflicts(&rebase.inmemory_index()?)?;
+            if !conflicts.is_empty() {
+                let id = commit.id().to_string();
+                return Ok(Some(RebaseStop {
+                    commit: format!("{} {}", &id[..7], commit.summary().unwrap_or("")),
+                    conflicts,
+                }));
+            }
+            commit_rebase_step(&mut rebase, &signature)?;
         }
+        Ok(None)
+    }
+
+    /// Merges `hash` into HEAD: only by moving HEAD forward when
+    /// `fast_forward_only`, otherwise always with a merge commit. Conflicts
+    /// leave the merge in progress for [`continue_operation`](Self::continue_operation)
+    /// or [`abort_operation`](Self::abort_operation).
+    pub fn merge(&self, hash: &str, fast_forward_only: bool) -> Result<Applied, Box<dyn std::error::Error>> {
+        let mode = if fast_forward_only { MergeMode::FastForward } else { MergeMode::NoFastForward };
+        self.record(format!("merge --{} {}", mode.name(), &hash[..7]), || {
+            let (target, annotated) = self.merged_commit(hash)?;
+            if fast_forward_only {
+                let (analysis, _) = self.repo.merge_analysis(&[&annotated])?;
+                if !analysis.is_fast_forward() {
+                    return Err(format!("can't fast-forward: HEAD and {} have diverged", &hash[..7]).into());
+                }
+                self.move_files(target.id())?;
+                let message = format!("merge {}: Fast-forward", &hash[..7]);
+                self.repo.head()?.set_target(target.id(), &message)?;
+                return Ok(Applied::Committed(hash.to_string()));
+            }
+            let mut checkout = git2::build::CheckoutBuilder::new();
+            checkout.safe();
+            self.repo.merge(&[&annotated], None, Some(&mut checkout))?;
+            let conflicts = self.conflicts()?;
+            if !conflicts.is_empty() {
+                return Ok(Applied::Conflicts(conflicts));
+            }
+            self.commit_operation().map(Applied::Committed)
+        })
+    }
 
+    /// Stages the changes merging `hash` would bring, like
+    /// `git merge --squash`, for the user to commit. Refuses when they
+    /// would conflict.
+    pub fn squash(&self, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let (target, _) = self.merged_commit(hash)?;
+        let head = self.repo.head()?.peel_to_commit()?;
+        let mut index = self.repo.merge_commits(&head, &target, None)?;
+        let conflicts = index_conflicts(&index)?;
+        if !conflicts.is_empty() {
+            let paths: Vec<&str> = conflicts.iter().map(|conflict| conflict.path.as_str()).collect();
+            return Err(format!("squashing would conflict in {}; merge with no-ff to resolve them", paths.join(", ")).into());
+        }
+        let tree = self.repo.find_tree(index.write_tree_to(&self.repo)?)?;
+        self.move_files_to(&head.tree()?, &tree)?;
+        let mut index = self.index()?;
+        index.read_tree(&tree)?;
+        index.write()?;
         Ok(())
     }
 
+    /// The commit to merge, once the repository is ready, annotated with a
+    /// branch pointing at it so the merge message names the branch.
+    fn merged_commit(&self, hash: &str) -> Result<(git2::Commit<'_>, git2::AnnotatedCommit<'_>), Box<dyn std::error::Error>> {
+        if let Some(operation) = self.operation() {
+            return Err(format!("a {} is already in progress", operation).into());
+        }
+        if self.status_counts()?.staged > 0 {
+            return Err("commit or unstage the staged changes first".into());
+        }
+        let target = self.repo.find_commit(Oid::from_str(hash)?)?;
+        let head = self.repo.head()?.peel_to_commit()?;
+        if head.id() == target.id() || self.repo.graph_descendant_of(head.id(), target.id())? {
+            return Err("already up to date".into());
+        }
+        let branch = self
+            .repo
+            .branches(None)?
+            .filter_map(Result::ok)
+            .map(|(branch, _)| branch.into_reference())
+            .find(|reference| reference.target() == Some(target.id()) && !reference.is_tag());
+        let annotated = match branch {
+            Some(reference) => self.repo.reference_to_annotated_commit(&reference)?,
+            None => self.repo.find_annotated_commit(target.id())?,
+        };
+        Ok((target, annotated))
+    }
+
+    /// Replays the commits of the checked-out branch that `hash` lacks on
+    /// top of it. A conflict leaves the rebase stopped for
+    /// [`continue_operation`](Self::continue_operation),
+    /// [`skip_operation`](Self::skip_operation) or
+    /// [`abort_operation`](Self::abort_operation).
+    pub fn rebase_onto(&self, hash: &str) -> Result<Applied, Box<dyn std::error::Error>> {
+        self.record(format!("rebase onto {}", &hash[..7]), || {
+            if let Some(operation) = self.operation() {
+                return Err(format!("a {} is already in progress", operation).into());
+            }
+            let status = self.status_counts()?;
+            if status.staged + status.unstaged > 0 {
+                return Err("commit or stash your changes first".into());
+            }
+            let head = self.repo.head()?;
+            if !head.is_branch() {
+                return Err("check out the branch to rebase first".into());
+            }
+            let branch = self.repo.reference_to_annotated_commit(&head)?;
+            let onto = self.repo.find_annotated_commit(Oid::from_str(hash)?)?;
+            let mut rebase = self.repo.rebase(Some(&branch), Some(&onto), None, None)?;
+            self.run_rebase(&mut rebase)
+        })
+    }
+
+    /// Applies the remaining steps of `rebase`, stopping at a conflict.
+    fn run_rebase(&self, rebase: &mut git2::Rebase) -> Result<Applied, Box<dyn std::error::Error>> {
+        let signature = self.repo.signature()?;
+        while let Some(operation) = rebase.next() {
+            operation?;
+            let conflicts = self.conflicts()?;
+            if !conflicts.is_empty() {
+                return Ok(Applied::Conflicts(conflicts));
+            }
+            commit_rebase_step(rebase, &signature)?;
+        }
+        rebase.finish(Some(&signature))?;
+        Ok(Applied::Committed(self.head_hash()?))
+    }
+
+    /// The rebase libgit2 can carry on with; `git rebase -i` ones are left
+    /// to git.
+    fn open_rebase(&self) -> Result<git2::Rebase<'_>, Box<dyn std::error::Error>> {
+        if self.repo.state() != git2::RepositoryState::RebaseMerge {
+            return Err(self.unsupported_operation());
+        }
+        self.repo.open_rebase(None).map_err(|_| self.unsupported_operation())
+    }
+
     pub fn create_branch(&self, name: &str, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.record(format!("branch {}", name), || self.create_branch_now(name, hash))
+    }
+
+    fn create_branch_now(&self, name: &str, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
         let output = Command::new("git")
-            .args(&["branch", name, hash])
+            .args(["branch", name, hash])
             .current_dir(&self.path)
             .output()?;
 
@@ -372,25 +1323,323 @@ impl Repository {
         Ok(())
     }
 
-    pub fn create_tag(&self, name: &str, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let output = Command::new("git")
-            .args(&["tag", name, hash])
-            .current_dir(&self.path)
-            .output()?;
+    /// Deletes the local branch `name`. Unless `force`, refuses when it has
+    /// commits that neither HEAD nor its upstream contain.
+    pub fn delete_branch(&self, name: &str, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+        self.record(format!("delete branch {}", name), || {
+            let mut branch = self.repo.find_branch(name, git2::BranchType::Local)?;
+            if branch.is_head() {
+                return Err(format!("{} is checked out", name).into());
+            }
+            let unmerged = self.unmerged(&branch)?;
+            if unmerged > 0 && !force {
+                return Err(format!(
+                    "{} has {} commit{} not merged into HEAD or its upstream",
+                    name,
+                    unmerged,
+                    if unmerged == 1 { "" } else { "s" }
+                )
+                .into());
+            }
+            branch.delete()?;
+            Ok(())
+        })
+    }
 
-        if !output.status.success() {
-            return Err(format!("git tag failed: {}", String::from_utf8_lossy(&output.stderr)).into());
+    /// Commits on `branch` that neither HEAD nor its upstream reach.
+    fn unmerged(&self, branch: &git2::Branch) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut walk = self.repo.revwalk()?;
+        walk.push(branch.get().peel_to_commit()?.id())?;
+        if let Ok(head) = self.repo.head().and_then(|head| head.peel_to_commit()) {
+            walk.hide(head.id())?;
+        }
+        if let Ok(upstream) = branch.upstream() {
+            walk.hide(upstream.get().peel_to_commit()?.id())?;
         }
+        Ok(walk.count())
+    }
 
+    /// Renames the local branch `old` to `new`, keeping HEAD on it.
+    pub fn rename_branch(&self, old: &str, new: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.record(format!("rename branch {} to {}", old, new), || {
+            self.repo.find_branch(old, git2::BranchType::Local)?.rename(new, false)?;
+            Ok(())
+        })
+    }
+
+    /// Points the local branch `name` at `hash`, like `git branch -f`.
+    pub fn move_branch(&self, name: &str, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.record(format!("move branch {} to {}", name, &hash[..7.min(hash.len())]), || {
+            if self.repo.find_branch(name, git2::BranchType::Local)?.is_head() {
+                return Err(format!("{} is checked out; reset it instead", name).into());
+            }
+            let commit = self.repo.find_commit(Oid::from_str(hash)?)?;
+            self.repo.branch(name, &commit, true)?;
+            Ok(())
+        })
+    }
+
+    /// Sets the branch `name` tracks (`origin/main`, or a local branch), or
+    /// stops it tracking one when `upstream` is `None`.
+    pub fn set_upstream(&self, name: &str, upstream: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+        self.repo.find_branch(name, git2::BranchType::Local)?.set_upstream(upstream)?;
         Ok(())
     }
 
+    /// Tags `hash` as `name`: an annotated tag when there is a message,
+    /// otherwise a lightweight one.
+    pub fn create_tag(&self, name: &str, hash: &str, message: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+        self.record(format!("tag {}", name), || {
+            let object = self.repo.find_object(Oid::from_str(hash)?, None)?;
+            match message {
+                Some(message) => self.repo.tag(name, &object, &self.repo.signature()?, message, false)?,
+                None => self.repo.tag_lightweight(name, &object, false)?,
+            };
+            Ok(())
+        })
+    }
+
+    pub fn delete_tag(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.record(format!("delete tag {}", name), || Ok(self.repo.tag_delete(name)?))
+    }
+
+    /// Points the tag `name` at `hash`, like `git tag -f`. An annotated tag
+    /// keeps its message with a new tagger and date; its signature is
+    /// dropped as it no longer applies.
+    pub fn move_tag(&self, name: &str, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.record(format!("move tag {} to {}", name, &hash[..7.min(hash.len())]), || {
+            let reference = self.repo.find_reference(&format!("refs/tags/{}", name))?;
+            let object = self.repo.find_object(Oid::from_str(hash)?, None)?;
+            match reference.peel_to_tag() {
+                Ok(tag) => {
+                    let (message, _) = tags::split_signature(tag.message().unwrap_or(""));
+                    self.repo.tag(name, &object, &self.repo.signature()?, message, true)?;
+                }
+                Err(_) => {
+                    self.repo.tag_lightweight(name, &object, true)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Every tag with its tagger and message, newest first.
+    pub fn tags(&self) -> Result<Vec<Tag>, Box<dyn std::error::Error>> {
+        Ok(tags::load(&self.repo)?)
+    }
+
     pub fn get_current_branch(&self) -> Result<String, Box<dyn std::error::Error>> {
         let head = self.repo.head()?;
         let name = head.name().unwrap_or("HEAD");
         Ok(name.to_string())
     }
 
+    pub fn head_hash(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let commit = self.repo.head()?.peel_to_commit()?;
+        Ok(commit.id().to_string())
+    }
+
+    /// Staged, unstaged and untracked file counts; a file with both staged
+    /// and unstaged changes counts as both.
+    pub fn status_counts(&self) -> Result<StatusCounts, Box<dyn std::error::Error>> {
+        let mut options = git2::StatusOptions::new();
+        options.include_ignored(false);
+        options.include_untracked(true);
+
+        let staged = git2::Status::INDEX_NEW
+            | git2::Status::INDEX_MODIFIED
+            | git2::Status::INDEX_DELETED
+            | git2::Status::INDEX_RENAMED
+            | git2::Status::INDEX_TYPECHANGE;
+        let unstaged = git2::Status::WT_MODIFIED
+            | git2::Status::WT_DELETED
+            | git2::Status::WT_RENAMED
+            | git2::Status::WT_TYPECHANGE
+            | git2::Status::CONFLICTED;
+
+        let mut counts = StatusCounts::default();
+        for entry in self.repo.statuses(Some(&mut options))?.iter() {
+            let status = entry.status();
+            counts.staged += status.intersects(staged) as usize;
+            counts.unstaged += status.intersects(unstaged) as usize;
+            counts.untracked += status.contains(git2::Status::WT_NEW) as usize;
+        }
+        Ok(counts)
+    }
+
+    /// Files with staged, unstaged or untracked changes, sorted by path.
+    pub fn changed_files(&self) -> Result<Vec<FileChange>, Box<dyn std::error::Error>> {
+        use git2::Status;
+        let mut options = git2::StatusOptions::new();
+        options.include_untracked(true).recurse_untracked_dirs(true);
+
+        let letter = |status: Status, flags: [(Status, char); 5]| {
+            flags.iter().find(|(flag, _)| status.contains(*flag)).map(|(_, c)| *c)
+        };
+        let mut files = Vec::new();
+        for entry in self.repo.statuses(Some(&mut options))?.iter() {
+            let status = entry.status();
+            let Some(path) = entry.path() else {
+                continue;
+            };
+            let staged = letter(
+                status,
+                [
+                    (Status::INDEX_NEW, 'A'),
+                    (Status::INDEX_MODIFIED, 'M'),
+                    (Status::INDEX_DELETED, 'D'),
+                    (Status::INDEX_RENAMED, 'R'),
+                    (Status::INDEX_TYPECHANGE, 'T'),
+                ],
+            );
+            let unstaged = letter(
+                status,
+                [
+                    (Status::WT_NEW, '?'),
+                    (Status::WT_MODIFIED, 'M'),
+                    (Status::WT_DELETED, 'D'),
+                    (Status::WT_RENAMED, 'R'),
+                    (Status::WT_TYPECHANGE, 'T'),
+                ],
+            )
+            .or(status.contains(Status::CONFLICTED).then_some('U'));
+            if staged.is_some() || unstaged.is_some() {
+                files.push(FileChange {
+                    path: path.to_string(),
+                    staged,
+                    unstaged,
+                });
+            }
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    /// Patch of the staged (`staged`) or unstaged changes to `path`, one
+    /// line per entry with its `+`/`-`/` ` prefix.
+    pub fn diff_file(&self, path: &str, staged: bool) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut options = git2::DiffOptions::new();
+        options
+            .pathspec(path)
+            .disable_pathspec_match(true)
+            .include_untracked(true)
+            .show_untracked_content(true);
+        let diff = if staged {
+            let head = self.repo.head().ok().and_then(|head| head.peel_to_tree().ok());
+            self.repo.diff_tree_to_index(head.as_ref(), None, Some(&mut options))?
+        } else {
+            self.repo.diff_index_to_workdir(None, Some(&mut options))?
+        };
+
+        let mut lines = Vec::new();
+        diff.print(git2::DiffFormat::Patch, |_, _, line| {
+            let prefix = match line.origin() {
+                origin @ ('+' | '-' | ' ') => origin.to_string(),
+                _ => String::new(),
+            };
+            // File headers arrive as one entry spanning several lines.
+            let content = String::from_utf8_lossy(line.content());
+            lines.extend(content.lines().map(|text| format!("{}{}", prefix, text)));
+            true
+        })?;
+        Ok(lines)
+    }
+
+    /// Stages the working-tree version of `path`, deletion included.
+    pub fn stage(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut index = self.index()?;
+        let file = Path::new(path);
+        let workdir = self.repo.workdir().ok_or("bare repository")?;
+        if workdir.join(file).exists() {
+            index.add_path(file)?;
+        } else {
+            index.remove_path(file)?;
+        }
+        index.write()?;
+        Ok(())
+    }
+
+    /// Puts the HEAD version of `path` back in the index, keeping the
+    /// working tree as it is.
+    pub fn unstage(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        match self.repo.head().and_then(|head| head.peel_to_commit()) {
+            Ok(head) => self.repo.reset_default(Some(head.as_object()), [path])?,
+            Err(_) => {
+                let mut index = self.index()?;
+                index.remove_path(Path::new(path))?;
+                index.write()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Commits the index on top of HEAD, returning the new commit's hash.
+    pub fn commit(&self, message: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let subject = message.lines().next().unwrap_or("");
+        self.record(format!("commit \"{}\"", subject), || self.commit_now(message))
+    }
+
+    fn commit_now(&self, message: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let signature = self.repo.signature()?;
+        let tree = self.repo.find_tree(self.index()?.write_tree()?)?;
+        let parent = self.repo.head().and_then(|head| head.peel_to_commit()).ok();
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        let id = self
+            .repo
+            .commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)?;
+        Ok(id.to_string())
+    }
+
+    /// Takes back the commit at HEAD, keeping its changes staged, and drops
+    /// it from the journal when it was the last thing recorded there.
+    pub fn uncommit(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.record_rest(|| {
+            let parent = self.repo.head()?.peel_to_commit()?.parent(0)?;
+            self.repo.reset(parent.as_object(), git2::ResetType::Soft, None)?;
+            Ok(())
+        })
+    }
+
+    /// Folds the staged changes into HEAD, keeping its message, like
+    /// `git commit --amend --no-edit`.
+    pub fn amend(&self) -> Result<String, Box<dyn std::error::Error>> {
+        self.record("amend HEAD".to_string(), || {
+            let head = self.repo.head()?.peel_to_commit()?;
+            let tree = self.repo.find_tree(self.index()?.write_tree()?)?;
+            let id = head.amend(Some("HEAD"), None, Some(&self.repo.signature()?), None, None, Some(&tree))?;
+            Ok(id.to_string())
+        })
+    }
+
+    /// Gives HEAD a new message, leaving its changes and the staged ones
+    /// as they are.
+    pub fn reword_head(&self, message: &str) -> Result<String, Box<dyn std::error::Error>> {
+        self.record("reword HEAD".to_string(), || {
+            let head = self.repo.head()?.peel_to_commit()?;
+            let id = head.amend(Some("HEAD"), None, Some(&self.repo.signature()?), None, Some(message), None)?;
+            Ok(id.to_string())
+        })
+    }
+
+    /// Where a commit message is kept while it is edited.
+    pub fn message_path(&self) -> PathBuf {
+        self.repo.path().join("gittree").join("MESSAGE")
+    }
+
+    /// The operation in progress (`rebase`, `merge`, ...), if any.
+    pub fn operation(&self) -> Option<&'static str> {
+        use git2::RepositoryState::*;
+        match self.repo.state() {
+            Clean => None,
+            Merge => Some("merge"),
+            Revert | RevertSequence => Some("revert"),
+            CherryPick | CherryPickSequence => Some("cherry-pick"),
+            Bisect => Some("bisect"),
+            Rebase | RebaseInteractive | RebaseMerge => Some("rebase"),
+            ApplyMailbox | ApplyMailboxOrRebase => Some("am"),
+        }
+    }
+
     pub fn is_dirty(&self) -> Result<bool, Box<dyn std::error::Error>> {
         let mut status_options = git2::StatusOptions::new();
         status_options.include_ignored(false);
@@ -401,23 +1650,448 @@ impl Repository {
     }
 }
 
+
+/// Commits the current step of `rebase`, keeping the original author. A
+/// commit whose changes are already there is dropped, as git does.
+fn commit_rebase_step(rebase: &mut git2::Rebase, signature: &git2::Signature) -> Result<(), git2::Error> {
+    match rebase.commit(None, signature, None) {
+        Err(err) if err.code() != git2::ErrorCode::Applied => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// The conflicted files of `index`.
+fn index_conflicts(index: &git2::Index) -> Result<Vec<Conflict>, git2::Error> {
+    let mut conflicts = Vec::new();
+    for conflict in index.conflicts()? {
+        let conflict = conflict?;
+        let entry = conflict
+            .our
+            .as_ref()
+            .or(conflict.their.as_ref())
+            .or(conflict.ancestor.as_ref());
+        let Some(entry) = entry else {
+            continue;
+        };
+        conflicts.push(Conflict {
+            path: String::from_utf8_lossy(&entry.path).to_string(),
+            ancestor: conflict.ancestor.is_some(),
+            ours: conflict.our.is_some(),
+            theirs: conflict.their.is_some(),
+        });
+    }
+    Ok(conflicts)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// A repository in a fresh temporary directory with Alice as its user;
+    /// the directory goes away on drop, even when the test fails.
+    struct TempRepo {
+        dir: PathBuf,
+        git: Git2Repository,
+        repo: Repository,
+    }
+
+    impl TempRepo {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("gittree-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            let git = Git2Repository::init(&dir).unwrap();
+            let mut config = git.config().unwrap();
+            config.set_str("user.name", "Alice").unwrap();
+            config.set_str("user.email", "alice@example.com").unwrap();
+            let repo = Repository::new(dir.to_str().unwrap()).unwrap();
+            TempRepo { dir, git, repo }
+        }
+
+        /// Writes `content` to `path` and commits it; returns the hash.
+        fn commit(&self, path: &str, content: &str, message: &str) -> String {
+            std::fs::write(self.dir.join(path), content).unwrap();
+            self.repo.stage(path).unwrap();
+            self.repo.commit(message).unwrap()
+        }
+    }
+
+    impl Drop for TempRepo {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn test_status_counts() {
+        let fixture = TempRepo::new("status");
+        let TempRepo { dir, git, repo } = &fixture;
+        assert!(repo.status_counts().unwrap().is_clean());
+        assert_eq!(repo.operation(), None);
+
+        std::fs::write(dir.join("a"), "a").unwrap();
+        std::fs::write(dir.join("b"), "b").unwrap();
+        let mut index = git.index().unwrap();
+        index.add_path(Path::new("a")).unwrap();
+        index.write().unwrap();
+        std::fs::write(dir.join("a"), "changed").unwrap();
+
+        let counts = repo.status_counts().unwrap();
+        assert_eq!(
+            counts,
+            StatusCounts {
+                staged: 1,
+                unstaged: 1,
+                untracked: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_stage_unstage_and_commit() {
+        let fixture = TempRepo::new("stage");
+        let TempRepo { dir, repo, .. } = &fixture;
+
+        std::fs::write(dir.join("a"), "one\n").unwrap();
+        let change = |staged, unstaged| FileChange {
+            path: "a".to_string(),
+            staged,
+            unstaged,
+        };
+        assert_eq!(repo.changed_files().unwrap(), [change(None, Some('?'))]);
+        assert_eq!(repo.diff_file("a", false).unwrap().last().unwrap(), "+one");
+
+        repo.stage("a").unwrap();
+        assert_eq!(repo.changed_files().unwrap(), [change(Some('A'), None)]);
+        let first = repo.commit("first").unwrap();
+        assert_eq!(repo.head_hash().unwrap(), first);
+        assert!(repo.changed_files().unwrap().is_empty());
+
+        std::fs::write(dir.join("a"), "two\n").unwrap();
+        repo.stage("a").unwrap();
+        let diff = repo.diff_file("a", true).unwrap();
+        assert_eq!(diff[0], "diff --git a/a b/a");
+        assert_eq!(diff[diff.len() - 2..], ["-one", "+two"]);
+        repo.unstage("a").unwrap();
+        assert_eq!(repo.changed_files().unwrap(), [change(None, Some('M'))]);
+    }
+
     #[test]
-    fn test_parse_graph_line() {
-        let repo = Repository {
-            repo: unsafe { std::mem::zeroed() },
-            path: "".to_string(),
+    fn test_cherry_pick_conflicts() {
+        let fixture = TempRepo::new("pick");
+        let TempRepo { dir, repo, .. } = &fixture;
+        let base = fixture.commit("a", "base\n", "base");
+        let theirs = fixture.commit("a", "theirs\n", "theirs");
+        repo.reset(&base, ResetMode::Hard).unwrap();
+        let ours = fixture.commit("a", "ours\n", "ours");
+
+        let conflict = Conflict {
+            path: "a".to_string(),
+            ancestor: true,
+            ours: true,
+            theirs: true,
         };
+        assert_eq!(repo.cherry_pick(&theirs, 0).unwrap(), Applied::Conflicts(vec![conflict.clone()]));
+        assert_eq!(conflict.describe(), "both modified");
+        assert_eq!(repo.operation(), Some("cherry-pick"));
+        assert!(repo.continue_operation().is_err());
+        repo.abort_operation().unwrap();
+        assert_eq!(repo.operation(), None);
+        assert_eq!(std::fs::read_to_string(dir.join("a")).unwrap(), "ours\n");
+        assert!(repo.status_counts().unwrap().is_clean());
+
+        repo.cherry_pick(&theirs, 0).unwrap();
+        repo.take_side("a", false).unwrap();
+        let picked = repo.continue_operation().unwrap();
+        let details = repo.get_commit_details(&picked).unwrap();
+        assert_eq!((details.message.as_str(), details.parents.clone()), ("theirs\n", vec![ours]));
+        assert_eq!(std::fs::read_to_string(dir.join("a")).unwrap(), "theirs\n");
+
+        match repo.revert(&picked, 0).unwrap() {
+            Applied::Committed(hash) => assert_eq!(repo.head_hash().unwrap(), hash),
+            conflicts => panic!("{:?}", conflicts),
+        }
+        assert_eq!(std::fs::read_to_string(dir.join("a")).unwrap(), "ours\n");
+        assert!(repo.cherry_pick(&theirs, 1).is_err());
+    }
 
-        let graph = repo.parse_graph_line("| | *");
-        assert_eq!(graph.len(), 5);
-        assert!(matches!(graph[0].line_type, GraphLineType::Vertical));
-        assert!(matches!(graph[1].line_type, GraphLineType::None));
-        assert!(matches!(graph[2].line_type, GraphLineType::Vertical));
-        assert!(matches!(graph[3].line_type, GraphLineType::None));
-        assert!(matches!(graph[4].line_type, GraphLineType::Vertical));
+    #[test]
+    fn test_reset_modes() {
+        let fixture = TempRepo::new("reset");
+        let TempRepo { dir, git, repo } = &fixture;
+        fixture.commit("b", "b\n", "base");
+        let base = fixture.commit("a", "one\n", "one");
+        let two = fixture.commit("a", "two\n", "two");
+
+        std::fs::write(dir.join("b"), "local\n").unwrap();
+        let preview = repo.reset_preview(&base).unwrap();
+        assert_eq!(preview.lost, [(two[..7].to_string(), "two".to_string())]);
+        assert_eq!(preview.differing, ["a"]);
+        assert!(preview.keep_blockers().is_empty());
+
+        repo.reset(&base, ResetMode::Keep).unwrap();
+        assert_eq!(repo.head_hash().unwrap(), base);
+        assert_eq!(std::fs::read_to_string(dir.join("a")).unwrap(), "one\n");
+        assert_eq!(std::fs::read_to_string(dir.join("b")).unwrap(), "local\n");
+
+        repo.reset(&two, ResetMode::Soft).unwrap();
+        std::fs::write(dir.join("a"), "local\n").unwrap();
+        assert!(repo.reset(&base, ResetMode::Keep).is_err());
+
+        let backup = repo.reset(&base, ResetMode::Hard).unwrap();
+        let name = format!("refs/gittree/backup/{}", &two[..7]);
+        assert_eq!(backup, Backup { reference: Some(name.clone()), stashed: true });
+        assert_eq!(git.refname_to_id(&name).unwrap().to_string(), two);
+        assert!(repo.status_counts().unwrap().is_clean());
+        assert!(repo.reset_preview(&base).unwrap().lost.is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_undo_redo() {
+        let fixture = TempRepo::new("undo");
+        let TempRepo { dir, git, repo } = &fixture;
+        let one = fixture.commit("a", "one\n", "one");
+        let two = fixture.commit("a", "two\n", "two");
+
+        repo.reset(&one, ResetMode::Hard).unwrap();
+        repo.create_branch("topic", &one).unwrap();
+        assert_eq!(repo.undo().unwrap(), "Undid branch topic");
+        assert!(git.find_reference("refs/heads/topic").is_err());
+        assert_eq!(repo.undo().unwrap(), format!("Undid reset --hard to {}", &one[..7]));
+        assert_eq!(repo.head_hash().unwrap(), two);
+        assert_eq!(std::fs::read_to_string(dir.join("a")).unwrap(), "two\n");
+        assert_eq!(repo.redo().unwrap(), format!("Redid reset --hard to {}", &one[..7]));
+        assert_eq!(repo.head_hash().unwrap(), one);
+        assert!(repo.status_counts().unwrap().is_clean());
+
+        // Moved outside gittree: the reflog takes over.
+        git.reference("refs/heads/master", Oid::from_str(&two).unwrap(), true, "reset: moving to two")
+            .unwrap();
+        git.checkout_head(Some(git2::build::CheckoutBuilder::new().force())).unwrap();
+        assert!(repo.redo().is_err());
+        assert_eq!(repo.undo().unwrap(), "Undid \"reset: moving to two\" from the reflog");
+        assert_eq!(repo.head_hash().unwrap(), one);
+        assert_eq!(std::fs::read_to_string(dir.join("a")).unwrap(), "one\n");
+        // Back where the reset left it, so that can be undone too.
+        assert_eq!(repo.undo().unwrap(), format!("Undid reset --hard to {}", &one[..7]));
+        assert_eq!(repo.undo().unwrap(), "Undid commit \"two\"");
+        assert!(repo.undo().is_err());
+        for _ in 0..3 {
+            repo.redo().unwrap();
+        }
+        assert_eq!(repo.head_hash().unwrap(), two);
+        assert!(repo.redo().is_err());
+    }
+
+    #[test]
+    fn test_branch_management() {
+        let fixture = TempRepo::new("branches");
+        let TempRepo { git, repo, .. } = &fixture;
+        let one = fixture.commit("a", "one", "one");
+        let two = fixture.commit("a", "two", "two");
+        let main = repo.get_current_branch().unwrap().trim_start_matches("refs/heads/").to_string();
+
+        repo.create_branch("topic", &one).unwrap();
+        repo.move_branch("topic", &two).unwrap();
+        assert!(repo.move_branch(&main, &one).is_err());
+        repo.rename_branch("topic", "feature").unwrap();
+        repo.set_upstream("feature", Some(&main)).unwrap();
+        let feature = git.find_branch("feature", git2::BranchType::Local).unwrap();
+        assert_eq!(feature.upstream().unwrap().name().unwrap(), Some(main.as_str()));
+        repo.set_upstream("feature", None).unwrap();
+
+        repo.checkout("feature").unwrap();
+        assert_eq!(repo.get_current_branch().unwrap(), "refs/heads/feature");
+        assert!(repo.delete_branch("feature", false).is_err());
+        let three = fixture.commit("a", "three", "three");
+        repo.checkout(&main).unwrap();
+        let err = repo.delete_branch("feature", false).unwrap_err();
+        assert_eq!(err.to_string(), "feature has 1 commit not merged into HEAD or its upstream");
+        repo.delete_branch("feature", true).unwrap();
+        assert!(git.find_branch("feature", git2::BranchType::Local).is_err());
+        repo.undo().unwrap();
+        assert_eq!(git.find_branch("feature", git2::BranchType::Local).unwrap().get().target().unwrap().to_string(), three);
+    }
+
+    #[test]
+    fn test_checkout() {
+        let fixture = TempRepo::new("checkout");
+        let TempRepo { dir, git, repo } = &fixture;
+        let mut config = git.config().unwrap();
+        config.set_str("remote.origin.url", dir.to_str().unwrap()).unwrap();
+        config.set_str("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*").unwrap();
+        let one = fixture.commit("a", "one", "one");
+        let main = repo.get_current_branch().unwrap().trim_start_matches("refs/heads/").to_string();
+        repo.create_branch("topic", &one).unwrap();
+        let two = fixture.commit("b", "two", "two");
+
+        std::fs::write(dir.join("b"), "changed").unwrap();
+        assert_eq!(repo.checkout_blockers(&one).unwrap(), ["b"]);
+        let err = repo.checkout("topic").unwrap_err();
+        assert_eq!(err.to_string(), "local changes in b would be overwritten; commit or stash them first");
+        repo.stash_for(&one).unwrap();
+        repo.checkout("topic").unwrap();
+        assert_eq!(repo.get_current_branch().unwrap(), "refs/heads/topic");
+        assert!(!dir.join("b").exists());
+
+        std::fs::write(dir.join("b"), "untracked").unwrap();
+        assert_eq!(repo.checkout_blockers(&two).unwrap(), ["b"]);
+        repo.stash_for(&two).unwrap();
+        repo.checkout(&two).unwrap();
+        assert_eq!(repo.get_current_branch().unwrap(), "HEAD");
+        assert_eq!(std::fs::read_to_string(dir.join("b")).unwrap(), "two");
+
+        git.reference("refs/remotes/origin/feature", Oid::from_str(&one).unwrap(), false, "").unwrap();
+        assert_eq!(repo.track_name("origin/feature").unwrap().as_deref(), Some("feature"));
+        assert_eq!(repo.track_name(&format!("origin/{}", main)).unwrap(), None);
+        assert_eq!(repo.track("origin/feature").unwrap(), "feature");
+        let feature = git.find_branch("feature", git2::BranchType::Local).unwrap();
+        assert!(feature.is_head());
+        assert_eq!(feature.upstream().unwrap().name().unwrap(), Some("origin/feature"));
+        repo.undo().unwrap();
+        assert!(git.find_branch("feature", git2::BranchType::Local).is_err());
+        assert_eq!(repo.head_hash().unwrap(), two);
+    }
+
+    #[test]
+    fn test_amend_and_reword_head() {
+        let fixture = TempRepo::new("amend");
+        let TempRepo { dir, git, repo } = &fixture;
+        std::fs::write(dir.join("a"), "one").unwrap();
+        repo.stage("a").unwrap();
+        let one = repo.commit("one").unwrap();
+
+        std::fs::write(dir.join("b"), "two").unwrap();
+        repo.stage("b").unwrap();
+        let amended = repo.amend().unwrap();
+        let head = git.find_commit(Oid::from_str(&amended).unwrap()).unwrap();
+        assert_eq!((head.message(), head.parent_count()), (Some("one"), 0));
+        assert!(head.tree().unwrap().get_name("b").is_some());
+
+        std::fs::write(dir.join("c"), "staged").unwrap();
+        repo.stage("c").unwrap();
+        let reworded = repo.reword_head("first\n\nbody").unwrap();
+        let head = git.find_commit(Oid::from_str(&reworded).unwrap()).unwrap();
+        assert_eq!(head.message(), Some("first\n\nbody"));
+        assert!(head.tree().unwrap().get_name("c").is_none());
+        assert_eq!(repo.status_counts().unwrap().staged, 1);
+
+        repo.undo().unwrap();
+        repo.undo().unwrap();
+        assert_eq!(repo.head_hash().unwrap(), one);
+    }
+
+    #[test]
+    fn test_tags() {
+        let fixture = TempRepo::new("tags");
+        let TempRepo { git, repo, .. } = &fixture;
+        let one = fixture.commit("a", "one", "one");
+        let two = fixture.commit("a", "two", "two");
+
+        repo.create_tag("light", &one, None).unwrap();
+        repo.create_tag("v1.0", &one, Some("Release 1.0\n\nNotes")).unwrap();
+        let object = git.find_object(Oid::from_str(&two).unwrap(), None).unwrap();
+        let signed = "Signed\n-----BEGIN PGP SIGNATURE-----\nabc\n-----END PGP SIGNATURE-----\n";
+        git.tag("v2.0", &object, &git.signature().unwrap(), signed, false).unwrap();
+        assert!(repo.create_tag("v1.0", &two, None).is_err());
+
+        let tags = repo.tags().unwrap();
+        let find = |name: &str| tags.iter().find(|tag| tag.name == name).unwrap().clone();
+        assert_eq!(tags.len(), 3);
+        assert!(find("light").annotation.is_none());
+        let annotation = find("v1.0").annotation.unwrap();
+        assert_eq!((annotation.tagger.as_str(), annotation.message.as_str()), ("Alice", "Release 1.0\n\nNotes"));
+        assert_eq!(find("v2.0").annotation.unwrap().signature, Some(tags::SignatureKind::Pgp));
+
+        repo.move_tag("v2.0", &one).unwrap();
+        repo.move_tag("light", &two).unwrap();
+        repo.delete_tag("v1.0").unwrap();
+        let tags = repo.tags().unwrap();
+        assert_eq!(tags.len(), 2);
+        let moved = tags.iter().find(|tag| tag.name == "v2.0").unwrap();
+        assert_eq!(moved.target, one);
+        let annotation = moved.annotation.as_ref().unwrap();
+        assert_eq!((annotation.message.as_str(), annotation.signature), ("Signed", None));
+        assert_eq!(tags.iter().find(|tag| tag.name == "light").unwrap().target, two);
+    }
+
+    #[test]
+    fn test_merge_and_rebase_onto() {
+        let fixture = TempRepo::new("merge");
+        let TempRepo { dir, git, repo } = &fixture;
+        let base = fixture.commit("a", "base\n", "base\n");
+        let main = repo.get_current_branch().unwrap().trim_start_matches("refs/heads/").to_string();
+        repo.create_branch("topic", &base).unwrap();
+        repo.create_branch("clash", &base).unwrap();
+        let ours = fixture.commit("b", "main\n", "main\n");
+        repo.checkout("topic").unwrap();
+        let topic = fixture.commit("c", "topic\n", "topic\n");
+        repo.checkout("clash").unwrap();
+        let clash = fixture.commit("a", "clash\n", "clash\n");
+        repo.checkout(&main).unwrap();
+
+        let preview = repo.merge_preview(&topic).unwrap();
+        assert_eq!((preview.incoming, preview.replayed, preview.fast_forward()), (1, 1, false));
+        assert!(preview.conflicts.is_empty() && preview.rebase_stop.is_none());
+        assert!(repo.merge(&topic, true).is_err());
+        let Applied::Committed(merge) = repo.merge(&topic, false).unwrap() else {
+            panic!("the merge conflicted");
+        };
+        let merge = git.find_commit(Oid::from_str(&merge).unwrap()).unwrap();
+        assert_eq!(merge.parent_ids().map(|id| id.to_string()).collect::<Vec<_>>(), [ours.clone(), topic.clone()]);
+        assert_eq!(merge.summary(), Some("Merge branch 'topic'"));
+        assert!(repo.merge(&topic, false).is_err());
+
+        repo.undo().unwrap();
+        repo.squash(&topic).unwrap();
+        assert_eq!(repo.head_hash().unwrap(), ours);
+        assert_eq!(repo.status_counts().unwrap().staged, 1);
+        repo.commit("squashed").unwrap();
+
+        std::fs::write(dir.join("a"), "main\n").unwrap();
+        repo.stage("a").unwrap();
+        let mine = repo.commit("mine").unwrap();
+        let preview = repo.merge_preview(&clash).unwrap();
+        assert_eq!(preview.conflicts.len(), 1);
+        let stop = preview.rebase_stop.unwrap();
+        assert_eq!((stop.commit, stop.conflicts[0].path.as_str()), (format!("{} mine", &mine[..7]), "a"));
+        assert!(repo.squash(&clash).is_err());
+
+        let Applied::Conflicts(conflicts) = repo.rebase_onto(&clash).unwrap() else {
+            panic!("the rebase didn't stop");
+        };
+        assert_eq!(conflicts[0].path, "a");
+        assert_eq!(repo.operation(), Some("rebase"));
+        repo.take_side("a", false).unwrap();
+        let head = repo.continue_operation().unwrap();
+        assert_eq!(repo.operation(), None);
+        let head = git.find_commit(Oid::from_str(&head).unwrap()).unwrap();
+        assert_eq!(head.summary(), Some("mine"));
+        assert_eq!(std::fs::read_to_string(dir.join("a")).unwrap(), "main\n");
+        let first = head.parent(0).unwrap().parent(0).unwrap();
+        assert_eq!(first.parent_id(0).unwrap().to_string(), clash);
+        assert_eq!(repo.undo().unwrap(), format!("Undid rebase onto {}", &clash[..7]));
+        assert_eq!(repo.head_hash().unwrap(), mine);
+    }
+
+    #[test]
+    fn test_rebase_stops_at_edit() {
+        let fixture = TempRepo::new("edit");
+        let TempRepo { dir, repo, .. } = &fixture;
+        let base = fixture.commit("a", "base\n", "base\n");
+        fixture.commit("b", "edited\n", "edited\n");
+        let head = fixture.commit("c", "after\n", "after\n");
+
+        let mut plan = repo.rebase_plan(&base).unwrap();
+        plan.steps[0].command = crate::rebase::Command::Edit;
+        repo.rebase(&plan).unwrap();
+        assert_eq!(repo.operation(), Some("rebase"));
+        for result in [repo.continue_operation().map(|_| ()), repo.skip_operation(), repo.abort_operation()] {
+            let err = result.unwrap_err().to_string();
+            assert!(err.contains("in a shell with git rebase --continue"), "{}", err);
+        }
+
+        Command::new("git").args(["rebase", "--abort"]).current_dir(dir).output().unwrap();
+        assert_eq!(repo.head_hash().unwrap(), head);
+    }
+}
+
@@ -0,0 +1,238 @@
+//! Glyph sets used to draw the graph from the lane engine's cells.
+
+use crate::graph::{Cell, NodeKind, DOWN, LEFT, RIGHT, UP};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlyphSet {
+    Ascii,
+    Unicode,
+    Rounded,
+    Heavy,
+    Dots,
+}
+
+/// Characters for one glyph set. Corners and tees are named after the sides
+/// they connect.
+struct Glyphs {
+    vertical: char,
+    horizontal: char,
+    down_left: char,
+    down_right: char,
+    up_left: char,
+    up_right: char,
+    vertical_left: char,
+    vertical_right: char,
+    horizontal_down: char,
+    horizontal_up: char,
+    cross: char,
+    normal: char,
+    merge: char,
+    head: char,
+    stash: char,
+}
+
+const ASCII: Glyphs = Glyphs {
+    vertical: '|',
+    horizontal: '-',
+    down_left: '.',
+    down_right: '.',
+    up_left: '\'',
+    up_right: '\'',
+    vertical_left: '+',
+    vertical_right: '+',
+    horizontal_down: '+',
+    horizontal_up: '+',
+    cross: '+',
+    normal: '*',
+    merge: 'M',
+    head: '@',
+    stash: 's',
+};
+
+const UNICODE: Glyphs = Glyphs {
+    vertical: '│',
+    horizontal: '─',
+    down_left: '┐',
+    down_right: '┌',
+    up_left: '┘',
+    up_right: '└',
+    vertical_left: '┤',
+    vertical_right: '├',
+    horizontal_down: '┬',
+    horizontal_up: '┴',
+    cross: '┼',
+    normal: '●',
+    merge: '◆',
+    head: '◉',
+    stash: '◌',
+};
+
+const ROUNDED: Glyphs = Glyphs {
+    down_left: '╮',
+    down_right: '╭',
+    up_left: '╯',
+    up_right: '╰',
+    ..UNICODE
+};
+
+const HEAVY: Glyphs = Glyphs {
+    vertical: '┃',
+    horizontal: '━',
+    down_left: '┓',
+    down_right: '┏',
+    up_left: '┛',
+    up_right: '┗',
+    vertical_left: '┫',
+    vertical_right: '┣',
+    horizontal_down: '┳',
+    horizontal_up: '┻',
+    cross: '╋',
+    normal: '●',
+    merge: '◆',
+    head: '◉',
+    stash: '◌',
+};
+
+const DOTS: Glyphs = Glyphs {
+    vertical: '┊',
+    horizontal: '┈',
+    normal: '•',
+    merge: '◦',
+    head: '◉',
+    stash: '∘',
+    ..ROUNDED
+};
+
+impl GlyphSet {
+    pub const ALL: [GlyphSet; 5] = [
+        GlyphSet::Ascii,
+        GlyphSet::Unicode,
+        GlyphSet::Rounded,
+        GlyphSet::Heavy,
+        GlyphSet::Dots,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GlyphSet::Ascii => "ascii",
+            GlyphSet::Unicode => "unicode",
+            GlyphSet::Rounded => "rounded",
+            GlyphSet::Heavy => "heavy",
+            GlyphSet::Dots => "dots",
+        }
+    }
+
+    /// The set after this one, wrapping around.
+    pub fn next(&self) -> GlyphSet {
+        let index = Self::ALL.iter().position(|set| set == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    fn glyphs(&self) -> &'static Glyphs {
+        match self {
+            GlyphSet::Ascii => &ASCII,
+            GlyphSet::Unicode => &UNICODE,
+            GlyphSet::Rounded => &ROUNDED,
+            GlyphSet::Heavy => &HEAVY,
+            GlyphSet::Dots => &DOTS,
+        }
+    }
+
+    /// Character drawn for `cell`.
+    pub fn glyph(&self, cell: &Cell) -> char {
+        let g = self.glyphs();
+        if let Some(node) = cell.node {
+            return match node {
+                NodeKind::Normal => g.normal,
+                NodeKind::Merge => g.merge,
+                NodeKind::Head => g.head,
+                // Uncommitted changes look like a stash: work not on a branch.
+                NodeKind::Stash | NodeKind::WorkTree => g.stash,
+            };
+        }
+
+        let vertical = cell.edges & (UP | DOWN);
+        let horizontal = cell.edges & (LEFT | RIGHT);
+        match (vertical, horizontal) {
+            (0, 0) => ' ',
+            (_, 0) => g.vertical,
+            (0, _) => g.horizontal,
+            (v, h) if v == UP | DOWN && h == LEFT | RIGHT => g.cross,
+            (v, LEFT) if v == UP | DOWN => g.vertical_left,
+            (v, RIGHT) if v == UP | DOWN => g.vertical_right,
+            (DOWN, h) if h == LEFT | RIGHT => g.horizontal_down,
+            (UP, h) if h == LEFT | RIGHT => g.horizontal_up,
+            (DOWN, LEFT) => g.down_left,
+            (DOWN, _) => g.down_right,
+            (_, LEFT) => g.up_left,
+            _ => g.up_right,
+        }
+    }
+}
+
+impl fmt::Display for GlyphSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for GlyphSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|set| set.name() == s.to_lowercase())
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|set| set.name()).collect();
+                format!("unknown glyph set '{}' (expected one of: {})", s, names.join(", "))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(edges: u8) -> Cell {
+        Cell {
+            edges,
+            ..Cell::default()
+        }
+    }
+
+    #[test]
+    fn test_corners_follow_edge_direction() {
+        let set = GlyphSet::Rounded;
+        assert_eq!(set.glyph(&cell(DOWN | LEFT)), '╮');
+        assert_eq!(set.glyph(&cell(DOWN | RIGHT)), '╭');
+        assert_eq!(set.glyph(&cell(UP | LEFT)), '╯');
+        assert_eq!(set.glyph(&cell(UP | RIGHT)), '╰');
+        assert_eq!(set.glyph(&cell(UP | DOWN | LEFT | RIGHT)), '┼');
+        assert_eq!(set.glyph(&cell(UP | DOWN | RIGHT)), '├');
+        assert_eq!(GlyphSet::Heavy.glyph(&cell(UP | LEFT | RIGHT)), '┻');
+        assert_eq!(GlyphSet::Ascii.glyph(&cell(LEFT | RIGHT)), '-');
+    }
+
+    #[test]
+    fn test_node_kinds() {
+        let node = |kind| Cell {
+            edges: UP | DOWN,
+            node: Some(kind),
+            color: 0,
+        };
+        assert_eq!(GlyphSet::Ascii.glyph(&node(NodeKind::Normal)), '*');
+        assert_eq!(GlyphSet::Ascii.glyph(&node(NodeKind::Head)), '@');
+        assert_eq!(GlyphSet::Unicode.glyph(&node(NodeKind::Merge)), '◆');
+        assert_eq!(GlyphSet::Dots.glyph(&node(NodeKind::Stash)), '∘');
+    }
+
+    #[test]
+    fn test_parse_and_cycle() {
+        assert_eq!("Rounded".parse::<GlyphSet>(), Ok(GlyphSet::Rounded));
+        assert!("fancy".parse::<GlyphSet>().is_err());
+        assert_eq!(GlyphSet::Dots.next(), GlyphSet::Ascii);
+    }
+}
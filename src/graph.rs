@@ -0,0 +1,466 @@
+//! Lane engine: assigns every commit to a lane from the parent structure and
+//! computes the edges drawn on each row of the graph.
+//!
+//! A row is a list of cells. Lane `i` lives in column `2 * i`; the odd columns
+//! in between only ever carry horizontal edges. Each cell records which sides
+//! it connects to, so renderers can pick the right glyph for forks, merges
+//! and crossings.
+
+use crate::git::Commit;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+pub const UP: u8 = 0b0001;
+pub const DOWN: u8 = 0b0010;
+pub const LEFT: u8 = 0b0100;
+pub const RIGHT: u8 = 0b1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeKind {
+    Normal,
+    Merge,
+    Head,
+    Stash,
+    /// The uncommitted changes above HEAD.
+    WorkTree,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cell {
+    pub edges: u8,
+    pub node: Option<NodeKind>,
+    pub color: usize,
+}
+
+impl Cell {
+    pub fn is_empty(&self) -> bool {
+        self.edges == 0 && self.node.is_none()
+    }
+}
+
+/// A side branch that was merged back: every commit between the fork point
+/// and the merge commit that joined it.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub id: usize,
+    /// Row of the merge commit that brought the branch in.
+    pub merge_row: usize,
+    /// Row where the branch lane converged into its fork point, if seen.
+    pub fork_row: Option<usize>,
+    /// Rows whose node sits on this segment's lane.
+    pub rows: Vec<usize>,
+    /// Enclosing segment when the merge commit itself sits on a side branch.
+    pub parent: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Lane {
+    target: String,
+    color: usize,
+    segment: Option<usize>,
+}
+
+/// Lays out `commits` (children before parents), filling in `lane` and
+/// `graph` on each commit, and returns the merged branch segments found.
+pub fn layout(commits: &mut [Commit]) -> Vec<Segment> {
+    let mut lanes: Vec<Option<Lane>> = Vec::new();
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut next_color = 0;
+
+    for (row, commit) in commits.iter_mut().enumerate() {
+        let hash = commit.hash.clone();
+        let parents = commit.parents.clone();
+        let before = lanes.len();
+
+        let incoming: Vec<usize> = lanes
+            .iter()
+            .enumerate()
+            .filter(|(_, lane)| lane.as_ref().is_some_and(|l| l.target == hash))
+            .map(|(i, _)| i)
+            .collect();
+
+        // Lanes that simply pass through this row.
+        let passing: Vec<(usize, usize)> = lanes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !incoming.contains(i))
+            .filter_map(|(i, lane)| lane.as_ref().map(|l| (i, l.color)))
+            .collect();
+
+        // Stay on the shallowest branch so side lanes converge into the
+        // mainline rather than the other way round.
+        let node = incoming
+            .iter()
+            .copied()
+            .min_by_key(|&i| depth(&segments, lanes[i].as_ref().and_then(|l| l.segment)))
+            .unwrap_or_else(|| {
+                let color = next_color;
+                next_color += 1;
+                let lane = Lane {
+                    target: hash.clone(),
+                    color,
+                    segment: None,
+                };
+                allocate(&mut lanes, lane, &HashSet::new())
+            });
+
+        let mut cells = vec![Cell::default(); columns(lanes.len().max(before))];
+
+        for &(i, color) in &passing {
+            cells[2 * i].edges |= UP | DOWN;
+            cells[2 * i].color = color;
+        }
+
+        let node_lane = lanes[node].clone().expect("node lane is allocated");
+        if let Some(segment) = node_lane.segment {
+            segments[segment].rows.push(row);
+        }
+        cells[2 * node].node = Some(if commit.is_work_tree() {
+            NodeKind::WorkTree
+        } else if commit.refs.iter().any(|r| r == "refs/stash") {
+            NodeKind::Stash
+        } else if parents.len() > 1 {
+            NodeKind::Merge
+        } else {
+            NodeKind::Normal
+        });
+        cells[2 * node].color = node_lane.color;
+        if incoming.contains(&node) {
+            cells[2 * node].edges |= UP;
+        }
+
+        // Other children of this commit end here: their lanes fold into the
+        // node, closing any segment they carried.
+        let mut freed = HashSet::new();
+        for &i in incoming.iter().filter(|&&i| i != node) {
+            let lane = lanes[i].take().expect("incoming lane is allocated");
+            cells[2 * i].edges |= UP;
+            connect(&mut cells, i, node, lane.color);
+            if let Some(segment) = lane.segment {
+                segments[segment].fork_row = Some(row);
+            }
+            freed.insert(i);
+        }
+
+        match parents.first() {
+            Some(first) => {
+                cells[2 * node].edges |= DOWN;
+                lanes[node] = Some(Lane {
+                    target: first.clone(),
+                    ..node_lane.clone()
+                });
+            }
+            None => {
+                lanes[node] = None;
+                freed.insert(node);
+            }
+        }
+
+        for parent in parents.iter().skip(1) {
+            let existing = lanes
+                .iter()
+                .enumerate()
+                .position(|(i, lane)| i != node && lane.as_ref().is_some_and(|l| &l.target == parent));
+            let (target, color) = match existing {
+                Some(i) => (i, lanes[i].as_ref().map_or(0, |l| l.color)),
+                None => {
+                    let color = next_color;
+                    next_color += 1;
+                    let id = segments.len();
+                    segments.push(Segment {
+                        id,
+                        merge_row: row,
+                        fork_row: None,
+                        rows: Vec::new(),
+                        parent: node_lane.segment,
+                    });
+                    let lane = Lane {
+                        target: parent.clone(),
+                        color,
+                        segment: Some(id),
+                    };
+                    (allocate(&mut lanes, lane, &freed), color)
+                }
+            };
+            if cells.len() < columns(lanes.len()) {
+                cells.resize(columns(lanes.len()), Cell::default());
+            }
+            cells[2 * target].edges |= DOWN;
+            connect(&mut cells, target, node, color);
+        }
+
+        while matches!(lanes.last(), Some(None)) {
+            lanes.pop();
+        }
+        while cells.last().is_some_and(Cell::is_empty) {
+            cells.pop();
+        }
+
+        commit.lane = node;
+        commit.graph = cells;
+    }
+
+    segments
+}
+
+/// Number of columns needed to draw `lanes` lanes.
+pub fn columns(lanes: usize) -> usize {
+    (2 * lanes).saturating_sub(1)
+}
+
+fn depth(segments: &[Segment], mut segment: Option<usize>) -> usize {
+    let mut depth = 0;
+    while let Some(id) = segment {
+        depth += 1;
+        segment = segments[id].parent;
+    }
+    depth
+}
+
+/// Places `lane` in the first free slot not released on the current row.
+fn allocate(lanes: &mut Vec<Option<Lane>>, lane: Lane, reserved: &HashSet<usize>) -> usize {
+    match (0..lanes.len()).find(|i| lanes[*i].is_none() && !reserved.contains(i)) {
+        Some(i) => {
+            lanes[i] = Some(lane);
+            i
+        }
+        None => {
+            lanes.push(Some(lane));
+            lanes.len() - 1
+        }
+    }
+}
+
+/// Draws a horizontal edge between lane `from` and the node lane `to`.
+fn connect(cells: &mut [Cell], from: usize, to: usize, color: usize) {
+    let (left, right) = (2 * from.min(to), 2 * from.max(to));
+    for (col, cell) in cells.iter_mut().enumerate().take(right + 1).skip(left) {
+        let mut edges = 0;
+        if col > left {
+            edges |= LEFT;
+        }
+        if col < right {
+            edges |= RIGHT;
+        }
+        // Vertical lanes keep their own color where an edge crosses them.
+        if cell.node.is_none() && (cell.edges & (UP | DOWN) == 0 || col == 2 * from) {
+            cell.color = color;
+        }
+        cell.edges |= edges;
+    }
+}
+
+/// Marks the node of the commit at `head` as the HEAD node.
+pub fn mark_head(commits: &mut [Commit], head: &str) {
+    if let Some(commit) = commits.iter_mut().find(|c| c.hash == head) {
+        if let Some(cell) = commit.graph.get_mut(2 * commit.lane) {
+            cell.node = Some(NodeKind::Head);
+        }
+    }
+}
+
+/// Lanes that carry an edge or node on at least one of `rows`.
+pub fn active_lanes<'c>(rows: impl IntoIterator<Item = &'c [Cell]>) -> Vec<usize> {
+    let mut active: Vec<usize> = Vec::new();
+    for cells in rows {
+        for (col, cell) in cells.iter().enumerate().step_by(2) {
+            if !cell.is_empty() && !active.contains(&(col / 2)) {
+                active.push(col / 2);
+            }
+        }
+    }
+    active.sort_unstable();
+    active
+}
+
+/// Keeps only `lanes` of a row, each with the connector on its left.
+///
+/// Dropping an idle lane never cuts an edge: a horizontal edge marks every
+/// lane it passes over, so those lanes are never idle.
+pub fn compress(cells: &[Cell], lanes: &[usize]) -> Vec<Cell> {
+    let mut out = Vec::with_capacity(columns(lanes.len()));
+    for (n, &lane) in lanes.iter().enumerate() {
+        if n > 0 {
+            out.push(cells.get(2 * lane - 1).copied().unwrap_or_default());
+        }
+        out.push(cells.get(2 * lane).copied().unwrap_or_default());
+    }
+    while out.last().is_some_and(Cell::is_empty) {
+        out.pop();
+    }
+    out
+}
+
+/// Commits hidden when `segment` is folded: its own rows plus those of any
+/// branch merged into it.
+pub fn segment_rows(segments: &[Segment], segment: usize) -> Vec<usize> {
+    let mut rows: Vec<usize> = segments
+        .iter()
+        .filter(|s| is_within(segments, s.id, segment))
+        .flat_map(|s| s.rows.iter().copied())
+        .collect();
+    rows.sort_unstable();
+    rows
+}
+
+/// Whether segment `id` is `ancestor` or nested inside it.
+pub fn is_within(segments: &[Segment], mut id: usize, ancestor: usize) -> bool {
+    loop {
+        if id == ancestor {
+            return true;
+        }
+        match segments[id].parent {
+            Some(parent) => id = parent,
+            None => return false,
+        }
+    }
+}
+
+/// One-line summary of a folded segment, e.g.
+/// `14 commits from feature/auth (alice, bob)`.
+pub fn segment_summary(commits: &[Commit], segments: &[Segment], segment: usize) -> String {
+    let rows = segment_rows(segments, segment);
+    let mut authors: Vec<&str> = Vec::new();
+    for &row in &rows {
+        let author = commits[row].author.as_str();
+        if !authors.contains(&author) {
+            authors.push(author);
+        }
+    }
+    let authors = if authors.len() > 3 {
+        format!("{}, +{}", authors[..3].join(", "), authors.len() - 3)
+    } else {
+        authors.join(", ")
+    };
+
+    format!(
+        "{} commit{} from {} ({})",
+        rows.len(),
+        if rows.len() == 1 { "" } else { "s" },
+        branch_name(commits, &segments[segment]),
+        authors
+    )
+}
+
+/// Best guess at the name of a merged branch: the merge commit subject, then
+/// any ref on the branch tip, then the tip's short hash.
+fn branch_name(commits: &[Commit], segment: &Segment) -> String {
+    let patterns = [
+        r"^Merge (?:remote-tracking )?branch '([^']+)'",
+        r"^Merge pull request #\d+ from (\S+)",
+        r"^Merge branch (\S+)",
+    ];
+    let subject = &commits[segment.merge_row].message;
+    for pattern in patterns {
+        let re = Regex::new(pattern).expect("valid merge subject pattern");
+        if let Some(captures) = re.captures(subject) {
+            return captures[1].to_string();
+        }
+    }
+
+    match segment.rows.first() {
+        Some(&tip) => commits[tip]
+            .refs
+            .first()
+            .cloned()
+            .unwrap_or_else(|| commits[tip].short_hash.clone()),
+        None => "unknown branch".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::collections::HashMap;
+
+    fn commit(hash: &str, parents: &[&str], author: &str, message: &str) -> Commit {
+        Commit {
+            hash: hash.to_string(),
+            short_hash: hash.to_string(),
+            message: message.to_string(),
+            author: author.to_string(),
+            email: String::new(),
+            date: Utc::now(),
+            parents: parents.iter().map(|p| p.to_string()).collect(),
+            refs: Vec::new(),
+            decorations: Vec::new(),
+            lane: 0,
+            graph: Vec::new(),
+            files: Vec::new(),
+            stats: HashMap::new(),
+        }
+    }
+
+    /// m merges feature (f2, f1) into main (a), both forked from base.
+    fn merged_branch() -> Vec<Commit> {
+        vec![
+            commit("m", &["a", "f2"], "carol", "Merge branch 'feature/auth'"),
+            commit("a", &["base"], "carol", "main work"),
+            commit("f2", &["f1"], "bob", "second"),
+            commit("f1", &["base"], "alice", "first"),
+            commit("base", &[], "carol", "initial"),
+        ]
+    }
+
+    #[test]
+    fn test_linear_history_stays_in_one_lane() {
+        let mut commits = vec![
+            commit("c", &["b"], "a", "c"),
+            commit("b", &["a"], "a", "b"),
+            commit("a", &[], "a", "a"),
+        ];
+        let segments = layout(&mut commits);
+
+        assert!(segments.is_empty());
+        assert!(commits.iter().all(|c| c.lane == 0 && c.graph.len() == 1));
+        assert_eq!(commits[0].graph[0].edges, DOWN);
+        assert_eq!(commits[1].graph[0].edges, UP | DOWN);
+        assert_eq!(commits[2].graph[0].edges, UP);
+    }
+
+    #[test]
+    fn test_merge_opens_and_fork_closes_segment() {
+        let mut commits = merged_branch();
+        let segments = layout(&mut commits);
+
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].merge_row, 0);
+        assert_eq!(segments[0].rows, vec![2, 3]);
+        assert_eq!(segments[0].fork_row, Some(4));
+        assert_eq!(commits[0].graph[0].node, Some(NodeKind::Merge));
+        assert_eq!(commits[0].graph[2].edges, DOWN | LEFT);
+        assert_eq!(commits[2].lane, 1);
+        assert_eq!(commits[4].graph[2].edges, UP | LEFT);
+        assert_ne!(commits[0].graph[0].color, commits[2].graph[2].color);
+    }
+
+    #[test]
+    fn test_compress_drops_idle_lanes() {
+        let node = Cell {
+            node: Some(NodeKind::Normal),
+            ..Cell::default()
+        };
+        let vertical = Cell {
+            edges: UP | DOWN,
+            ..Cell::default()
+        };
+        let row = vec![node, Cell::default(), Cell::default(), Cell::default(), vertical];
+
+        let lanes = active_lanes([row.as_slice()]);
+        assert_eq!(lanes, vec![0, 2]);
+        assert_eq!(compress(&row, &lanes), vec![node, Cell::default(), vertical]);
+    }
+
+    #[test]
+    fn test_segment_summary_names_branch_and_authors() {
+        let mut commits = merged_branch();
+        let segments = layout(&mut commits);
+
+        assert_eq!(
+            segment_summary(&commits, &segments, 0),
+            "2 commits from feature/auth (bob, alice)"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! The operation journal: where HEAD, the branches and the tags pointed
+//! before and after each operation gittree ran, so it can be undone and
+//! redone.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Entries kept; older ones are dropped.
+const LIMIT: usize = 100;
+
+/// Where HEAD and the local branches and tags point.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The checked-out branch's full name, or the commit when detached.
+    pub head: String,
+    pub detached: bool,
+    /// Full branch and tag names with the object each points at.
+    pub refs: BTreeMap<String, String>,
+}
+
+impl Snapshot {
+    /// The commit HEAD resolves to.
+    pub fn head_commit(&self) -> Option<&str> {
+        if self.detached {
+            Some(&self.head)
+        } else {
+            self.refs.get(&self.head).map(String::as_str)
+        }
+    }
+}
+
+/// One operation and the snapshots around it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// What was done, e.g. `reset --hard to 1a2b3c4`.
+    pub description: String,
+    pub before: Snapshot,
+    pub after: Snapshot,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Journal {
+    /// Oldest first.
+    pub entries: Vec<Entry>,
+    /// How many of the newest entries are undone.
+    pub undone: usize,
+}
+
+impl Journal {
+    /// Reads the journal at `path`; a missing or unreadable one is empty.
+    pub fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Adds a new operation, dropping the undone ones: they can't be redone
+    /// on top of it.
+    pub fn push(&mut self, entry: Entry) {
+        self.entries.truncate(self.entries.len() - self.undone);
+        self.undone = 0;
+        self.entries.push(entry);
+        if self.entries.len() > LIMIT {
+            self.entries.drain(..self.entries.len() - LIMIT);
+        }
+    }
+
+    /// The newest entry that isn't undone, if the repository is still where
+    /// it left it.
+    pub fn to_undo(&self, current: &Snapshot) -> Option<&Entry> {
+        let index = self.entries.len().checked_sub(self.undone + 1)?;
+        self.entries
+            .get(index)
+            .filter(|entry| entry.after == *current)
+    }
+
+    /// The oldest undone entry, if the repository is still where undoing it
+    /// left it.
+    pub fn to_redo(&self, current: &Snapshot) -> Option<&Entry> {
+        if self.undone == 0 {
+            return None;
+        }
+        let index = self.entries.len() - self.undone;
+        self.entries
+            .get(index)
+            .filter(|entry| entry.before == *current)
+    }
+
+    pub fn undo(&mut self) {
+        self.undone = (self.undone + 1).min(self.entries.len());
+    }
+
+    pub fn redo(&mut self) {
+        self.undone = self.undone.saturating_sub(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(main: &str) -> Snapshot {
+        Snapshot {
+            head: "refs/heads/main".to_string(),
+            detached: false,
+            refs: BTreeMap::from([("refs/heads/main".to_string(), main.to_string())]),
+        }
+    }
+
+    fn entry(description: &str, before: &str, after: &str) -> Entry {
+        Entry {
+            description: description.to_string(),
+            before: snapshot(before),
+            after: snapshot(after),
+        }
+    }
+
+    #[test]
+    fn test_undo_and_redo() {
+        let mut journal = Journal::default();
+        journal.push(entry("one", "a", "b"));
+        journal.push(entry("two", "b", "c"));
+        assert_eq!(snapshot("c").head_commit(), Some("c"));
+
+        assert!(journal.to_undo(&snapshot("b")).is_none());
+        assert_eq!(journal.to_undo(&snapshot("c")).unwrap().description, "two");
+        journal.undo();
+        assert_eq!(journal.to_undo(&snapshot("b")).unwrap().description, "one");
+        assert_eq!(journal.to_redo(&snapshot("b")).unwrap().description, "two");
+        assert!(journal.to_redo(&snapshot("c")).is_none());
+        journal.redo();
+        assert!(journal.to_redo(&snapshot("c")).is_none());
+
+        journal.undo();
+        journal.push(entry("three", "b", "d"));
+        let descriptions: Vec<&str> = journal
+            .entries
+            .iter()
+            .map(|e| e.description.as_str())
+            .collect();
+        assert_eq!(descriptions, ["one", "three"]);
+        assert_eq!(journal.undone, 0);
+    }
+
+    #[test]
+    fn test_load_and_save() {
+        let path = std::env::temp_dir()
+            .join(format!("gittree-journal-{}", std::process::id()))
+            .join("journal.json");
+        assert_eq!(Journal::load(&path), Journal::default());
+
+        let mut journal = Journal::default();
+        for i in 0..LIMIT + 5 {
+            journal.push(entry(&i.to_string(), "a", "b"));
+        }
+        journal.save(&path).unwrap();
+        let loaded = Journal::load(&path);
+        assert_eq!(loaded.entries.len(), LIMIT);
+        assert_eq!(loaded.entries[0].description, "5");
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,660 @@
+//! Key bindings: actions, key chord parsing, presets and the active keymap.
+
+use crate::config::KeysConfig;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    Help,
+    MoveUp,
+    MoveDown,
+    Parent,
+    Child,
+    PageUp,
+    PageDown,
+    Top,
+    Bottom,
+    Open,
+    Fold,
+    FoldAll,
+    PanLeft,
+    PanRight,
+    CompressLanes,
+    CycleGlyphs,
+    Filter,
+    Refs,
+    Sidebar,
+    Mark,
+    Visual,
+    SelectUp,
+    SelectDown,
+    Checkout,
+    Reset,
+    CherryPick,
+    Revert,
+    Rebase,
+    Merge,
+    Reword,
+    Fixup,
+    Branch,
+    Tag,
+    Tags,
+    Undo,
+    Redo,
+}
+
+/// Every action with its config name, help text and default keys.
+const ACTIONS: &[(Action, &str, &str, &[&str])] = &[
+    (Action::MoveUp, "move_up", "Move selection up", &["up", "k"]),
+    (
+        Action::MoveDown,
+        "move_down",
+        "Move selection down",
+        &["down", "j"],
+    ),
+    (Action::Parent, "parent", "Jump to parent", &["left", "h"]),
+    (Action::Child, "child", "Jump to child", &["right", "l"]),
+    (Action::PageUp, "page_up", "Page up", &["pageup"]),
+    (Action::PageDown, "page_down", "Page down", &["pagedown"]),
+    (Action::Top, "top", "Go to top", &["g", "home"]),
+    (Action::Bottom, "bottom", "Go to bottom", &["G", "end"]),
+    (
+        Action::Open,
+        "open",
+        "Open commit / expand folded branch",
+        &["enter"],
+    ),
+    (Action::Fold, "fold", "Fold branch at selected", &["z"]),
+    (
+        Action::FoldAll,
+        "fold_all",
+        "Fold or unfold all branches",
+        &["Z"],
+    ),
+    (
+        Action::PanLeft,
+        "pan_left",
+        "Pan the lane area left",
+        &["<"],
+    ),
+    (
+        Action::PanRight,
+        "pan_right",
+        "Pan the lane area right",
+        &[">"],
+    ),
+    (
+        Action::CompressLanes,
+        "compress_lanes",
+        "Toggle lane compression",
+        &["W"],
+    ),
+    (
+        Action::CycleGlyphs,
+        "cycle_glyphs",
+        "Cycle glyph set",
+        &["u"],
+    ),
+    (Action::Filter, "filter", "Filter (author/msg/hash)", &["/"]),
+    (Action::Refs, "refs", "Pick the refs to show", &["R"]),
+    (
+        Action::Sidebar,
+        "sidebar",
+        "Show or focus the ref sidebar",
+        &["tab"],
+    ),
+    (
+        Action::Mark,
+        "mark",
+        "Mark or unmark selected for cherry-pick/revert",
+        &["space"],
+    ),
+    (
+        Action::Visual,
+        "visual",
+        "Start or end a range selection",
+        &["v"],
+    ),
+    (
+        Action::SelectUp,
+        "select_up",
+        "Extend the range selection up",
+        &["shift+up", "K"],
+    ),
+    (
+        Action::SelectDown,
+        "select_down",
+        "Extend the range selection down",
+        &["shift+down", "J"],
+    ),
+    (Action::Checkout, "checkout", "Checkout selected", &["c"]),
+    (
+        Action::Reset,
+        "reset",
+        "Reset to selected (soft/mixed/hard/keep)",
+        &["x"],
+    ),
+    (
+        Action::CherryPick,
+        "cherry_pick",
+        "Cherry-pick selected",
+        &["p"],
+    ),
+    (Action::Revert, "revert", "Revert selected", &["r"]),
+    (
+        Action::Rebase,
+        "rebase",
+        "Plan an interactive rebase onto selected",
+        &["i"],
+    ),
+    (
+        Action::Merge,
+        "merge",
+        "Merge selected, or rebase onto it",
+        &["m"],
+    ),
+    (
+        Action::Reword,
+        "reword",
+        "Reword selected in the editor",
+        &["w"],
+    ),
+    (
+        Action::Fixup,
+        "fixup",
+        "Fix up selected with the staged changes",
+        &["F"],
+    ),
+    (Action::Branch, "branch", "New branch at selected", &["b"]),
+    (Action::Tag, "tag", "New tag at selected", &["t"]),
+    (Action::Tags, "tags", "List tags", &["T"]),
+    (
+        Action::Undo,
+        "undo",
+        "Undo the last operation",
+        &["U", "ctrl+z"],
+    ),
+    (
+        Action::Redo,
+        "redo",
+        "Redo the last undone operation",
+        &["ctrl+r", "ctrl+y"],
+    ),
+    (Action::Help, "help", "Help", &["?"]),
+    (Action::Quit, "quit", "Quit", &["q", "esc"]),
+];
+
+/// Former action names still accepted in the config.
+const ALIASES: &[(&str, Action)] = &[("reset_hard", Action::Reset)];
+
+/// Bindings a preset changes relative to the defaults.
+const VIM: &[(Action, &[&str])] = &[
+    (Action::Top, &["gg", "home"]),
+    (Action::PageUp, &["pageup", "ctrl+b", "ctrl+u"]),
+    (Action::PageDown, &["pagedown", "ctrl+f", "ctrl+d"]),
+    (Action::Fold, &["za"]),
+    (Action::FoldAll, &["zR"]),
+];
+
+const EMACS: &[(Action, &[&str])] = &[
+    (Action::MoveUp, &["up", "ctrl+p"]),
+    (Action::MoveDown, &["down", "ctrl+n"]),
+    (Action::Parent, &["left", "ctrl+b"]),
+    (Action::Child, &["right", "ctrl+f"]),
+    (Action::PageUp, &["pageup", "alt+v"]),
+    (Action::PageDown, &["pagedown", "ctrl+v"]),
+    (Action::Top, &["home", "alt+<"]),
+    (Action::Bottom, &["end", "alt+>"]),
+    (Action::Filter, &["/", "ctrl+s"]),
+    (Action::Quit, &["q", "ctrl+x ctrl+c"]),
+];
+
+pub const PRESETS: &[&str] = &["default", "vim", "emacs"];
+
+impl Action {
+    /// Every action, in help order.
+    pub fn all() -> impl Iterator<Item = Action> {
+        ACTIONS.iter().map(|entry| entry.0)
+    }
+
+    pub fn name(&self) -> &'static str {
+        Self::entry(*self).1
+    }
+
+    pub fn description(&self) -> &'static str {
+        Self::entry(*self).2
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS
+            .iter()
+            .map(|entry| (entry.1, entry.0))
+            .chain(ALIASES.iter().copied())
+            .find(|entry| entry.0 == name)
+            .map(|entry| entry.1)
+    }
+
+    /// Former names of actions, with the action each now means.
+    pub fn aliases() -> impl Iterator<Item = (&'static str, Action)> {
+        ALIASES.iter().copied()
+    }
+
+    fn entry(
+        action: Action,
+    ) -> &'static (Action, &'static str, &'static str, &'static [&'static str]) {
+        ACTIONS
+            .iter()
+            .find(|entry| entry.0 == action)
+            .expect("every action has an entry")
+    }
+}
+
+/// A single key press with modifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyChord {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let mut modifiers =
+            modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        // Terminals disagree on reporting shift for characters and backtab,
+        // which carry it in the key itself.
+        if matches!(code, KeyCode::Char(_) | KeyCode::BackTab) {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        Self { code, modifiers }
+    }
+}
+
+impl From<KeyEvent> for KeyChord {
+    fn from(event: KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+}
+
+const NAMED_KEYS: &[(&str, KeyCode)] = &[
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("enter", KeyCode::Enter),
+    ("esc", KeyCode::Esc),
+    ("tab", KeyCode::Tab),
+    ("backtab", KeyCode::BackTab),
+    ("space", KeyCode::Char(' ')),
+    ("backspace", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("insert", KeyCode::Insert),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+];
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            f.write_str("ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            f.write_str("alt+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            f.write_str("shift+")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => f.write_str("space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            code => {
+                let name = NAMED_KEYS
+                    .iter()
+                    .find(|(_, named)| *named == code)
+                    .map_or("?", |(name, _)| name);
+                f.write_str(name)
+            }
+        }
+    }
+}
+
+/// Parses one chord such as `j`, `G`, `enter`, `ctrl+d`, `alt+<` or `C-x`.
+fn parse_chord(spec: &str) -> Result<KeyChord, String> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = spec;
+    loop {
+        let lower = rest.to_lowercase();
+        let (modifier, len) = if lower.starts_with("ctrl+") {
+            (KeyModifiers::CONTROL, 5)
+        } else if lower.starts_with("alt+") {
+            (KeyModifiers::ALT, 4)
+        } else if lower.starts_with("shift+") {
+            (KeyModifiers::SHIFT, 6)
+        } else if rest.len() > 2
+            && (rest.starts_with("C-") || rest.starts_with("M-") || rest.starts_with("S-"))
+        {
+            match &rest[..1] {
+                "C" => (KeyModifiers::CONTROL, 2),
+                "M" => (KeyModifiers::ALT, 2),
+                _ => (KeyModifiers::SHIFT, 2),
+            }
+        } else {
+            break;
+        };
+        modifiers |= modifier;
+        rest = &rest[len..];
+    }
+
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => {
+            let lower = rest.to_lowercase();
+            if let Some((_, code)) = NAMED_KEYS.iter().find(|(name, _)| *name == lower) {
+                *code
+            } else if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                KeyCode::F(n)
+            } else {
+                return Err(format!("unknown key '{}'", spec));
+            }
+        }
+    };
+
+    Ok(KeyChord::new(code, modifiers))
+}
+
+/// Parses a binding: one chord, chords separated by spaces (`ctrl+x ctrl+c`)
+/// or a run of plain characters typed in sequence (`gg`, `dd`).
+pub fn parse_binding(spec: &str) -> Result<Vec<KeyChord>, String> {
+    let spec = spec.trim();
+    if spec.is_empty() {
+        return Err("empty key binding".to_string());
+    }
+    if spec.contains(char::is_whitespace) {
+        return spec.split_whitespace().map(parse_chord).collect();
+    }
+    match parse_chord(spec) {
+        Ok(chord) => Ok(vec![chord]),
+        Err(err) if spec.chars().all(|c| c.is_ascii_graphic()) && !spec.contains('+') => {
+            // Not a key name, so a sequence of characters such as `gg`.
+            let chords: Vec<KeyChord> = spec
+                .chars()
+                .map(|c| KeyChord::new(KeyCode::Char(c), KeyModifiers::NONE))
+                .collect();
+            if chords.len() > 1 {
+                Ok(chords)
+            } else {
+                Err(err)
+            }
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Human-readable form of a binding: `gg`, `ctrl+x ctrl+c`.
+pub fn format_binding(chords: &[KeyChord]) -> String {
+    let plain = chords.iter().all(|chord| {
+        chord.modifiers.is_empty() && matches!(chord.code, KeyCode::Char(c) if c != ' ')
+    });
+    let parts: Vec<String> = chords.iter().map(|chord| chord.to_string()).collect();
+    if plain {
+        parts.concat()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// Outcome of feeding a key press into the keymap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    Action(Action),
+    /// The keys so far start a longer binding.
+    Pending,
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<(Vec<KeyChord>, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::from_config(&KeysConfig::default()).expect("default key bindings are valid")
+    }
+}
+
+impl Keymap {
+    /// Builds the keymap for `config`: the preset's bindings with the
+    /// configured actions replaced. Reports every invalid key, unknown action
+    /// and conflicting binding.
+    pub fn from_config(config: &KeysConfig) -> Result<Self, Vec<String>> {
+        let mut errors = Vec::new();
+        let preset: &[(Action, &[&str])] = match config.preset.as_deref().unwrap_or("default") {
+            "default" => &[],
+            "vim" => VIM,
+            "emacs" => EMACS,
+            other => {
+                errors.push(format!(
+                    "unknown key preset '{}' (expected one of: {})",
+                    other,
+                    PRESETS.join(", ")
+                ));
+                &[]
+            }
+        };
+
+        let mut specs: Vec<(Action, Vec<String>)> = ACTIONS
+            .iter()
+            .map(|(action, _, _, keys)| {
+                let keys = preset
+                    .iter()
+                    .find(|(a, _)| a == action)
+                    .map_or(*keys, |(_, keys)| *keys);
+                (*action, keys.iter().map(|k| k.to_string()).collect())
+            })
+            .collect();
+        for (name, keys) in &config.bindings {
+            match Action::from_name(name) {
+                Some(action) => {
+                    if let Some(entry) = specs.iter_mut().find(|(a, _)| *a == action) {
+                        entry.1 = keys.to_vec();
+                    }
+                }
+                None => errors.push(format!("unknown action '{}' in keys", name)),
+            }
+        }
+
+        let mut bindings = Vec::new();
+        for (action, keys) in specs {
+            for key in keys {
+                match parse_binding(&key) {
+                    Ok(chords) => bindings.push((chords, action)),
+                    Err(err) => errors.push(format!("{} for action '{}'", err, action.name())),
+                }
+            }
+        }
+
+        for (i, (a, action_a)) in bindings.iter().enumerate() {
+            for (b, action_b) in bindings.iter().skip(i + 1) {
+                if a == b {
+                    if action_a != action_b {
+                        errors.push(format!(
+                            "'{}' is bound to both '{}' and '{}'",
+                            format_binding(a),
+                            action_a.name(),
+                            action_b.name()
+                        ));
+                    }
+                } else if a.starts_with(b) || b.starts_with(a) {
+                    let (short, short_action, long, long_action) = if a.len() < b.len() {
+                        (a, action_a, b, action_b)
+                    } else {
+                        (b, action_b, a, action_a)
+                    };
+                    errors.push(format!(
+                        "'{}' ('{}') hides '{}' ('{}')",
+                        format_binding(short),
+                        short_action.name(),
+                        format_binding(long),
+                        long_action.name()
+                    ));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Self { bindings })
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Looks up the keys pressed so far.
+    pub fn lookup(&self, pressed: &[KeyChord]) -> Lookup {
+        let mut pending = false;
+        for (chords, action) in &self.bindings {
+            if chords.as_slice() == pressed {
+                return Lookup::Action(*action);
+            }
+            if chords.starts_with(pressed) {
+                pending = true;
+            }
+        }
+        if pending {
+            Lookup::Pending
+        } else {
+            Lookup::None
+        }
+    }
+
+    /// Keys bound to `action`, formatted for display.
+    pub fn keys_for(&self, action: Action) -> Vec<String> {
+        self.bindings
+            .iter()
+            .filter(|(_, a)| *a == action)
+            .map(|(chords, _)| format_binding(chords))
+            .collect()
+    }
+
+    /// Help lines for every bound action, in the order of the action table.
+    pub fn help(&self) -> Vec<(String, &'static str)> {
+        let mut seen = HashMap::new();
+        ACTIONS
+            .iter()
+            .filter_map(|(action, _, description, _)| {
+                let keys = self.keys_for(*action);
+                if keys.is_empty() || seen.insert(*action, ()).is_some() {
+                    return None;
+                }
+                Some((keys.join(" / "), *description))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::KeyList;
+
+    fn chord(c: char) -> KeyChord {
+        KeyChord::new(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_parse_binding() {
+        assert_eq!(parse_binding("j"), Ok(vec![chord('j')]));
+        assert_eq!(parse_binding("gg"), Ok(vec![chord('g'), chord('g')]));
+        assert_eq!(
+            parse_binding("ctrl+d"),
+            Ok(vec![KeyChord::new(
+                KeyCode::Char('d'),
+                KeyModifiers::CONTROL
+            )])
+        );
+        assert_eq!(
+            parse_binding("C-x C-c"),
+            Ok(vec![
+                KeyChord::new(KeyCode::Char('x'), KeyModifiers::CONTROL),
+                KeyChord::new(KeyCode::Char('c'), KeyModifiers::CONTROL),
+            ])
+        );
+        assert_eq!(
+            parse_binding("PageDown"),
+            Ok(vec![KeyChord::new(KeyCode::PageDown, KeyModifiers::NONE)])
+        );
+        assert!(parse_binding("ctrl+nope").is_err());
+        assert_eq!(format_binding(&parse_binding("alt+<").unwrap()), "alt+<");
+    }
+
+    #[test]
+    fn test_sequences_and_presets() {
+        let config = KeysConfig {
+            preset: Some("vim".to_string()),
+            ..KeysConfig::default()
+        };
+        let keymap = Keymap::from_config(&config).unwrap();
+        assert_eq!(keymap.lookup(&[chord('g')]), Lookup::Pending);
+        assert_eq!(
+            keymap.lookup(&[chord('g'), chord('g')]),
+            Lookup::Action(Action::Top)
+        );
+        assert_eq!(keymap.lookup(&[chord('G')]), Lookup::Action(Action::Bottom));
+        assert_eq!(keymap.lookup(&[chord('%')]), Lookup::None);
+
+        let config = KeysConfig {
+            preset: Some("emacs".to_string()),
+            ..KeysConfig::default()
+        };
+        let keymap = Keymap::from_config(&config).unwrap();
+        let ctrl = |c| KeyChord::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        assert_eq!(keymap.lookup(&[ctrl('x')]), Lookup::Pending);
+        assert_eq!(
+            keymap.lookup(&[ctrl('x'), ctrl('c')]),
+            Lookup::Action(Action::Quit)
+        );
+    }
+
+    #[test]
+    fn test_conflicts_are_reported() {
+        let mut config = KeysConfig::default();
+        config
+            .bindings
+            .insert("checkout".to_string(), KeyList::One("x".to_string()));
+        config
+            .bindings
+            .insert("quit".to_string(), KeyList::Many(vec!["cc".to_string()]));
+        config
+            .bindings
+            .insert("explode".to_string(), KeyList::One("e".to_string()));
+
+        let errors = Keymap::from_config(&config).unwrap_err();
+        assert!(errors
+            .iter()
+            .any(|e| e.contains("'x' is bound to both 'checkout' and 'reset'")));
+        assert!(errors
+            .iter()
+            .any(|e| e.contains("unknown action 'explode'")));
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn test_old_action_names() {
+        let mut config = KeysConfig::default();
+        config
+            .bindings
+            .insert("reset_hard".to_string(), KeyList::One("X".to_string()));
+        let keymap = Keymap::from_config(&config).unwrap();
+        assert_eq!(
+            keymap.lookup(&[chord('X')]),
+            Lookup::Action(Action::Reset)
+        );
+        assert_eq!(keymap.keys_for(Action::Reset), ["X"]);
+    }
+}
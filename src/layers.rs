@@ -0,0 +1,476 @@
+//! Layered configuration: built-in defaults, then the system, user and
+//! per-repository config files, `gittree.*` keys in `.git/config`,
+//! `GITTREE_*` environment variables and command-line flags, each layer
+//! overriding the ones before it.
+
+use crate::config::Config;
+use crate::schema::{self, Issue, Severity};
+use serde_yaml::{Mapping, Value as Yaml};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const SYSTEM_CONFIG: &str = "/etc/gittree/config.yml";
+/// Per-repository config file, at the top of the working tree.
+pub const REPO_CONFIG: &str = ".gittree.yml";
+const ENV_PREFIX: &str = "GITTREE_";
+/// Settings a repository's `.gittree.yml` can't change: whoever commits it
+/// could otherwise rebind keys or turn off the confirmations in front of
+/// destructive actions for everyone who clones the repository.
+const UNTRUSTED: &[&str] = &["keys", "confirmDangerous"];
+
+/// Where an effective setting came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    File(PathBuf),
+    GitConfig(PathBuf),
+    Env(String),
+    CommandLine(String),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => f.write_str("default"),
+            Origin::File(path) => write!(f, "{}", path.display()),
+            Origin::GitConfig(path) => write!(f, "{} (git config)", path.display()),
+            Origin::Env(name) => write!(f, "${}", name),
+            Origin::CommandLine(flag) => write!(f, "{}", flag),
+        }
+    }
+}
+
+/// The merged config value plus where each setting came from.
+#[derive(Debug, Clone)]
+pub struct Layers {
+    value: Yaml,
+    /// Origin of every leaf setting, by dotted path.
+    origins: BTreeMap<String, Origin>,
+    /// Config files that were read, lowest layer first.
+    files: Vec<PathBuf>,
+    /// Unknown keys and settings, by the layer they were found in.
+    issues: Vec<(Origin, Issue)>,
+    /// Layers that could not be read or parsed.
+    errors: Vec<String>,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Layers {
+    /// Just the built-in defaults.
+    pub fn new() -> Self {
+        let value = serde_yaml::to_value(Config::default()).expect("default config serializes");
+        let mut origins = BTreeMap::new();
+        record(&value, &mut Vec::new(), &Origin::Default, &mut origins);
+        Self {
+            value,
+            origins,
+            files: Vec::new(),
+            issues: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Every layer that applies when running in `dir`, short of command-line
+    /// flags.
+    pub fn discover(dir: &Path) -> Self {
+        let mut layers = Self::new();
+        layers.add_file(Path::new(SYSTEM_CONFIG));
+        if let Some(path) = user_config_path() {
+            layers.add_file(&path);
+        }
+        if let Ok(repo) = git2::Repository::discover(dir) {
+            if let Some(workdir) = repo.workdir() {
+                layers.add_repo_file(&workdir.join(REPO_CONFIG));
+            }
+            layers.add_git_config(&repo);
+        }
+        layers.add_env(std::env::vars());
+        layers
+    }
+
+    /// Merges the YAML file at `path`; a missing file is skipped.
+    pub fn add_file(&mut self, path: &Path) {
+        self.read_file(path, &[]);
+    }
+
+    /// Merges a repository's own `.gittree.yml`, leaving out the settings
+    /// it isn't trusted with.
+    pub fn add_repo_file(&mut self, path: &Path) {
+        self.read_file(path, UNTRUSTED);
+    }
+
+    fn read_file(&mut self, path: &Path, ignored: &[&str]) {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return,
+            Err(err) => {
+                self.errors.push(format!("{}: {}", path.display(), err));
+                return;
+            }
+        };
+        self.files.push(path.to_path_buf());
+
+        let origin = Origin::File(path.to_path_buf());
+        let issues = schema::check_keys(&text);
+        let (unknown, broken): (Vec<Issue>, Vec<Issue>) = issues
+            .into_iter()
+            .partition(|issue| issue.severity == Severity::Warning);
+        if !broken.is_empty() {
+            self.errors.extend(
+                broken
+                    .iter()
+                    .map(|issue| format!("{}: {}", path.display(), issue)),
+            );
+            return;
+        }
+        self.issues
+            .extend(unknown.into_iter().map(|issue| (origin.clone(), issue)));
+
+        match serde_yaml::from_str::<Yaml>(&text) {
+            Ok(Yaml::Null) => {}
+            Ok(mut value) => {
+                schema::normalize(&mut value);
+                if let Yaml::Mapping(mapping) = &mut value {
+                    for key in ignored {
+                        if mapping.remove(*key).is_some() {
+                            let issue = Issue {
+                                line: schema::line_of(&text, &[key.to_string()]),
+                                severity: Severity::Warning,
+                                message: format!(
+                                    "'{}' is ignored in a repository's {}; set it in your own config",
+                                    key, REPO_CONFIG
+                                ),
+                            };
+                            self.issues.push((origin.clone(), issue));
+                        }
+                    }
+                }
+                self.merge(value, &origin);
+            }
+            Err(err) => self.errors.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+
+    /// Merges `gittree.*` keys from the repository's own git config, e.g.
+    /// `git config gittree.graph.maxWidth 60`.
+    pub fn add_git_config(&mut self, repo: &git2::Repository) {
+        let path = repo.path().join("config");
+        let Ok(config) = repo
+            .config()
+            .and_then(|config| config.open_level(git2::ConfigLevel::Local))
+        else {
+            return;
+        };
+        let Ok(entries) = config.entries(Some("gittree\\..*")) else {
+            return;
+        };
+
+        let mut settings = Vec::new();
+        entries
+            .for_each(|entry| {
+                if let (Some(name), Some(value)) = (entry.name(), entry.value()) {
+                    settings.push((name.to_string(), value.to_string()));
+                }
+            })
+            .ok();
+        for (name, value) in settings {
+            let words: Vec<&str> = name.split('.').skip(1).collect();
+            self.add_setting(&words, &value, Origin::GitConfig(path.clone()), &name);
+        }
+    }
+
+    /// Merges `GITTREE_*` variables from `vars`, e.g. `GITTREE_STYLE=dark` or
+    /// `GITTREE_GRAPH_MAX_WIDTH=60`.
+    pub fn add_env(&mut self, vars: impl Iterator<Item = (String, String)>) {
+        let mut vars: Vec<(String, String)> = vars
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        vars.sort();
+        for (name, value) in vars {
+            let lower = name[ENV_PREFIX.len()..].to_lowercase();
+            let words: Vec<&str> = lower.split('_').filter(|word| !word.is_empty()).collect();
+            self.add_setting(&words, &value, Origin::Env(name.clone()), &name);
+        }
+    }
+
+    fn add_setting(&mut self, words: &[&str], raw: &str, origin: Origin, name: &str) {
+        match schema::setting(words, raw) {
+            Some((path, value)) => self.set(&path, value, origin),
+            None => self.issues.push((
+                origin,
+                Issue {
+                    line: None,
+                    severity: Severity::Warning,
+                    message: format!("unknown setting '{}'", name),
+                },
+            )),
+        }
+    }
+
+    /// Overrides the setting at `path` (canonical key names), as command-line
+    /// flags do.
+    pub fn set<S: AsRef<str>>(&mut self, path: &[S], value: impl Into<Yaml>, origin: Origin) {
+        let mut layer = value.into();
+        for key in path.iter().rev() {
+            let mut mapping = Mapping::new();
+            mapping.insert(Yaml::String(key.as_ref().to_string()), layer);
+            layer = Yaml::Mapping(mapping);
+        }
+        self.merge(layer, &origin);
+    }
+
+    fn merge(&mut self, layer: Yaml, origin: &Origin) {
+        merge_into(
+            &mut self.value,
+            layer,
+            &mut Vec::new(),
+            origin,
+            &mut self.origins,
+        );
+    }
+
+    /// The effective config, or the errors of layers that failed to parse.
+    pub fn config(&self) -> Result<Config, Box<dyn std::error::Error>> {
+        if !self.errors.is_empty() {
+            return Err(self.errors.join("\n").into());
+        }
+        Ok(serde_yaml::from_value(self.value.clone())?)
+    }
+
+    /// Config files that were read, lowest layer first.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Unknown keys and settings, with the layer they were found in.
+    pub fn issues(&self) -> &[(Origin, Issue)] {
+        &self.issues
+    }
+
+    /// Every effective setting as `(dotted path, value, origin)`.
+    pub fn origins(&self) -> Vec<(String, String, &Origin)> {
+        let mut leaves = Vec::new();
+        leaves_of(&self.value, &mut Vec::new(), &mut leaves);
+        leaves
+            .into_iter()
+            .map(|(path, value)| {
+                let origin = self.origins.get(&path).unwrap_or(&Origin::Default);
+                let value = serde_json::to_string(value).unwrap_or_default();
+                (path, value, origin)
+            })
+            .collect()
+    }
+}
+
+/// The user config file: `$XDG_CONFIG_HOME/gittree/config.yml`, falling back
+/// to `~/.config/gittree/config.yml`.
+pub fn user_config_path() -> Option<PathBuf> {
+    let xdg = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute());
+    let base = xdg.or_else(|| {
+        std::env::var_os("HOME")
+            .filter(|home| !home.is_empty())
+            .map(|home| PathBuf::from(home).join(".config"))
+    })?;
+    Some(base.join("gittree").join("config.yml"))
+}
+
+fn merge_into(
+    target: &mut Yaml,
+    layer: Yaml,
+    path: &mut Vec<String>,
+    origin: &Origin,
+    origins: &mut BTreeMap<String, Origin>,
+) {
+    match (target, layer) {
+        (Yaml::Mapping(target), Yaml::Mapping(layer)) if !layer.is_empty() => {
+            for (key, value) in layer {
+                path.push(key_name(&key));
+                match target.get_mut(&key) {
+                    Some(existing) => merge_into(existing, value, path, origin, origins),
+                    None => {
+                        record(&value, path, origin, origins);
+                        target.insert(key, value);
+                    }
+                }
+                path.pop();
+            }
+        }
+        (target, layer) => {
+            let prefix = path.join(".");
+            origins.retain(|key, _| !(key == &prefix || key.starts_with(&format!("{}.", prefix))));
+            record(&layer, path, origin, origins);
+            *target = layer;
+        }
+    }
+}
+
+fn record(
+    value: &Yaml,
+    path: &mut Vec<String>,
+    origin: &Origin,
+    origins: &mut BTreeMap<String, Origin>,
+) {
+    let mut leaves = Vec::new();
+    leaves_of(value, path, &mut leaves);
+    for (key, _) in leaves {
+        origins.insert(key, origin.clone());
+    }
+}
+
+/// Leaf settings under `value`; lists and empty maps count as leaves.
+fn leaves_of<'a>(value: &'a Yaml, path: &mut Vec<String>, out: &mut Vec<(String, &'a Yaml)>) {
+    match value {
+        Yaml::Mapping(mapping) if !mapping.is_empty() => {
+            for (key, child) in mapping {
+                path.push(key_name(key));
+                leaves_of(child, path, out);
+                path.pop();
+            }
+        }
+        _ => out.push((path.join("."), value)),
+    }
+}
+
+fn key_name(key: &Yaml) -> String {
+    match key {
+        Yaml::String(name) => name.clone(),
+        other => serde_yaml::to_string(other)
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn origin_of<'a>(layers: &'a Layers, key: &str) -> Option<&'a Origin> {
+        layers
+            .origins()
+            .into_iter()
+            .find(|(path, _, _)| path == key)
+            .map(|(_, _, origin)| origin)
+    }
+
+    #[test]
+    fn test_layers_override_in_order() {
+        let dir = env::temp_dir().join(format!("gittree-layers-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("user.yml");
+        let repo = dir.join("repo.yml");
+        fs::write(
+            &user,
+            "style: dark\ndate_format: \"%d\"\ngraph:\n  maxWidth: 20\n",
+        )
+        .unwrap();
+        fs::write(&repo, "style: light\ngraph:\n  compressLanes: false\n").unwrap();
+
+        let mut layers = Layers::new();
+        layers.add_file(&user);
+        layers.add_file(&repo);
+        layers.add_file(&dir.join("missing.yml"));
+        let vars = [("GITTREE_GRAPH_MAX_WIDTH", "60"), ("HOME", "/nowhere")];
+        layers.add_env(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        layers.set(
+            &["noColor"],
+            true,
+            Origin::CommandLine("--no-color".to_string()),
+        );
+        fs::remove_dir_all(&dir).ok();
+
+        let config = layers.config().unwrap();
+        assert_eq!(config.style, "light");
+        assert_eq!(config.date_format, "%d");
+        assert_eq!(config.graph.max_width, 60);
+        assert!(!config.graph.compress_lanes);
+        assert!(config.no_color);
+        assert_eq!(layers.files(), &[user.clone(), repo.clone()]);
+
+        assert_eq!(
+            origin_of(&layers, "style"),
+            Some(&Origin::File(repo.clone()))
+        );
+        assert_eq!(origin_of(&layers, "dateFormat"), Some(&Origin::File(user)));
+        assert_eq!(
+            origin_of(&layers, "graph.maxWidth"),
+            Some(&Origin::Env("GITTREE_GRAPH_MAX_WIDTH".to_string()))
+        );
+        assert_eq!(
+            origin_of(&layers, "graph.compressLanes"),
+            Some(&Origin::File(repo))
+        );
+        assert_eq!(origin_of(&layers, "paging"), Some(&Origin::Default));
+    }
+
+    #[test]
+    fn test_repo_file_cannot_change_keys_or_confirmations() {
+        let dir = env::temp_dir().join(format!("gittree-repo-layer-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let repo = dir.join(REPO_CONFIG);
+        fs::write(
+            &repo,
+            "style: light\nconfirm_dangerous: false\nkeys:\n  move_down: d\n",
+        )
+        .unwrap();
+
+        let mut layers = Layers::new();
+        layers.add_repo_file(&repo);
+        let config = layers.config().unwrap();
+        assert_eq!(config.style, "light");
+        assert!(config.confirm_dangerous);
+        assert!(config.keys.bindings.is_empty());
+        let issues: Vec<String> = layers
+            .issues()
+            .iter()
+            .map(|(_, issue)| issue.to_string())
+            .collect();
+        assert_eq!(issues.len(), 2);
+        assert!(issues[0].starts_with("line 3: 'keys' is ignored"));
+
+        // The same settings are fine in the user's own files.
+        let mut layers = Layers::new();
+        layers.add_file(&repo);
+        fs::remove_dir_all(&dir).ok();
+        assert!(!layers.config().unwrap().confirm_dangerous);
+    }
+
+    #[test]
+    fn test_env_names_map_to_settings() {
+        let mut layers = Layers::new();
+        let vars = [
+            ("GITTREE_KEYS_MOVE_DOWN", "n"),
+            ("GITTREE_COLORS_HEAD", "red"),
+            ("GITTREE_CONFIRM_DANGEROUS", "false"),
+            ("GITTREE_THEMES_SEA_GRAPH1", "#0077be"),
+            ("GITTREE_FROBNICATE", "yes"),
+        ];
+        layers.add_env(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+
+        let config = layers.config().unwrap();
+        assert_eq!(config.keys.bindings["move_down"].to_vec(), vec!["n"]);
+        assert_eq!(config.colors.head.as_deref(), Some("red"));
+        assert!(!config.confirm_dangerous);
+        assert_eq!(
+            config.themes["sea"].colors.graph1.as_deref(),
+            Some("#0077be")
+        );
+
+        let issues: Vec<String> = layers
+            .issues()
+            .iter()
+            .map(|(_, issue)| issue.to_string())
+            .collect();
+        assert_eq!(issues, vec!["unknown setting 'GITTREE_FROBNICATE'"]);
+    }
+}
@@ -1,5 +1,18 @@
 pub mod app;
+pub mod colors;
 pub mod config;
+pub mod editor;
 pub mod git;
+pub mod glyphs;
+pub mod graph;
+pub mod journal;
+pub mod keys;
+pub mod layers;
+pub mod rebase;
+pub mod refs;
+pub mod revwalk;
+pub mod schema;
 pub mod ui;
-pub mod simple_ui;
\ No newline at end of file
+pub mod simple_ui;
+pub mod tags;
+pub mod theme;
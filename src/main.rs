@@ -1,12 +1,18 @@
 use clap::{Arg, Command};
 use gittree::app::App;
-use gittree::config::Config;
 use gittree::git::{FilterOptions, Repository};
+use gittree::glyphs::GlyphSet;
+use gittree::keys::Keymap;
+use gittree::layers::{Layers, Origin};
+use gittree::revwalk::RefSelection;
+use gittree::schema;
+use std::path::Path;
 use std::process;
 
 fn main() {
     let matches = Command::new("gittree")
         .version("0.1.0")
+        .disable_version_flag(true)
         .about("A fast TUI that renders an ASCII/Unicode commit tree like GitHub's network graph")
         .arg(
             Arg::new("unicode")
@@ -51,6 +57,29 @@ fn main() {
                 .help("Rev range (e.g. main..feature)")
                 .value_name("RANGE"),
         )
+        .arg(
+            Arg::new("all")
+                .long("all")
+                .help("Walk every ref, like GitHub's network graph")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .args(["branches", "remotes", "tags"].map(|kind| {
+            Arg::new(kind)
+                .long(kind)
+                .help(format!("Walk {} matching GLOB (all when omitted; repeatable)", kind))
+                .value_name("GLOB")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("*")
+                .action(clap::ArgAction::Append)
+        }))
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .help("Leave refs matching GLOB out of --all/--branches/... (repeatable)")
+                .value_name("GLOB")
+                .action(clap::ArgAction::Append),
+        )
         .arg(
             Arg::new("max-commits")
                 .long("max-commits")
@@ -80,9 +109,8 @@ fn main() {
         .arg(
             Arg::new("style")
                 .long("style")
-                .help("Style (light, dark, auto)")
-                .value_name("STYLE")
-                .default_value("auto"),
+                .help("Style (light, dark, auto or a theme from the config)")
+                .value_name("STYLE"),
         )
         .arg(
             Arg::new("version")
@@ -90,6 +118,29 @@ fn main() {
                 .help("Show version information")
                 .action(clap::ArgAction::SetTrue),
         )
+        .subcommand(
+            Command::new("config")
+                .about("Inspect the config file")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("validate")
+                        .about("Check the config layers for errors and unknown keys")
+                        .arg(
+                            Arg::new("file")
+                                .help("Check only this config file")
+                                .value_name("FILE"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("show").about("Print the effective config").arg(
+                        Arg::new("origin")
+                            .long("origin")
+                            .help("List each setting with the layer it came from")
+                            .action(clap::ArgAction::SetTrue),
+                    ),
+                )
+                .subcommand(Command::new("schema").about("Print the JSON Schema of the config file")),
+        )
         .get_matches();
 
     if matches.get_flag("version") {
@@ -97,8 +148,38 @@ fn main() {
         process::exit(0);
     }
 
-    // Load configuration
-    let config = Config::load().unwrap_or_else(|_| Config::default());
+    // Merge the config layers, with command-line flags on top
+    let mut layers = Layers::discover(Path::new("."));
+    apply_flags(&mut layers, &matches);
+
+    if let Some(("config", matches)) = matches.subcommand() {
+        process::exit(config_command(matches, &layers));
+    }
+
+    for (origin, issue) in layers.issues() {
+        eprintln!("{}: {}", origin, issue);
+    }
+    let config = match layers.config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load config:\n{}", e);
+            process::exit(1);
+        }
+    };
+
+    // Refuse to start with key bindings that clash or don't parse
+    if let Err(errors) = Keymap::from_config(&config.keys) {
+        eprintln!("Invalid key bindings in config:");
+        for error in errors {
+            eprintln!("  {}", error);
+        }
+        process::exit(1);
+    }
+
+    if let Err(error) = schema::check_date_format(&config.date_format) {
+        eprintln!("Invalid config: {}", error);
+        process::exit(1);
+    }
 
     // Parse date filters
     let since = matches.get_one::<String>("since").map(|s| parse_time(s));
@@ -114,12 +195,38 @@ fn main() {
     };
 
     // Create filter options
+    // Refs selected on the command line replace the configured ones, and
+    // --exclude adds to the configured exclusions
+    let mut refs = match config.git.ref_selection() {
+        Ok(refs) => refs,
+        Err(e) => {
+            eprintln!("Invalid git.extraArgs in config: {}", e);
+            process::exit(1);
+        }
+    };
+    let globs = |name: &str| -> Vec<String> {
+        matches
+            .get_many::<String>(name)
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default()
+    };
+    let cli_refs = RefSelection {
+        all: matches.get_flag("all"),
+        branches: globs("branches"),
+        remotes: globs("remotes"),
+        tags: globs("tags"),
+        exclude: globs("exclude"),
+        ..RefSelection::default()
+    };
+    refs = refs.overridden_by(&cli_refs);
+    let default_range = Some(config.git.default_range.clone()).filter(|range| !range.is_empty());
     let filter = FilterOptions {
         author: matches.get_one::<String>("author").cloned(),
         path: matches.get_one::<String>("path").cloned(),
         since,
         until,
-        range: matches.get_one::<String>("range").cloned(),
+        range: matches.get_one::<String>("range").cloned().or(default_range),
+        refs,
         max_commits: matches.get_one::<usize>("max-commits").copied(),
     };
 
@@ -131,6 +238,97 @@ fn main() {
     }
 }
 
+/// Puts the command-line flags that mirror config settings on top of the
+/// config layers.
+fn apply_flags(layers: &mut Layers, matches: &clap::ArgMatches) {
+    let flag = |name: &str| Origin::CommandLine(format!("--{}", name));
+    if matches.get_flag("no-color") {
+        layers.set(&["noColor"], true, flag("no-color"));
+    } else if gittree::colors::no_color_env() {
+        layers.set(&["noColor"], true, Origin::Env("NO_COLOR".to_string()));
+    }
+    if let Some(style) = matches.get_one::<String>("style") {
+        layers.set(&["style"], style.as_str(), flag("style"));
+    }
+    if matches.get_flag("yes") {
+        layers.set(&["confirmDangerous"], false, flag("yes"));
+    }
+    let ascii = layers.config().is_ok_and(|config| config.glyph_set() == GlyphSet::Ascii);
+    if matches.get_flag("unicode") && ascii {
+        layers.set(&["glyphs"], GlyphSet::Unicode.name(), flag("unicode"));
+    }
+}
+
+/// Runs `gittree config ...`, returning the exit code.
+fn config_command(matches: &clap::ArgMatches, layers: &Layers) -> i32 {
+    match matches.subcommand() {
+        Some(("validate", matches)) => {
+            let mut issues: Vec<String> = Vec::new();
+            if let Some(file) = matches.get_one::<String>("file") {
+                match schema::check_file(Path::new(file)) {
+                    Ok(found) => issues.extend(found.iter().map(|issue| format!("{}: {}", file, issue))),
+                    Err(e) => issues.push(format!("{}: {}", file, e)),
+                }
+            } else {
+                issues.extend(layers.issues().iter().map(|(origin, issue)| format!("{}: {}", origin, issue)));
+                match layers.config() {
+                    Ok(config) => issues.extend(schema::check_values(&config).iter().map(|issue| issue.to_string())),
+                    Err(e) => issues.push(e.to_string()),
+                }
+            }
+
+            if issues.is_empty() {
+                let files: Vec<String> = layers.files().iter().map(|path| path.display().to_string()).collect();
+                match matches.get_one::<String>("file") {
+                    Some(file) => println!("{}: ok", file),
+                    None if files.is_empty() => println!("ok (no config files, defaults only)"),
+                    None => println!("ok ({})", files.join(", ")),
+                }
+                0
+            } else {
+                for issue in issues {
+                    println!("{}", issue);
+                }
+                1
+            }
+        }
+        Some(("show", matches)) => {
+            if matches.get_flag("origin") {
+                let settings = layers.origins();
+                let width = settings
+                    .iter()
+                    .map(|(path, value, _)| path.len() + value.len() + 3)
+                    .max()
+                    .unwrap_or(0);
+                for (path, value, origin) in settings {
+                    let setting = format!("{} = {}", path, value);
+                    println!("{:width$}  # {}", setting, origin, width = width);
+                }
+                return 0;
+            }
+            match layers.config().map(|config| serde_yaml::to_string(&config)) {
+                Ok(Ok(text)) => {
+                    print!("{}", text);
+                    0
+                }
+                Ok(Err(e)) => {
+                    eprintln!("Failed to print config: {}", e);
+                    1
+                }
+                Err(e) => {
+                    eprintln!("Failed to load config:\n{}", e);
+                    1
+                }
+            }
+        }
+        Some(("schema", _)) => {
+            print!("{}", schema::schema_json());
+            0
+        }
+        _ => 2,
+    }
+}
+
 fn parse_time(s: &str) -> chrono::DateTime<chrono::Utc> {
     // Try common formats
     if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(s) {
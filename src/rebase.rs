@@ -0,0 +1,428 @@
+//! Interactive rebase plans: the todo list of commits on top of a base, how
+//! it is edited, the history it would produce, and the todo file git runs.
+
+use crate::git::Commit;
+use git2::{Oid, Repository, Sort};
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Pick,
+    Reword,
+    Edit,
+    Squash,
+    Fixup,
+    Drop,
+}
+
+impl Command {
+    pub const ALL: [Command; 6] = [
+        Command::Pick,
+        Command::Reword,
+        Command::Edit,
+        Command::Squash,
+        Command::Fixup,
+        Command::Drop,
+    ];
+
+    /// The word used in git's todo list.
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Pick => "pick",
+            Command::Reword => "reword",
+            Command::Edit => "edit",
+            Command::Squash => "squash",
+            Command::Fixup => "fixup",
+            Command::Drop => "drop",
+        }
+    }
+
+    /// The command whose todo abbreviation is `key` (`p`, `r`, `e`, ...).
+    pub fn from_key(key: char) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|command| command.name().starts_with(key))
+    }
+
+    /// Whether the commit is folded into the one before it.
+    fn folds(self) -> bool {
+        matches!(self, Command::Squash | Command::Fixup)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub command: Command,
+    pub hash: String,
+    /// The commit's parent before the rebase.
+    pub parent: String,
+    pub subject: String,
+    pub author: String,
+    /// The new message of a `reword` step.
+    pub message: Option<String>,
+}
+
+/// The commits between a base and HEAD, oldest first, each with what to do
+/// with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub base: String,
+    pub steps: Vec<Step>,
+}
+
+impl Plan {
+    /// Plans picking every commit from `base` (exclusive) to HEAD again.
+    pub fn new(repo: &Repository, base: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let base_id = Oid::from_str(base)?;
+        let head = repo.head()?.peel_to_commit()?.id();
+        if head != base_id && !repo.graph_descendant_of(head, base_id)? {
+            return Err("the selected commit is not an ancestor of HEAD".into());
+        }
+
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        walk.push(head)?;
+        walk.hide(base_id)?;
+
+        let mut steps = Vec::new();
+        for oid in walk {
+            let commit = repo.find_commit(oid?)?;
+            if commit.parent_count() > 1 {
+                return Err(format!(
+                    "merge commit {} can't be rebased; pick a base above it",
+                    &commit.id().to_string()[..7]
+                )
+                .into());
+            }
+            steps.push(Step {
+                command: Command::Pick,
+                hash: commit.id().to_string(),
+                parent: commit.parent_id(0)?.to_string(),
+                subject: commit.summary().unwrap_or("").to_string(),
+                author: commit.author().name().unwrap_or("").to_string(),
+                message: None,
+            });
+        }
+        if steps.is_empty() {
+            return Err("no commits between the selected commit and HEAD".into());
+        }
+        Ok(Self {
+            base: base.to_string(),
+            steps,
+        })
+    }
+
+    /// Swaps the step at `index` with its neighbour above (`up`, towards the
+    /// base) or below, returning where the step ended up.
+    pub fn move_step(&mut self, index: usize, up: bool) -> usize {
+        let other = if up {
+            index.checked_sub(1)
+        } else {
+            Some(index + 1).filter(|&i| i < self.steps.len())
+        };
+        match other {
+            Some(other) => {
+                self.steps.swap(index, other);
+                other
+            }
+            None => index,
+        }
+    }
+
+    /// Moves each `fixup! <subject>` and `squash! <subject>` commit after the
+    /// commit it names (by subject or hash prefix) and folds it in, as
+    /// `git rebase --autosquash` does.
+    pub fn autosquash(&mut self) {
+        let mut steps: Vec<Step> = Vec::new();
+        for mut step in std::mem::take(&mut self.steps) {
+            let target = fold_target(&step.subject).and_then(|(command, target)| {
+                let position = steps.iter().position(|other| {
+                    other.subject == target || (target.len() >= 4 && other.hash.starts_with(target))
+                })?;
+                Some((command, position))
+            });
+            match target {
+                Some((command, position)) => {
+                    step.command = command;
+                    let mut at = position + 1;
+                    while steps.get(at).is_some_and(|other| other.command.folds()) {
+                        at += 1;
+                    }
+                    steps.insert(at, step);
+                }
+                None => steps.push(step),
+            }
+        }
+        self.steps = steps;
+    }
+
+    /// Why git would refuse the plan, if it would.
+    pub fn validate(&self) -> Result<(), String> {
+        let first = self.steps.iter().find(|step| step.command != Command::Drop);
+        match first {
+            None => Err("every commit is dropped".to_string()),
+            Some(step) if step.command.folds() => Err(format!(
+                "cannot {} {} without a commit before it",
+                step.command.name(),
+                &step.hash[..7]
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// The todo list for `git rebase -i`. A reworded commit is picked and
+    /// then amended with the message saved at `message_file(index)`.
+    pub fn todo(&self, message_file: impl Fn(usize) -> String) -> String {
+        let mut todo = String::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            match (&step.command, &step.message) {
+                (Command::Reword, Some(_)) => {
+                    todo.push_str(&format!("pick {} {}\n", step.hash, step.subject));
+                    todo.push_str(&format!(
+                        "exec git commit --amend --only --quiet --file '{}'\n",
+                        message_file(i).replace('\'', r"'\''")
+                    ));
+                }
+                (command, _) => todo.push_str(&format!(
+                    "{} {} {}\n",
+                    command.name(),
+                    step.hash,
+                    step.subject
+                )),
+            }
+        }
+        todo
+    }
+
+    /// The history the plan produces on top of `base`, newest first, as
+    /// commits for the lane engine. Squashed and fixed-up commits fold into
+    /// the one before them. A commit that is picked onto its old parent
+    /// keeps its hash; the others are shown as `new`.
+    pub fn preview(&self, base: Option<&Commit>) -> Vec<Commit> {
+        let mut results: Vec<Commit> = Vec::new();
+        let mut folded: HashMap<usize, usize> = HashMap::new();
+        // Hash of the last result while history is unchanged so far.
+        let mut kept = Some(self.base.clone());
+        for step in self
+            .steps
+            .iter()
+            .filter(|step| step.command != Command::Drop)
+        {
+            if step.command.folds() {
+                if let Some(last) = results.len().checked_sub(1) {
+                    *folded.entry(last).or_default() += 1;
+                    results[last].short_hash = "new".to_string();
+                    kept = None;
+                    continue;
+                }
+            }
+            let unchanged = matches!(step.command, Command::Pick | Command::Edit)
+                && kept.as_deref() == Some(step.parent.as_str());
+            kept = unchanged.then(|| step.hash.clone());
+
+            let subject = match (&step.command, &step.message) {
+                (Command::Reword, Some(message)) => {
+                    message.lines().next().unwrap_or("").to_string()
+                }
+                _ => step.subject.clone(),
+            };
+            let parent = match results.last() {
+                Some(previous) => previous.hash.clone(),
+                None => self.base.clone(),
+            };
+            results.push(Commit {
+                hash: format!("rebased-{}", results.len()),
+                short_hash: if unchanged {
+                    step.hash[..7].to_string()
+                } else {
+                    "new".to_string()
+                },
+                message: if step.command == Command::Edit {
+                    format!("{} (stop to edit)", subject)
+                } else {
+                    subject
+                },
+                author: step.author.clone(),
+                email: String::new(),
+                date: base.map(|base| base.date).unwrap_or_default(),
+                parents: vec![parent],
+                refs: Vec::new(),
+                decorations: Vec::new(),
+                lane: 0,
+                graph: Vec::new(),
+                files: Vec::new(),
+                stats: HashMap::new(),
+            });
+        }
+        for (index, count) in folded {
+            results[index]
+                .message
+                .push_str(&format!(" (+{} squashed)", count));
+        }
+
+        results.reverse();
+        if let Some(base) = base {
+            let mut base = base.clone();
+            base.parents.clear();
+            results.push(base);
+        }
+        results
+    }
+
+    /// Writes the todo list and reworded messages into `dir` and returns
+    /// the todo file's path.
+    pub fn write(&self, dir: &Path) -> std::io::Result<std::path::PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let message_file = |i: usize| dir.join(format!("message-{}", i));
+        for (i, step) in self.steps.iter().enumerate() {
+            if let Some(message) = &step.message {
+                std::fs::write(message_file(i), message)?;
+            }
+        }
+        let todo = dir.join("todo");
+        std::fs::write(&todo, self.todo(|i| message_file(i).display().to_string()))?;
+        Ok(todo)
+    }
+}
+
+/// How a `fixup! ` or `squash! ` commit folds in and the subject or hash
+/// it names; repeated prefixes are skipped.
+fn fold_target(subject: &str) -> Option<(Command, &str)> {
+    let (command, mut target) = match (
+        subject.strip_prefix("fixup! "),
+        subject.strip_prefix("squash! "),
+    ) {
+        (Some(rest), _) => (Command::Fixup, rest),
+        (_, Some(rest)) => (Command::Squash, rest),
+        _ => return None,
+    };
+    while let Some(rest) = target
+        .strip_prefix("fixup! ")
+        .or_else(|| target.strip_prefix("squash! "))
+    {
+        target = rest;
+    }
+    Some((command, target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(repo: &Repository, parents: &[Oid], subject: &str) -> Oid {
+        let signature = git2::Signature::now("Alice", "alice@example.com").unwrap();
+        let tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let parents: Vec<git2::Commit> = parents
+            .iter()
+            .map(|id| repo.find_commit(*id).unwrap())
+            .collect();
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            subject,
+            &tree,
+            &parents,
+        )
+        .unwrap()
+    }
+
+    fn plan(name: &str) -> (Plan, std::path::PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("gittree-rebase-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo = Repository::init(&dir).unwrap();
+        let base = commit(&repo, &[], "base");
+        let one = commit(&repo, &[base], "one");
+        let two = commit(&repo, &[one], "two");
+        commit(&repo, &[two], "three");
+        (Plan::new(&repo, &base.to_string()).unwrap(), dir)
+    }
+
+    #[test]
+    fn test_plan_and_todo() {
+        let (mut plan, dir) = plan("todo");
+        let subjects: Vec<&str> = plan.steps.iter().map(|s| s.subject.as_str()).collect();
+        assert_eq!(subjects, ["one", "two", "three"]);
+
+        assert_eq!(plan.move_step(2, true), 1);
+        assert_eq!(plan.move_step(0, true), 0);
+        plan.steps[1].command = Command::from_key('f').unwrap();
+        plan.steps[2].command = Command::Reword;
+        plan.steps[2].message = Some("second\n".to_string());
+        let todo = plan.todo(|i| format!("/tmp/msg-{}", i));
+        let lines: Vec<String> = todo
+            .lines()
+            .map(|line| {
+                line.split(' ')
+                    .filter(|w| w.len() != 40)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "pick one",
+                "fixup three",
+                "pick two",
+                "exec git commit --amend --only --quiet --file '/tmp/msg-2'",
+            ]
+        );
+        assert_eq!(plan.validate(), Ok(()));
+        plan.steps[0].command = Command::Drop;
+        assert!(plan.validate().is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_autosquash() {
+        let (mut plan, dir) = plan("autosquash");
+        let one = plan.steps[0].hash.clone();
+        plan.steps[1].subject = "fixup! fixup! one".to_string();
+        plan.steps[2].subject = format!("squash! {}", &one[..7]);
+        plan.autosquash();
+        let steps: Vec<(Command, &str)> = plan
+            .steps
+            .iter()
+            .map(|step| (step.command, step.subject.as_str()))
+            .collect();
+        assert_eq!(
+            steps[..2],
+            [
+                (Command::Pick, "one"),
+                (Command::Fixup, "fixup! fixup! one")
+            ]
+        );
+        assert_eq!(steps[2].0, Command::Squash);
+        assert_eq!(plan.preview(None)[0].message, "one (+2 squashed)");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_preview() {
+        let (mut plan, dir) = plan("preview");
+        plan.steps[1].command = Command::Squash;
+        plan.steps[2].command = Command::Edit;
+        let preview = plan.preview(None);
+        let messages: Vec<&str> = preview.iter().map(|c| c.message.as_str()).collect();
+        assert_eq!(messages, ["three (stop to edit)", "one (+1 squashed)"]);
+        assert_eq!(preview[0].parents, [preview[1].hash.clone()]);
+        assert_eq!(preview[1].parents, [plan.base.clone()]);
+        assert_eq!(preview[1].short_hash, "new");
+
+        plan.steps[1].command = Command::Pick;
+        let preview = plan.preview(None);
+        assert_eq!(preview[0].short_hash, plan.steps[2].hash[..7]);
+        plan.move_step(2, true);
+        assert_eq!(plan.preview(None)[0].short_hash, "new");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
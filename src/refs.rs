@@ -0,0 +1,471 @@
+//! Branches, remotes, tags and stashes for the ref sidebar, the badges
+//! shown next to commits, and how local branches track their upstreams.
+
+use crate::colors::RefKind;
+use crate::revwalk::time_of;
+use chrono::{DateTime, Utc};
+use git2::{BranchType, Repository};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// A branch, remote-tracking branch, tag or stash entry and the commit it
+/// points at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ref {
+    /// Full name (`refs/heads/main`), or `stash@{n}` for stash entries.
+    pub name: String,
+    /// Name as shown to the user (`main`, `origin/main`, `v1.0`).
+    pub short: String,
+    pub group: Group,
+    /// Commit the ref resolves to, with annotated tags peeled.
+    pub target: String,
+    /// Committer date of `target`.
+    pub date: DateTime<Utc>,
+    /// Short name of the upstream branch of a local branch.
+    pub upstream: Option<String>,
+    /// Commits ahead of and behind `upstream`.
+    pub ahead_behind: Option<(usize, usize)>,
+    /// Whether HEAD points at this branch.
+    pub is_head: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Group {
+    Branches,
+    Remotes,
+    Tags,
+    Stashes,
+}
+
+impl Group {
+    pub const ALL: [Group; 4] = [Group::Branches, Group::Remotes, Group::Tags, Group::Stashes];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Group::Branches => "Branches",
+            Group::Remotes => "Remotes",
+            Group::Tags => "Tags",
+            Group::Stashes => "Stashes",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sort {
+    /// Newest commit first.
+    Recency,
+    Name,
+}
+
+impl Ref {
+    /// Kind used to pick the ref's color.
+    pub fn kind(&self) -> RefKind {
+        if self.is_head {
+            RefKind::Head
+        } else {
+            RefKind::of(&self.name)
+        }
+    }
+}
+
+/// One badge in a commit's decoration, e.g. `HEAD -> main = origin/main`
+/// or `tag: v1.0`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decoration {
+    /// Full name of the ref the badge is for (`HEAD` when detached).
+    pub name: String,
+    /// Short name, prefixed by the symbolic refs pointing at it.
+    pub label: String,
+    /// Remote-tracking branches of the same name on the same commit,
+    /// folded into a local branch's badge.
+    pub remotes: Vec<String>,
+    /// Whether HEAD points here.
+    pub head: bool,
+    /// Ahead/behind counts against the branch's upstream, e.g. `↑2 ↓1`.
+    #[serde(default)]
+    pub tracking: String,
+}
+
+impl Decoration {
+    /// Kind used to pick the badge's color.
+    pub fn kind(&self) -> RefKind {
+        if self.head {
+            RefKind::Head
+        } else {
+            RefKind::of(&self.name)
+        }
+    }
+}
+
+impl std::fmt::Display for Decoration {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.label)?;
+        if !self.tracking.is_empty() {
+            write!(f, " {}", self.tracking)?;
+        }
+        for remote in &self.remotes {
+            write!(f, " = {}", remote)?;
+        }
+        Ok(())
+    }
+}
+
+/// Name as `git log --decorate` shows it: `main`, `origin/main`,
+/// `tag: v1.0`, `stash`.
+pub fn short_name(name: &str) -> String {
+    if let Some(tag) = name.strip_prefix("refs/tags/") {
+        return format!("tag: {}", tag);
+    }
+    ["refs/heads/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+        .to_string()
+}
+
+/// Turns the full names of the refs on one commit into badges. `symbolic`
+/// maps symbolic refs (`HEAD`, `refs/remotes/origin/HEAD`) to the ref they
+/// point at; one pointing at a ref on the same commit is shown as
+/// `HEAD -> main` instead of on its own. A remote-tracking branch named like
+/// a local branch on the same commit joins that branch's badge.
+pub fn decorate(refs: &[String], symbolic: &HashMap<String, String>) -> Vec<Decoration> {
+    let present = |name: &String| refs.contains(name);
+    let label = |name: &str| {
+        let mut label = String::new();
+        let mut pointing: Vec<&String> = symbolic
+            .iter()
+            .filter(|(from, to)| to.as_str() == name && present(from))
+            .map(|(from, _)| from)
+            .collect();
+        pointing.sort_by_key(|from| (from.as_str() != "HEAD", from.as_str()));
+        for from in pointing {
+            label.push_str(&short_name(from));
+            label.push_str(" -> ");
+        }
+        label.push_str(&short_name(name));
+        label
+    };
+    let points_here = |name: &str| {
+        name == "HEAD" && !symbolic.contains_key("HEAD")
+            || symbolic
+                .get("HEAD")
+                .is_some_and(|to| to == name && present(&"HEAD".to_string()))
+    };
+
+    let mut names: Vec<&String> = refs
+        .iter()
+        .filter(|name| !symbolic.get(*name).is_some_and(present))
+        .collect();
+    names.sort_by_key(|name| (!points_here(name), rank(name), name.as_str()));
+
+    let mut decorations: Vec<Decoration> = Vec::new();
+    let mut folded = Vec::new();
+    for name in &names {
+        if folded.contains(name) {
+            continue;
+        }
+        let mut remotes = Vec::new();
+        if let Some(branch) = name.strip_prefix("refs/heads/") {
+            for other in &names {
+                let same = other
+                    .strip_prefix("refs/remotes/")
+                    .and_then(|remote| remote.split_once('/'))
+                    .is_some_and(|(_, remote_branch)| remote_branch == branch);
+                if same {
+                    remotes.push(label(other));
+                    folded.push(*other);
+                }
+            }
+        }
+        decorations.push(Decoration {
+            name: name.to_string(),
+            label: label(name),
+            remotes,
+            head: points_here(name),
+            tracking: String::new(),
+        });
+    }
+    decorations
+}
+
+/// Order of badges: branches, remote-tracking branches, tags, the rest.
+fn rank(name: &str) -> usize {
+    ["refs/heads/", "refs/remotes/", "refs/tags/"]
+        .iter()
+        .position(|prefix| name.starts_with(prefix))
+        .unwrap_or(3)
+}
+
+/// A local branch, its configured upstream and how far apart they are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tracking {
+    /// Full name of the local branch.
+    pub branch: String,
+    /// Full name of the upstream branch.
+    pub upstream: String,
+    /// Commits on the branch that the upstream lacks.
+    pub ahead: usize,
+    /// Commits on the upstream that the branch lacks.
+    pub behind: usize,
+    /// The commit where the two split, when both have commits of their own.
+    pub fork_point: Option<String>,
+}
+
+impl Tracking {
+    /// `↑2 ↓1`, leaving out zero counts; empty when in sync.
+    pub fn counts(&self) -> String {
+        let mut parts = Vec::new();
+        if self.ahead > 0 {
+            parts.push(format!("↑{}", self.ahead));
+        }
+        if self.behind > 0 {
+            parts.push(format!("↓{}", self.behind));
+        }
+        parts.join(" ")
+    }
+}
+
+/// Upstream tracking of every local branch that has an upstream.
+pub fn tracking(repo: &Repository) -> Result<Vec<Tracking>, git2::Error> {
+    let mut tracking = Vec::new();
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        tracking.extend(track(repo, &branch)?);
+    }
+    Ok(tracking)
+}
+
+fn track(repo: &Repository, branch: &git2::Branch) -> Result<Option<Tracking>, git2::Error> {
+    let Ok(upstream) = branch.upstream() else {
+        return Ok(None);
+    };
+    let (Some(name), Some(upstream_name)) = (branch.get().name(), upstream.get().name()) else {
+        return Ok(None);
+    };
+    let ours = branch.get().peel_to_commit()?.id();
+    let theirs = upstream.get().peel_to_commit()?.id();
+    let (ahead, behind) = repo.graph_ahead_behind(ours, theirs)?;
+    let fork_point = if ahead > 0 && behind > 0 {
+        repo.merge_base(ours, theirs).ok().map(|id| id.to_string())
+    } else {
+        None
+    };
+    Ok(Some(Tracking {
+        branch: name.to_string(),
+        upstream: upstream_name.to_string(),
+        ahead,
+        behind,
+        fork_point,
+    }))
+}
+
+/// Every ref of `repo` that resolves to a commit, grouped and sorted by
+/// `sort` within each group; stashes stay in stack order.
+pub fn load(repo: &Repository, sort: Sort) -> Result<Vec<Ref>, git2::Error> {
+    let head = repo
+        .head()
+        .ok()
+        .and_then(|head| head.name().map(str::to_string));
+    let mut refs = Vec::new();
+
+    for reference in repo.references()? {
+        let reference = reference?;
+        let (Some(name), Ok(commit)) = (reference.name(), reference.peel_to_commit()) else {
+            continue;
+        };
+        let group = if reference.is_branch() {
+            Group::Branches
+        } else if reference.is_remote() {
+            if name.ends_with("/HEAD") {
+                continue;
+            }
+            Group::Remotes
+        } else if reference.is_tag() {
+            Group::Tags
+        } else {
+            continue;
+        };
+
+        let mut entry = Ref {
+            name: name.to_string(),
+            short: reference.shorthand().unwrap_or(name).to_string(),
+            group,
+            target: commit.id().to_string(),
+            date: time_of(&commit.time()),
+            upstream: None,
+            ahead_behind: None,
+            is_head: head.as_deref() == Some(name),
+        };
+        if group == Group::Branches {
+            let branch = repo.find_branch(&entry.short, BranchType::Local)?;
+            if let Some(tracking) = track(repo, &branch)? {
+                entry.upstream = Some(short_name(&tracking.upstream));
+                entry.ahead_behind = Some((tracking.ahead, tracking.behind));
+            }
+        }
+        refs.push(entry);
+    }
+
+    let mut stashes = Vec::new();
+    if let Ok(reflog) = repo.reflog("refs/stash") {
+        for (i, entry) in reflog.iter().enumerate() {
+            let Ok(commit) = repo.find_commit(entry.id_new()) else {
+                continue;
+            };
+            stashes.push(Ref {
+                name: format!("stash@{{{}}}", i),
+                short: entry.message().unwrap_or("").to_string(),
+                group: Group::Stashes,
+                target: commit.id().to_string(),
+                date: time_of(&commit.time()),
+                upstream: None,
+                ahead_behind: None,
+                is_head: false,
+            });
+        }
+    }
+
+    refs.sort_by(|a, b| a.group.cmp(&b.group).then_with(|| compare(a, b, sort)));
+    refs.extend(stashes);
+    Ok(refs)
+}
+
+fn compare(a: &Ref, b: &Ref, sort: Sort) -> Ordering {
+    match sort {
+        Sort::Recency => b.date.cmp(&a.date).then_with(|| a.short.cmp(&b.short)),
+        Sort::Name => a.short.cmp(&b.short),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Signature, Time};
+
+    fn commit(repo: &Repository, parent: Option<git2::Oid>, time: i64) -> git2::Oid {
+        let sig = Signature::new("Test", "test@example.com", &Time::new(time, 0)).unwrap();
+        let tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let parents: Vec<git2::Commit> = parent
+            .into_iter()
+            .map(|id| repo.find_commit(id).unwrap())
+            .collect();
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        repo.commit(None, &sig, &sig, "commit", &tree, &parents)
+            .unwrap()
+    }
+
+    fn repo(name: &str) -> (Repository, std::path::PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("gittree-refs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo = Repository::init(&dir).unwrap();
+        (repo, dir)
+    }
+
+    #[test]
+    fn test_groups_and_sorting() {
+        let (repo, dir) = repo("groups");
+        let base = commit(&repo, None, 100);
+        let newer = commit(&repo, Some(base), 200);
+        repo.reference("refs/heads/main", newer, true, "").unwrap();
+        repo.reference("refs/heads/alpha", base, true, "").unwrap();
+        repo.reference("refs/remotes/origin/main", base, true, "")
+            .unwrap();
+        repo.reference("refs/tags/v1", base, true, "").unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+
+        let names = |refs: Vec<Ref>| refs.into_iter().map(|r| r.short).collect::<Vec<_>>();
+        assert_eq!(
+            names(load(&repo, Sort::Recency).unwrap()),
+            ["main", "alpha", "origin/main", "v1"]
+        );
+        let refs = load(&repo, Sort::Name).unwrap();
+        assert!(refs.iter().find(|r| r.short == "main").unwrap().is_head);
+        assert_eq!(refs[0].group, Group::Branches);
+        assert_eq!(refs[3].group, Group::Tags);
+        assert_eq!(names(refs), ["alpha", "main", "origin/main", "v1"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_decorations() {
+        let refs = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let symbolic: HashMap<String, String> = [
+            ("HEAD", "refs/heads/main"),
+            ("refs/remotes/origin/HEAD", "refs/remotes/origin/main"),
+        ]
+        .iter()
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .collect();
+        let labels = |decorations: Vec<Decoration>| {
+            decorations
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let decorations = decorate(
+            &refs(&[
+                "refs/tags/v1",
+                "refs/remotes/origin/main",
+                "refs/remotes/origin/HEAD",
+                "refs/heads/main",
+                "HEAD",
+                "refs/heads/dev",
+            ]),
+            &symbolic,
+        );
+        assert!(decorations[0].head);
+        assert_eq!(decorations[0].kind(), RefKind::Head);
+        assert_eq!(
+            labels(decorations),
+            [
+                "HEAD -> main = origin/HEAD -> origin/main",
+                "dev",
+                "tag: v1"
+            ]
+        );
+
+        // Detached HEAD, and a remote branch on a different commit.
+        let decorations = decorate(
+            &refs(&["refs/remotes/origin/dev", "HEAD", "refs/stash"]),
+            &HashMap::new(),
+        );
+        assert!(decorations[0].head);
+        assert_eq!(labels(decorations), ["HEAD", "origin/dev", "stash"]);
+    }
+
+    #[test]
+    fn test_ahead_behind_upstream() {
+        let (repo, dir) = repo("upstream");
+        let base = commit(&repo, None, 100);
+        let local = commit(&repo, Some(base), 200);
+        let local = commit(&repo, Some(local), 300);
+        let remote = commit(&repo, Some(base), 250);
+        repo.reference("refs/heads/main", local, true, "").unwrap();
+        repo.reference("refs/remotes/origin/main", remote, true, "")
+            .unwrap();
+        repo.remote("origin", "https://example.com/repo.git")
+            .unwrap();
+        repo.find_branch("main", BranchType::Local)
+            .unwrap()
+            .set_upstream(Some("origin/main"))
+            .unwrap();
+
+        let refs = load(&repo, Sort::Name).unwrap();
+        assert_eq!(refs[0].upstream.as_deref(), Some("origin/main"));
+        assert_eq!(refs[0].ahead_behind, Some((2, 1)));
+        let tracking = tracking(&repo).unwrap();
+        assert_eq!(tracking.len(), 1);
+        assert_eq!(tracking[0].upstream, "refs/remotes/origin/main");
+        assert_eq!(tracking[0].counts(), "↑2 ↓1");
+        assert_eq!(tracking[0].fork_point, Some(base.to_string()));
+        assert_eq!(refs[1].ahead_behind, None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
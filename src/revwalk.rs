@@ -0,0 +1,617 @@
+//! Native history walk: which refs to start from and which commits to keep.
+
+use crate::git::{Commit, FilterOptions};
+use chrono::{DateTime, Utc};
+use git2::{DiffOptions, Oid, Repository, RevparseMode, Sort};
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The refs a walk starts from, the structured form of `git log`'s `--all`,
+/// `--branches=<glob>`, `--remotes=<glob>`, `--tags=<glob>`, `--glob=<glob>`
+/// and `--exclude=<glob>`. Empty means HEAD.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct RefSelection {
+    /// Every ref, plus HEAD.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub all: bool,
+    /// Local branches matching these globs (`*` for all of them).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub branches: Vec<String>,
+    /// Remote-tracking branches matching these globs.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub remotes: Vec<String>,
+    /// Tags matching these globs.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Refs matching these globs, relative to `refs/`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub globs: Vec<String>,
+    /// Refs to leave out of the ones selected above.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Refs picked by name (`main`, `origin/main`, the full name or `HEAD`);
+    /// these are never excluded.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub names: Vec<String>,
+}
+
+impl RefSelection {
+    /// Parses `git log`-style options such as `--all` or
+    /// `--branches=release/*` into a selection.
+    pub fn parse_args<S: AsRef<str>>(args: &[S]) -> Result<Self, String> {
+        let mut selection = Self::default();
+        for arg in args {
+            let arg = arg.as_ref();
+            let (option, value) = match arg.split_once('=') {
+                Some((option, value)) => (option, Some(value.to_string())),
+                None => (arg, None),
+            };
+            let pattern = || value.clone().unwrap_or_else(|| "*".to_string());
+            match option {
+                "--all" if value.is_none() => selection.all = true,
+                "--branches" => selection.branches.push(pattern()),
+                "--remotes" => selection.remotes.push(pattern()),
+                "--tags" => selection.tags.push(pattern()),
+                "--glob" if value.is_some() => selection.globs.push(pattern()),
+                "--exclude" if value.is_some() => selection.exclude.push(pattern()),
+                name if !name.starts_with('-') => selection.names.push(arg.to_string()),
+                _ => return Err(format!("unsupported revision option '{}'", arg)),
+            }
+        }
+        Ok(selection)
+    }
+
+    /// `self` with the refs selected by `other` added.
+    pub fn merged(&self, other: &RefSelection) -> RefSelection {
+        let join = |a: &[String], b: &[String]| {
+            let mut out = a.to_vec();
+            out.extend(b.iter().filter(|item| !a.contains(item)).cloned());
+            out
+        };
+        RefSelection {
+            all: self.all || other.all,
+            branches: join(&self.branches, &other.branches),
+            remotes: join(&self.remotes, &other.remotes),
+            tags: join(&self.tags, &other.tags),
+            globs: join(&self.globs, &other.globs),
+            exclude: join(&self.exclude, &other.exclude),
+            names: join(&self.names, &other.names),
+        }
+    }
+
+    /// `self` overridden by ref options from the command line: refs they
+    /// select replace the ones selected here, while their `--exclude`
+    /// patterns add to the excluded ones.
+    pub fn overridden_by(&self, flags: &RefSelection) -> RefSelection {
+        let base = if flags.is_empty() {
+            self.clone()
+        } else {
+            RefSelection {
+                exclude: self.exclude.clone(),
+                ..RefSelection::default()
+            }
+        };
+        base.merged(flags)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.all
+            && self.branches.is_empty()
+            && self.remotes.is_empty()
+            && self.tags.is_empty()
+            && self.globs.is_empty()
+            && self.names.is_empty()
+    }
+
+    /// The selection as `git log` options, e.g. `--all --exclude=refs/stash`.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if self.all {
+            parts.push("--all".to_string());
+        }
+        let options = [
+            ("--branches", &self.branches),
+            ("--remotes", &self.remotes),
+            ("--tags", &self.tags),
+            ("--glob", &self.globs),
+            ("--exclude", &self.exclude),
+        ];
+        for (option, patterns) in options {
+            parts.extend(patterns.iter().map(|pattern| match pattern.as_str() {
+                "*" if option != "--exclude" && option != "--glob" => option.to_string(),
+                _ => format!("{}={}", option, pattern),
+            }));
+        }
+        parts.extend(
+            self.names
+                .iter()
+                .map(|name| short_name(name).unwrap_or(name).to_string()),
+        );
+        parts.join(" ")
+    }
+
+    /// Whether the full ref name `name` is selected.
+    pub fn selects(&self, name: &str) -> bool {
+        if self
+            .names
+            .iter()
+            .any(|picked| picked == name || short_name(name) == Some(picked.as_str()))
+        {
+            return true;
+        }
+        let excluded = self.exclude.iter().any(|pattern| {
+            glob_match(pattern, name)
+                || short_name(name).is_some_and(|short| glob_match(pattern, short))
+        });
+        if excluded {
+            return false;
+        }
+        let under = |prefix: &str, patterns: &[String]| {
+            name.strip_prefix(prefix).is_some_and(|rest| {
+                patterns
+                    .iter()
+                    .any(|pattern| glob_match(&directory_glob(pattern), rest))
+            })
+        };
+        self.all
+            || under("refs/heads/", &self.branches)
+            || under("refs/remotes/", &self.remotes)
+            || under("refs/tags/", &self.tags)
+            || under("refs/", &self.globs)
+    }
+}
+
+/// A pattern without glob characters names a directory, as in git:
+/// `--branches=release` selects `release/*`.
+fn directory_glob(pattern: &str) -> String {
+    if pattern.contains(['*', '?']) {
+        pattern.to_string()
+    } else {
+        format!("{}/*", pattern.trim_end_matches('/'))
+    }
+}
+
+fn short_name(name: &str) -> Option<&str> {
+    ["refs/heads/", "refs/remotes/", "refs/tags/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+}
+
+/// Shell-style match where `*` also crosses `/`, like git's ref globs.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                t += 1;
+            }
+            Some(c) if *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Walks the history selected by `filter`, newest first in date order, and
+/// keeps the commits its filters accept. A kept commit's parents are
+/// rewritten to its nearest kept ancestors, as `git log --parents` does, so
+/// the graph stays connected across the commits left out.
+pub fn commits(
+    repo: &Repository,
+    filter: &FilterOptions,
+) -> Result<Vec<Commit>, Box<dyn std::error::Error>> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+
+    if filter.refs.all || filter.refs.names.iter().any(|name| name == "HEAD") {
+        if let Ok(head) = repo.head().and_then(|head| head.peel_to_commit()) {
+            walk.push(head.id())?;
+        }
+    }
+    for reference in repo.references()? {
+        let reference = reference?;
+        let Some(name) = reference.name() else {
+            continue;
+        };
+        if filter.refs.selects(name) {
+            if let Ok(commit) = reference.peel_to_commit() {
+                walk.push(commit.id())?;
+            }
+        }
+    }
+    match &filter.range {
+        Some(range) => push_range(repo, &mut walk, range)?,
+        None if filter.refs.is_empty() => walk.push_head()?,
+        None => {}
+    }
+
+    let author = filter
+        .author
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .map_err(|err| format!("invalid --author pattern: {}", err))?;
+
+    let mut commits = Vec::new();
+    // Commits a filter left out, with their parents, in walk order
+    let mut dropped: Vec<(String, Vec<String>)> = Vec::new();
+    for oid in walk {
+        if filter
+            .max_commits
+            .is_some_and(|max| max > 0 && commits.len() >= max)
+        {
+            break;
+        }
+        let commit = repo.find_commit(oid?)?;
+        let signature = commit.author();
+        let name = signature.name().unwrap_or("");
+        let email = signature.email().unwrap_or("");
+        let date = time_of(&signature.when());
+        let hash = commit.id().to_string();
+        let parents: Vec<String> = commit.parent_ids().map(|id| id.to_string()).collect();
+
+        let committed = time_of(&commit.committer().when());
+        let kept = author
+            .as_ref()
+            .is_none_or(|author| author.is_match(&format!("{} <{}>", name, email)))
+            && filter.since.is_none_or(|since| committed >= since)
+            && filter.until.is_none_or(|until| committed <= until)
+            && match &filter.path {
+                Some(path) => touches(repo, &commit, path)?,
+                None => true,
+            };
+        if !kept {
+            dropped.push((hash, parents));
+            continue;
+        }
+
+        commits.push(Commit {
+            short_hash: hash[..7].to_string(),
+            hash,
+            message: commit.summary().unwrap_or("").to_string(),
+            author: name.to_string(),
+            email: email.to_string(),
+            date,
+            parents,
+            refs: Vec::new(),
+            decorations: Vec::new(),
+            lane: 0,
+            graph: Vec::new(),
+            files: Vec::new(),
+            stats: HashMap::new(),
+        });
+    }
+    if !dropped.is_empty() {
+        skip_dropped(&mut commits, dropped);
+    }
+    Ok(commits)
+}
+
+/// Points the parents of `commits` past the `dropped` ones (children before
+/// parents, as walked) to the nearest ancestors that were kept.
+fn skip_dropped(commits: &mut [Commit], dropped: Vec<(String, Vec<String>)>) {
+    // In reverse, a dropped commit's parents are resolved before it is.
+    let mut stand_ins: HashMap<String, Vec<String>> = HashMap::new();
+    for (hash, parents) in dropped.into_iter().rev() {
+        let ancestors = kept_ancestors(&stand_ins, parents);
+        stand_ins.insert(hash, ancestors);
+    }
+    for commit in commits {
+        commit.parents = kept_ancestors(&stand_ins, std::mem::take(&mut commit.parents));
+    }
+}
+
+/// `parents` with each dropped one replaced by what it stands in for,
+/// without duplicates.
+fn kept_ancestors(stand_ins: &HashMap<String, Vec<String>>, parents: Vec<String>) -> Vec<String> {
+    let mut ancestors: Vec<String> = Vec::new();
+    for parent in parents {
+        let found = stand_ins
+            .get(&parent)
+            .cloned()
+            .unwrap_or_else(|| vec![parent]);
+        for ancestor in found {
+            if !ancestors.contains(&ancestor) {
+                ancestors.push(ancestor);
+            }
+        }
+    }
+    ancestors
+}
+
+/// Adds a revision (`main`, `^old`) or range (`a..b`, `a...b`) to the walk.
+fn push_range(
+    repo: &Repository,
+    walk: &mut git2::Revwalk,
+    spec: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(hidden) = spec.strip_prefix('^') {
+        walk.hide(repo.revparse_single(hidden)?.peel_to_commit()?.id())?;
+        return Ok(());
+    }
+
+    let revspec = repo.revparse(spec)?;
+    let commit_of = |object: Option<&git2::Object>| -> Result<Option<Oid>, git2::Error> {
+        object
+            .map(|object| object.peel_to_commit().map(|c| c.id()))
+            .transpose()
+    };
+    let from = commit_of(revspec.from())?;
+    let to = commit_of(revspec.to())?;
+    let mode = revspec.mode();
+
+    if mode.contains(RevparseMode::SINGLE) {
+        if let Some(from) = from {
+            walk.push(from)?;
+        }
+        return Ok(());
+    }
+    // An open end of a range (`main..`) means HEAD.
+    let head = || {
+        repo.head()
+            .and_then(|head| head.peel_to_commit())
+            .map(|c| c.id())
+    };
+    let from = match from {
+        Some(from) => from,
+        None => head()?,
+    };
+    let to = match to {
+        Some(to) => to,
+        None => head()?,
+    };
+    walk.push(to)?;
+    if mode.contains(RevparseMode::MERGE_BASE) {
+        walk.push(from)?;
+        if let Ok(base) = repo.merge_base(from, to) {
+            walk.hide(base)?;
+        }
+    } else {
+        walk.hide(from)?;
+    }
+    Ok(())
+}
+
+/// Whether `commit` changes `path` relative to each of its parents, which is
+/// how `git log -- <path>` decides (a merge taking one side unchanged is
+/// left out).
+fn touches(repo: &Repository, commit: &git2::Commit, path: &str) -> Result<bool, git2::Error> {
+    let tree = commit.tree()?;
+    let mut options = DiffOptions::new();
+    options.pathspec(path);
+
+    if commit.parent_count() == 0 {
+        let diff = repo.diff_tree_to_tree(None, Some(&tree), Some(&mut options))?;
+        return Ok(diff.deltas().len() > 0);
+    }
+    for parent in commit.parents() {
+        let diff =
+            repo.diff_tree_to_tree(Some(&parent.tree()?), Some(&tree), Some(&mut options))?;
+        if diff.deltas().len() == 0 {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+pub(crate) fn time_of(time: &git2::Time) -> DateTime<Utc> {
+    DateTime::from_timestamp(time.seconds(), 0).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(repo: &Repository, refname: &str, parents: &[Oid], file: &str, time: i64) -> Oid {
+        commit_by(repo, "Alice", refname, parents, file, time)
+    }
+
+    fn commit_by(
+        repo: &Repository,
+        author: &str,
+        refname: &str,
+        parents: &[Oid],
+        file: &str,
+        time: i64,
+    ) -> Oid {
+        let email = format!("{}@example.com", author.to_lowercase());
+        let signature = git2::Signature::new(author, &email, &git2::Time::new(time, 0)).unwrap();
+        let blob = repo.blob(file.as_bytes()).unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder.insert(file, blob, 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let parents: Vec<git2::Commit> = parents
+            .iter()
+            .map(|id| repo.find_commit(*id).unwrap())
+            .collect();
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        let id = repo
+            .commit(None, &signature, &signature, file, &tree, &parents)
+            .unwrap();
+        repo.reference(refname, id, true, "test").unwrap();
+        id
+    }
+
+    fn repo(name: &str) -> (Repository, std::path::PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("gittree-walk-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo = Repository::init(&dir).unwrap();
+        (repo, dir)
+    }
+
+    fn subjects(repo: &Repository, filter: &FilterOptions) -> Vec<String> {
+        commits(repo, filter)
+            .unwrap()
+            .into_iter()
+            .map(|commit| commit.message)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_args_and_select() {
+        let selection =
+            RefSelection::parse_args(&["--all", "--branches=release", "--exclude=refs/remotes/*"])
+                .unwrap();
+        assert!(selection.all);
+        assert_eq!(
+            selection.describe(),
+            "--all --branches=release --exclude=refs/remotes/*"
+        );
+        assert!(selection.selects("refs/heads/main"));
+        assert!(!selection.selects("refs/remotes/origin/main"));
+
+        let release = RefSelection::parse_args(&["--branches=release"]).unwrap();
+        assert!(release.selects("refs/heads/release/1.0"));
+        assert!(!release.selects("refs/heads/main"));
+        assert!(RefSelection::parse_args(&["--first-parent"]).is_err());
+
+        let picked = RefSelection {
+            names: vec!["refs/remotes/origin/main".to_string()],
+            ..selection
+        };
+        assert!(picked.selects("refs/remotes/origin/main"));
+        assert_eq!(
+            picked.describe(),
+            "--all --branches=release --exclude=refs/remotes/* origin/main"
+        );
+        assert!(glob_match("v1.*", "v1.2.3"));
+        assert!(!glob_match("v1.?", "v1.10"));
+    }
+
+    #[test]
+    fn test_flags_override_config() {
+        let config = RefSelection::parse_args(&["--all", "--exclude=refs/stash"]).unwrap();
+        let exclude = RefSelection::parse_args(&["--exclude=refs/heads/wip*"]).unwrap();
+        let selection = config.overridden_by(&exclude);
+        assert_eq!(
+            selection.describe(),
+            "--all --exclude=refs/stash --exclude=refs/heads/wip*"
+        );
+        assert!(!selection.selects("refs/heads/wip/1"));
+
+        let branches = RefSelection::parse_args(&["--branches"]).unwrap();
+        assert_eq!(
+            config.overridden_by(&branches).describe(),
+            "--branches --exclude=refs/stash"
+        );
+        assert_eq!(config.overridden_by(&RefSelection::default()), config);
+    }
+
+    #[test]
+    fn test_walk_follows_selection_and_range() {
+        let (repo, dir) = repo("select");
+        let base = commit(&repo, "refs/heads/main", &[], "base", 100);
+        let main = commit(&repo, "refs/heads/main", &[base], "main", 200);
+        commit(&repo, "refs/heads/release/1", &[base], "release", 300);
+        commit(&repo, "refs/remotes/origin/wip", &[main], "wip", 400);
+        repo.set_head("refs/heads/main").unwrap();
+
+        let mut filter = FilterOptions::default();
+        assert_eq!(subjects(&repo, &filter), vec!["main", "base"]);
+
+        filter.refs = RefSelection::parse_args(&["--all", "--exclude=refs/remotes/*"]).unwrap();
+        assert_eq!(subjects(&repo, &filter), vec!["release", "main", "base"]);
+
+        filter.refs = RefSelection::default();
+        filter.range = Some("main..release/1".to_string());
+        assert_eq!(subjects(&repo, &filter), vec!["release"]);
+
+        filter.range = Some("release/1...main".to_string());
+        assert_eq!(subjects(&repo, &filter), vec!["release", "main"]);
+
+        // Refs picked by name are walked even when a pattern excludes them.
+        filter.range = None;
+        filter.refs =
+            RefSelection::parse_args(&["origin/wip", "--exclude=refs/remotes/*"]).unwrap();
+        assert_eq!(subjects(&repo, &filter), vec!["wip", "main", "base"]);
+        filter.refs = RefSelection::parse_args(&["release/1"]).unwrap();
+        assert_eq!(subjects(&repo, &filter), vec!["release", "base"]);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_walk_filters() {
+        let (repo, dir) = repo("filter");
+        let a = commit(&repo, "refs/heads/main", &[], "a.txt", 100);
+        let b = commit(&repo, "refs/heads/main", &[a], "b.txt", 200);
+        commit(&repo, "refs/heads/main", &[b], "a.txt", 300);
+        repo.set_head("refs/heads/main").unwrap();
+
+        let filter = FilterOptions {
+            path: Some("b.txt".to_string()),
+            ..FilterOptions::default()
+        };
+        // Each commit replaces the whole tree, so the last one removes b.txt.
+        assert_eq!(subjects(&repo, &filter), vec!["a.txt", "b.txt"]);
+
+        let filter = FilterOptions {
+            since: DateTime::from_timestamp(150, 0),
+            max_commits: Some(1),
+            ..FilterOptions::default()
+        };
+        assert_eq!(subjects(&repo, &filter), vec!["a.txt"]);
+
+        let filter = FilterOptions {
+            author: Some("bob".to_string()),
+            ..FilterOptions::default()
+        };
+        assert!(subjects(&repo, &filter).is_empty());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn test_filtered_history_stays_connected() {
+        let (repo, dir) = repo("connected");
+        let mut parents = Vec::new();
+        for i in 0..20 {
+            let author = if i % 2 == 0 { "Alice" } else { "Bob" };
+            let id = commit_by(
+                &repo,
+                author,
+                "refs/heads/main",
+                &parents,
+                &format!("{}.txt", i),
+                i,
+            );
+            parents = vec![id];
+        }
+        repo.set_head("refs/heads/main").unwrap();
+
+        let filter = FilterOptions {
+            author: Some("alice".to_string()),
+            ..FilterOptions::default()
+        };
+        let mut commits = commits(&repo, &filter).unwrap();
+        assert_eq!(commits.len(), 10);
+        for pair in commits.windows(2) {
+            assert_eq!(pair[0].parents, [pair[1].hash.clone()]);
+        }
+        assert!(commits[9].parents.is_empty());
+
+        crate::graph::layout(&mut commits);
+        assert!(commits.iter().all(|commit| commit.graph.len() == 1));
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
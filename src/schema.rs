@@ -0,0 +1,548 @@
+//! JSON Schema for `config.yml` and checks of config files against it.
+
+use crate::colors::parse_color;
+use crate::config::{Colors, Config};
+use crate::glyphs::GlyphSet;
+use crate::keys::{Action, Keymap};
+use chrono::format::{Item, StrftimeItems};
+use serde_json::{json, Map, Value as Json};
+use serde_yaml::Value as Yaml;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// A problem found in a config file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// 1-based line the problem is on, when it can be pinned down.
+    pub line: Option<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Whether a config file can still be used despite an issue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The file doesn't parse or a value has the wrong type.
+    Error,
+    /// Something is left out, such as an unknown key; the rest applies.
+    Warning,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// The JSON Schema of the config file. Objects reject keys they don't
+/// declare and the `keys` section lists every action, so editors can
+/// complete and flag keys.
+pub fn schema() -> Json {
+    let mut schema =
+        serde_json::to_value(schemars::schema_for!(Config)).expect("schema serializes");
+    close_objects(&mut schema);
+
+    let actions: Map<String, Json> = Action::all()
+        .map(|action| {
+            let entry = json!({
+                "description": action.description(),
+                "$ref": "#/definitions/KeyList",
+            });
+            (action.name().to_string(), entry)
+        })
+        .chain(Action::aliases().map(|(name, action)| {
+            let entry = json!({
+                "description": format!("Former name of `{}`", action.name()),
+                "$ref": "#/definitions/KeyList",
+            });
+            (name.to_string(), entry)
+        }))
+        .collect();
+    if let Some(Json::Object(properties)) = schema.pointer_mut("/definitions/KeysConfig/properties")
+    {
+        properties.extend(actions);
+    }
+    if let Some(keys) = schema.pointer_mut("/definitions/KeysConfig") {
+        keys["additionalProperties"] = Json::Bool(false);
+    }
+    schema
+}
+
+/// The schema as pretty-printed JSON, as shipped in `docs/config.schema.json`.
+pub fn schema_json() -> String {
+    let mut text = serde_json::to_string_pretty(&schema()).expect("schema serializes");
+    text.push('\n');
+    text
+}
+
+fn close_objects(schema: &mut Json) {
+    match schema {
+        Json::Object(object) => {
+            if object.contains_key("properties") && !object.contains_key("additionalProperties") {
+                object.insert("additionalProperties".to_string(), Json::Bool(false));
+            }
+            object.values_mut().for_each(close_objects);
+        }
+        Json::Array(items) => items.iter_mut().for_each(close_objects),
+        _ => {}
+    }
+}
+
+/// Checks the text of a config file: syntax and type errors, unknown or
+/// misspelled keys, and values that parse but don't mean anything.
+pub fn check(text: &str) -> Vec<Issue> {
+    let mut issues = check_keys(text);
+    if let Ok(config) = serde_yaml::from_str::<Config>(text) {
+        issues.extend(check_values(&config));
+    }
+    issues
+}
+
+/// Checks one config file on its own: syntax and type errors and unknown
+/// keys. Values are only meaningful once every layer is merged.
+pub fn check_keys(text: &str) -> Vec<Issue> {
+    let value: Yaml = match serde_yaml::from_str(text) {
+        Ok(value) => value,
+        Err(err) => return vec![yaml_issue(&err)],
+    };
+    if value.is_null() {
+        return Vec::new();
+    }
+
+    let schema = schema();
+    let mut issues = Vec::new();
+    let mut path = Vec::new();
+    unknown_keys(&value, &schema, &schema, &mut path, text, &mut issues);
+
+    // Parsed from the text rather than the value so errors keep their line.
+    if let Err(err) = serde_yaml::from_str::<Config>(text) {
+        issues.push(yaml_issue(&err));
+    }
+    issues
+}
+
+/// Renames snake_case keys to the documented camelCase ones, so layers
+/// spelling a key differently merge into the same setting.
+pub fn normalize(value: &mut Yaml) {
+    let schema = schema();
+    normalize_keys(value, &schema, &schema);
+}
+
+fn normalize_keys(value: &mut Yaml, schema: &Json, root: &Json) {
+    let Yaml::Mapping(mapping) = value else {
+        return;
+    };
+    let schema = resolve(schema, root);
+    let properties = schema.get("properties").and_then(Json::as_object);
+    let additional = schema
+        .get("additionalProperties")
+        .filter(|child| child.is_object());
+
+    let entries: Vec<(Yaml, Yaml)> = std::mem::take(mapping).into_iter().collect();
+    for (key, mut child) in entries {
+        let mut key = key;
+        let mut child_schema = additional;
+        if let (Some(name), Some(properties)) = (key.as_str(), properties) {
+            let canonical = camel_case(name);
+            if let Some(found) = properties.get(name) {
+                child_schema = Some(found);
+            } else if let Some(found) = properties.get(&canonical) {
+                child_schema = Some(found);
+                key = Yaml::String(canonical);
+            }
+        }
+        if let Some(child_schema) = child_schema {
+            normalize_keys(&mut child, child_schema, root);
+        }
+        mapping.insert(key, child);
+    }
+}
+
+/// Finds the setting named by `words`, matched loosely (ignoring case and
+/// underscores) as environment variables and git config keys spell them,
+/// and converts `raw` to the setting's type. `["graph", "max", "width"]`
+/// names `graph.maxWidth`.
+pub fn setting(words: &[&str], raw: &str) -> Option<(Vec<String>, Yaml)> {
+    let root = schema();
+    let mut schema = &root;
+    let mut path = Vec::new();
+    let mut rest = words;
+    while !rest.is_empty() {
+        let current = resolve(schema, &root);
+        let properties = current.get("properties").and_then(Json::as_object);
+        let additional = current
+            .get("additionalProperties")
+            .filter(|child| child.is_object());
+        let found = (1..=rest.len()).find_map(|count| {
+            let wanted = loose(&rest[..count].concat());
+            properties?
+                .iter()
+                .find(|(name, _)| loose(name) == wanted)
+                .map(|(name, child)| (count, name.clone(), child))
+        });
+        match (found, additional) {
+            (Some((count, name, child)), _) => {
+                path.push(name);
+                schema = child;
+                rest = &rest[count..];
+            }
+            // Map entries such as theme names take one word.
+            (None, Some(child)) => {
+                path.push(rest[0].to_string());
+                schema = child;
+                rest = &rest[1..];
+            }
+            (None, None) => return None,
+        }
+    }
+
+    let leaf = resolve(schema, &root);
+    if leaf.get("properties").is_some() || path.is_empty() {
+        return None;
+    }
+    let textual = match leaf.get("type") {
+        Some(Json::String(kind)) => kind == "string",
+        Some(Json::Array(kinds)) => kinds.iter().any(|kind| kind == "string"),
+        _ => leaf.get("anyOf").is_some(),
+    };
+    let value = if textual {
+        Yaml::String(raw.to_string())
+    } else {
+        serde_yaml::from_str(raw).unwrap_or_else(|_| Yaml::String(raw.to_string()))
+    };
+    Some((path, value))
+}
+
+fn loose(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '_' && *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Reads and checks the config file at `path`.
+pub fn check_file(path: &Path) -> std::io::Result<Vec<Issue>> {
+    Ok(check(&fs::read_to_string(path)?))
+}
+
+fn yaml_issue(err: &serde_yaml::Error) -> Issue {
+    let mut message = err.to_string();
+    if let Some(location) = err.location() {
+        // The line is reported separately.
+        let suffix = format!(" at line {} column {}", location.line(), location.column());
+        if let Some(stripped) = message.strip_suffix(&suffix) {
+            message = stripped.to_string();
+        }
+    }
+    Issue {
+        line: err.location().map(|location| location.line()),
+        severity: Severity::Error,
+        message,
+    }
+}
+
+/// Follows `$ref`s and the `allOf`/`anyOf` wrappers schemars puts around
+/// them to the schema describing an object.
+fn resolve<'a>(mut schema: &'a Json, root: &'a Json) -> &'a Json {
+    for _ in 0..8 {
+        if let Some(reference) = schema.get("$ref").and_then(Json::as_str) {
+            match reference
+                .strip_prefix('#')
+                .and_then(|pointer| root.pointer(pointer))
+            {
+                Some(target) => schema = target,
+                None => break,
+            }
+        } else if let Some(Json::Array(options)) =
+            schema.get("allOf").or_else(|| schema.get("anyOf"))
+        {
+            match options.iter().find(|option| option.get("$ref").is_some()) {
+                Some(option) => schema = option,
+                None => break,
+            }
+        } else {
+            break;
+        }
+    }
+    schema
+}
+
+fn unknown_keys(
+    value: &Yaml,
+    schema: &Json,
+    root: &Json,
+    path: &mut Vec<String>,
+    text: &str,
+    issues: &mut Vec<Issue>,
+) {
+    let Yaml::Mapping(mapping) = value else {
+        return;
+    };
+    let schema = resolve(schema, root);
+    let properties = schema.get("properties").and_then(Json::as_object);
+    let additional = schema.get("additionalProperties");
+
+    for (key, value) in mapping {
+        let Some(key) = key.as_str() else {
+            continue;
+        };
+        let known = properties.and_then(|properties| {
+            properties
+                .get(key)
+                .or_else(|| properties.get(&camel_case(key)))
+        });
+        let child = match (known, additional) {
+            (Some(child), _) => child,
+            (None, Some(child)) if child.is_object() => child,
+            _ => {
+                let mut message = format!("unknown key '{}'", dotted(path, key));
+                let names = properties
+                    .into_iter()
+                    .flat_map(|properties| properties.keys());
+                if let Some(suggestion) = suggest(key, names) {
+                    message.push_str(&format!(" (did you mean '{}'?)", suggestion));
+                }
+                path.push(key.to_string());
+                issues.push(Issue {
+                    line: line_of(text, path),
+                    severity: Severity::Warning,
+                    message,
+                });
+                path.pop();
+                continue;
+            }
+        };
+        path.push(key.to_string());
+        unknown_keys(value, child, root, path, text, issues);
+        path.pop();
+    }
+}
+
+/// Checks values that parse but don't mean anything: unknown glyph sets,
+/// themes and colors, bad date formats and broken key bindings.
+pub fn check_values(config: &Config) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut issue = |message: String| {
+        issues.push(Issue {
+            line: None,
+            severity: Severity::Error,
+            message,
+        })
+    };
+
+    if let Some(Err(err)) = config.glyphs.as_deref().map(str::parse::<GlyphSet>) {
+        issue(err);
+    }
+    if !["auto", "light", "dark"].contains(&config.style.as_str())
+        && !config.themes.contains_key(&config.style)
+    {
+        issue(format!(
+            "style '{}' is not light, dark, auto or a theme under themes",
+            config.style
+        ));
+    }
+    let mut sections = vec![("colors".to_string(), &config.colors)];
+    sections.extend(
+        config
+            .themes
+            .iter()
+            .map(|(name, theme)| (format!("themes.{}", name), &theme.colors)),
+    );
+    for (section, colors) in sections {
+        for color in color_names(colors) {
+            if parse_color(color).is_none() {
+                issue(format!("unknown color '{}' in {}", color, section));
+            }
+        }
+    }
+    if let Err(err) = check_date_format(&config.date_format) {
+        issue(err);
+    }
+    if let Err(err) = config.git.ref_selection() {
+        issue(format!("{} in git.extraArgs", err));
+    }
+    if let Err(errors) = Keymap::from_config(&config.keys) {
+        errors.into_iter().for_each(issue);
+    }
+    issues
+}
+
+/// Fails on a `dateFormat` chrono can't format with, which would otherwise
+/// panic while the dates are drawn.
+pub fn check_date_format(format: &str) -> Result<(), String> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(format!(
+            "dateFormat '{}' is not a strftime format (e.g. \"%Y-%m-%d %H:%M\")",
+            format
+        ));
+    }
+    Ok(())
+}
+
+fn color_names(colors: &Colors) -> impl Iterator<Item = &String> {
+    [
+        &colors.graph1,
+        &colors.graph2,
+        &colors.head,
+        &colors.branch,
+        &colors.remote,
+        &colors.tag,
+        &colors.selection,
+        &colors.muted,
+        &colors.error,
+    ]
+    .into_iter()
+    .flatten()
+    .chain(&colors.lanes)
+}
+
+/// `date_format` -> `dateFormat`, the spelling older versions wrote.
+fn camel_case(key: &str) -> String {
+    let mut out = String::new();
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn dotted(path: &[String], key: &str) -> String {
+    path.iter()
+        .map(String::as_str)
+        .chain([key])
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// The known key closest to `key`, if it's close enough to be a typo.
+fn suggest<'a>(key: &str, names: impl Iterator<Item = &'a String>) -> Option<&'a String> {
+    let key = key.to_lowercase();
+    names
+        .map(|name| (distance(&key, &name.to_lowercase()), name))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+/// Levenshtein distance.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Line of the block-style mapping key at `path`, found by tracking
+/// indentation; good enough for hand-written config files.
+pub fn line_of(text: &str, path: &[String]) -> Option<usize> {
+    let mut stack: Vec<(usize, String)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('-') {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        let Some(colon) = trimmed.find(':') else {
+            continue;
+        };
+        let key = trimmed[..colon]
+            .trim()
+            .trim_matches(|c| c == '"' || c == '\'');
+
+        while stack.last().is_some_and(|(depth, _)| *depth >= indent) {
+            stack.pop();
+        }
+        stack.push((indent, key.to_string()));
+        if stack.len() == path.len() && stack.iter().zip(path).all(|((_, a), b)| a == b) {
+            return Some(number + 1);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_documented_example_is_valid() {
+        let text = include_str!("../docs/example-config.yml");
+        assert_eq!(check(text), Vec::new());
+
+        let config: Config = serde_yaml::from_str(text).unwrap();
+        assert_eq!(config.date_format, "%Y-%m-%d %H:%M");
+        assert!(config.graph.compress_lanes);
+        // Files written by older versions use snake_case.
+        let config: Config =
+            serde_yaml::from_str("date_format: \"%d\"\ngit:\n  extra_args: [--all]\n").unwrap();
+        assert_eq!(config.date_format, "%d");
+        assert_eq!(config.git.extra_args, vec!["--all"]);
+    }
+
+    #[test]
+    fn test_unknown_keys_have_lines_and_suggestions() {
+        let text = "style: dark\ngit:\n  defaultRange: main\n  extraArg: []\ncolours:\n  head: red\nkeys:\n  move_dwn: j\n";
+        let issues = check(text);
+        let messages: Vec<String> = issues.iter().map(|issue| issue.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "line 4: unknown key 'git.extraArg' (did you mean 'extraArgs'?)",
+                "line 5: unknown key 'colours' (did you mean 'colors'?)",
+                "line 8: unknown key 'keys.move_dwn' (did you mean 'move_down'?)",
+                "unknown action 'move_dwn' in keys",
+            ]
+        );
+
+        assert!(issues[..3]
+            .iter()
+            .all(|issue| issue.severity == Severity::Warning));
+
+        let issues = check("unicode: maybe\n");
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, Some(1));
+        assert_eq!(issues[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn test_bad_date_format() {
+        let messages: Vec<String> = check("dateFormat: \"%Y-%Q %H\"\n")
+            .iter()
+            .map(|issue| issue.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            ["dateFormat '%Y-%Q %H' is not a strftime format (e.g. \"%Y-%m-%d %H:%M\")"]
+        );
+        assert_eq!(check_date_format("%d %b"), Ok(()));
+        assert!(check_date_format("%").is_err());
+    }
+
+    #[test]
+    fn test_schema_file_is_current() {
+        assert_eq!(
+            include_str!("../docs/config.schema.json"),
+            schema_json(),
+            "regenerate with `gittree config schema > docs/config.schema.json`"
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use crate::config::Config;
-use crate::git::{Commit, FilterOptions, Repository};
-use std::io::{self, Write};
+use crate::git::{Applied, Commit, FilterOptions, Repository, ResetMode};
+use crate::graph;
+use std::io;
 
 pub struct SimpleApp {
     repo: Repository,
@@ -10,7 +11,8 @@ pub struct SimpleApp {
 }
 
 impl SimpleApp {
-    pub fn new(repo: Repository, config: Config, filter: FilterOptions, commits: Vec<Commit>) -> Self {
+    pub fn new(repo: Repository, config: Config, filter: FilterOptions, mut commits: Vec<Commit>) -> Self {
+        graph::layout(&mut commits);
         Self {
             repo,
             config,
@@ -25,11 +27,16 @@ impl SimpleApp {
             return Ok(());
         }
 
-        println!("Git Graph - {} commits found", self.commits.len());
+        let filters = self.filter.describe();
+        if filters.is_empty() {
+            println!("Git Graph - {} commits found", self.commits.len());
+        } else {
+            println!("Git Graph - {} commits found ({})", self.commits.len(), filters);
+        }
         println!("{}", "=".repeat(80));
 
-        for (i, commit) in self.commits.iter().enumerate() {
-            self.render_commit(commit, i);
+        for commit in &self.commits {
+            self.render_commit(commit);
         }
 
         println!("\nCommands:");
@@ -53,7 +60,7 @@ impl SimpleApp {
         Ok(())
     }
 
-    fn render_commit(&self, commit: &Commit, index: usize) {
+    fn render_commit(&self, commit: &Commit) {
         // Render graph
         let graph = self.render_graph_line(commit);
         
@@ -67,8 +74,9 @@ impl SimpleApp {
         );
         
         // Add refs
-        let refs = if !commit.refs.is_empty() {
-            format!(" ({})", commit.refs.join(", "))
+        let refs = if !commit.decorations.is_empty() {
+            let labels: Vec<String> = commit.decorations.iter().map(|d| d.to_string()).collect();
+            format!(" ({})", labels.join(", "))
         } else {
             String::new()
         };
@@ -81,19 +89,8 @@ impl SimpleApp {
             return "●".to_string();
         }
         
-        let mut chars = Vec::new();
-        for line in &commit.graph {
-            let char = match line.line_type {
-                crate::git::GraphLineType::Vertical => "│",
-                crate::git::GraphLineType::Horizontal => "─",
-                crate::git::GraphLineType::Corner => "└",
-                crate::git::GraphLineType::Merge => "●",
-                crate::git::GraphLineType::None => " ",
-            };
-            chars.push(char);
-        }
-        
-        chars.join("")
+        let glyphs = self.config.glyph_set();
+        commit.graph.iter().map(|cell| glyphs.glyph(cell)).collect()
     }
 
     fn handle_command(&self, input: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -187,8 +184,14 @@ impl SimpleApp {
         
         if input.trim().to_lowercase() == "y" {
             println!("Resetting to commit {}...", hash);
-            self.repo.reset_hard(hash)?;
+            let backup = self.repo.reset(hash, ResetMode::Hard)?;
             println!("Successfully reset to {}", hash);
+            if let Some(reference) = backup.reference {
+                println!("The previous HEAD is saved as {}", reference);
+            }
+            if backup.stashed {
+                println!("Uncommitted changes were stashed (git stash pop restores them)");
+            }
         } else {
             println!("Reset cancelled");
         }
@@ -197,8 +200,16 @@ impl SimpleApp {
 
     fn cherry_pick_commit(&self, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
         println!("Cherry-picking commit {}...", hash);
-        self.repo.cherry_pick(hash)?;
-        println!("Successfully cherry-picked {}", hash);
+        match self.repo.cherry_pick(hash, 0)? {
+            Applied::Committed(new) => println!("Successfully cherry-picked {} as {}", hash, &new[..7]),
+            Applied::Conflicts(conflicts) => {
+                println!("Cherry-pick stopped with conflicts:");
+                for conflict in conflicts {
+                    println!("  {}: {}", conflict.describe(), conflict.path);
+                }
+                println!("Resolve them and commit, or run git cherry-pick --abort");
+            }
+        }
         Ok(())
     }
 
@@ -218,7 +229,7 @@ impl SimpleApp {
         // Get current commit (first in the list)
         if let Some(commit) = self.commits.first() {
             println!("Creating tag '{}' at commit {}...", name, commit.short_hash);
-            self.repo.create_tag(name, &commit.hash)?;
+            self.repo.create_tag(name, &commit.hash, None)?;
             println!("Successfully created tag '{}'", name);
         } else {
             println!("No commits available");
@@ -0,0 +1,122 @@
+//! Tags for the tag list: the commit each points at and, for annotated
+//! tags, the tagger, message and kind of signature.
+
+use crate::revwalk::time_of;
+use chrono::{DateTime, Utc};
+use git2::Repository;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    /// Name without `refs/tags/`.
+    pub name: String,
+    /// Commit the tag resolves to.
+    pub target: String,
+    /// Tagging date of an annotated tag, otherwise the commit's date.
+    pub date: DateTime<Utc>,
+    /// `None` for lightweight tags.
+    pub annotation: Option<Annotation>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub tagger: String,
+    /// The message without its signature block.
+    pub message: String,
+    pub signature: Option<SignatureKind>,
+}
+
+/// The kind of signature appended to a tag message. gittree only detects
+/// it; `git verify-tag` checks it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureKind {
+    Pgp,
+    Ssh,
+    X509,
+}
+
+impl SignatureKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            SignatureKind::Pgp => "PGP",
+            SignatureKind::Ssh => "SSH",
+            SignatureKind::X509 => "X.509",
+        }
+    }
+}
+
+/// Headers that start a signature block, as git writes them.
+const SIGNATURES: &[(&str, SignatureKind)] = &[
+    ("-----BEGIN PGP SIGNATURE-----", SignatureKind::Pgp),
+    ("-----BEGIN SSH SIGNATURE-----", SignatureKind::Ssh),
+    ("-----BEGIN SIGNED MESSAGE-----", SignatureKind::X509),
+];
+
+/// Splits a tag message into the message and the kind of the signature
+/// appended to it, if any.
+pub fn split_signature(message: &str) -> (&str, Option<SignatureKind>) {
+    for (header, kind) in SIGNATURES {
+        if let Some(start) = message.find(header) {
+            if start == 0 || message[..start].ends_with('\n') {
+                return (message[..start].trim_end(), Some(*kind));
+            }
+        }
+    }
+    (message.trim_end(), None)
+}
+
+/// Every tag that resolves to a commit, newest first.
+pub fn load(repo: &Repository) -> Result<Vec<Tag>, git2::Error> {
+    let mut tags = Vec::new();
+    for reference in repo.references_glob("refs/tags/*")? {
+        let reference = reference?;
+        let (Some(name), Ok(commit)) = (reference.shorthand(), reference.peel_to_commit()) else {
+            continue;
+        };
+        let mut tag = Tag {
+            name: name.to_string(),
+            target: commit.id().to_string(),
+            date: time_of(&commit.time()),
+            annotation: None,
+        };
+        if let Ok(object) = reference.peel_to_tag() {
+            let (message, signature) = split_signature(object.message().unwrap_or(""));
+            let tagger = object.tagger();
+            if let Some(tagger) = &tagger {
+                tag.date = time_of(&tagger.when());
+            }
+            tag.annotation = Some(Annotation {
+                tagger: tagger
+                    .as_ref()
+                    .and_then(|tagger| tagger.name())
+                    .unwrap_or("")
+                    .to_string(),
+                message: message.to_string(),
+                signature,
+            });
+        }
+        tags.push(tag);
+    }
+    tags.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.name.cmp(&b.name)));
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_signature() {
+        let signed = "Release 1.0\n\nNotes\n-----BEGIN PGP SIGNATURE-----\n\nabc\n-----END PGP SIGNATURE-----\n";
+        assert_eq!(
+            split_signature(signed),
+            ("Release 1.0\n\nNotes", Some(SignatureKind::Pgp))
+        );
+        let ssh = "v2\n-----BEGIN SSH SIGNATURE-----\nabc\n";
+        assert_eq!(split_signature(ssh), ("v2", Some(SignatureKind::Ssh)));
+        let quoted = "mentions -----BEGIN PGP SIGNATURE----- inline\n";
+        assert_eq!(
+            split_signature(quoted),
+            ("mentions -----BEGIN PGP SIGNATURE----- inline", None)
+        );
+    }
+}
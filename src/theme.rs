@@ -0,0 +1,388 @@
+//! Built-in and user-defined color themes, terminal background detection and
+//! downgrading of colors to what the terminal can display.
+
+use crate::colors::parse_color;
+use crate::config::{Colors, Config};
+use ratatui::style::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    Light,
+    Dark,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColorDepth {
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+/// Fully resolved colors for the TUI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub lanes: Vec<Color>,
+    pub head: Color,
+    pub branch: Color,
+    pub remote: Color,
+    pub tag: Color,
+    pub selection: Color,
+    pub muted: Color,
+    pub error: Color,
+}
+
+impl Theme {
+    /// Resolves the theme named by `config.style` for the given terminal,
+    /// then applies `config.colors` on top.
+    pub fn resolve(config: &Config, background: Background, depth: ColorDepth) -> Self {
+        let mut colors = builtin(background);
+        match config.style.as_str() {
+            "light" => colors = builtin(Background::Light),
+            "dark" => colors = builtin(Background::Dark),
+            "auto" | "" => {}
+            name => {
+                if let Some(custom) = config.themes.get(name) {
+                    colors = match custom.base.as_deref() {
+                        Some("light") => builtin(Background::Light),
+                        Some("dark") => builtin(Background::Dark),
+                        _ => colors,
+                    };
+                    overlay(&mut colors, &custom.colors);
+                }
+            }
+        }
+        overlay(&mut colors, &config.colors);
+
+        let color = |name: &Option<String>| {
+            let color = name.as_deref().and_then(parse_color).unwrap_or(Color::Reset);
+            downgrade(color, depth)
+        };
+        let mut lanes: Vec<Color> = [&colors.graph1, &colors.graph2]
+            .into_iter()
+            .flatten()
+            .chain(colors.lanes.iter())
+            .filter_map(|name| parse_color(name))
+            .map(|c| downgrade(c, depth))
+            .collect();
+        if lanes.is_empty() {
+            lanes.push(Color::Reset);
+        }
+
+        Self {
+            lanes,
+            head: color(&colors.head),
+            branch: color(&colors.branch),
+            remote: color(&colors.remote),
+            tag: color(&colors.tag),
+            selection: color(&colors.selection),
+            muted: color(&colors.muted),
+            error: color(&colors.error),
+        }
+    }
+
+    /// Resolves the theme for the current terminal, querying its background
+    /// only when the chosen theme does not pin one.
+    pub fn load(config: &Config) -> Self {
+        let detect = match config.style.as_str() {
+            "light" | "dark" => false,
+            name => config.themes.get(name).is_none_or(|theme| theme.base.is_none()),
+        };
+        let background = if detect {
+            detect_background()
+        } else {
+            Background::Dark
+        };
+        Self::resolve(config, background, detect_color_depth())
+    }
+}
+
+fn builtin(background: Background) -> Colors {
+    let s = |value: &str| Some(value.to_string());
+    match background {
+        Background::Dark => Colors {
+            graph1: s("#58a6ff"),
+            graph2: s("#d2a8ff"),
+            lanes: vec!["#3fb950".to_string(), "#d29922".to_string(), "#f85149".to_string()],
+            head: s("#39c5cf"),
+            branch: s("#3fb950"),
+            remote: s("#f85149"),
+            tag: s("#d29922"),
+            selection: s("#30363d"),
+            muted: s("#8b949e"),
+            error: s("#f85149"),
+        },
+        Background::Light => Colors {
+            graph1: s("#0969da"),
+            graph2: s("#8250df"),
+            lanes: vec!["#1a7f37".to_string(), "#9a6700".to_string(), "#cf222e".to_string()],
+            head: s("#1b7c83"),
+            branch: s("#1a7f37"),
+            remote: s("#cf222e"),
+            tag: s("#9a6700"),
+            selection: s("#d0d7de"),
+            muted: s("#57606a"),
+            error: s("#cf222e"),
+        },
+    }
+}
+
+/// Copies every color set in `overrides` onto `colors`.
+fn overlay(colors: &mut Colors, overrides: &Colors) {
+    let fields = [
+        (&mut colors.graph1, &overrides.graph1),
+        (&mut colors.graph2, &overrides.graph2),
+        (&mut colors.head, &overrides.head),
+        (&mut colors.branch, &overrides.branch),
+        (&mut colors.remote, &overrides.remote),
+        (&mut colors.tag, &overrides.tag),
+        (&mut colors.selection, &overrides.selection),
+        (&mut colors.muted, &overrides.muted),
+        (&mut colors.error, &overrides.error),
+    ];
+    for (field, value) in fields {
+        if value.is_some() {
+            *field = value.clone();
+        }
+    }
+    if !overrides.lanes.is_empty() {
+        colors.lanes = overrides.lanes.clone();
+    }
+}
+
+/// Asks the terminal for its background color (OSC 11), falling back to
+/// `COLORFGBG` and finally to a dark background.
+pub fn detect_background() -> Background {
+    query_background()
+        .or_else(|| std::env::var("COLORFGBG").ok().and_then(|v| parse_colorfgbg(&v)))
+        .unwrap_or(Background::Dark)
+}
+
+/// Color depth advertised by `COLORTERM` and `TERM`.
+pub fn detect_color_depth() -> ColorDepth {
+    let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+    let term = std::env::var("TERM").unwrap_or_default();
+    color_depth(&colorterm, &term)
+}
+
+fn color_depth(colorterm: &str, term: &str) -> ColorDepth {
+    if colorterm == "truecolor" || colorterm == "24bit" || term.ends_with("-direct") {
+        ColorDepth::TrueColor
+    } else if term.contains("256color") {
+        ColorDepth::Ansi256
+    } else {
+        ColorDepth::Ansi16
+    }
+}
+
+/// `COLORFGBG` is `fg;bg` (sometimes `fg;default;bg`) in ANSI color numbers.
+fn parse_colorfgbg(value: &str) -> Option<Background> {
+    let bg: u8 = value.rsplit(';').next()?.parse().ok()?;
+    Some(match bg {
+        7 | 9..=15 => Background::Light,
+        _ => Background::Dark,
+    })
+}
+
+/// Parses an OSC 11 reply such as `\x1b]11;rgb:ffff/ffff/ffff\x07`.
+fn parse_osc11(reply: &str) -> Option<Background> {
+    let rgb = reply.split("rgb:").nth(1)?;
+    let rgb = rgb.trim_end_matches(['\x07', '\\', '\x1b']);
+    let channels: Vec<f64> = rgb
+        .split('/')
+        .map(|hex| {
+            let hex = &hex[..hex.len().min(4)];
+            let max = (16f64).powi(hex.len() as i32) - 1.0;
+            u32::from_str_radix(hex, 16).map(|v| v as f64 / max)
+        })
+        .collect::<Result<_, _>>()
+        .ok()?;
+    if channels.len() != 3 {
+        return None;
+    }
+
+    let luma = 0.2126 * channels[0] + 0.7152 * channels[1] + 0.0722 * channels[2];
+    Some(if luma > 0.5 {
+        Background::Light
+    } else {
+        Background::Dark
+    })
+}
+
+#[cfg(unix)]
+fn query_background() -> Option<Background> {
+    use crossterm::terminal::{disable_raw_mode, enable_raw_mode, is_raw_mode_enabled};
+    use std::fs::OpenOptions;
+    use std::io::{Read, Write};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::time::{Duration, Instant};
+
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open("/dev/tty")
+        .ok()?;
+
+    let was_raw = is_raw_mode_enabled().unwrap_or(false);
+    if !was_raw {
+        enable_raw_mode().ok()?;
+    }
+
+    let mut reply = Vec::new();
+    if tty.write_all(b"\x1b]11;?\x07").and_then(|_| tty.flush()).is_ok() {
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let mut buf = [0u8; 64];
+        while Instant::now() < deadline {
+            match tty.read(&mut buf) {
+                Ok(n) if n > 0 => {
+                    reply.extend_from_slice(&buf[..n]);
+                    if reply.ends_with(b"\x07") || reply.ends_with(b"\x1b\\") {
+                        break;
+                    }
+                }
+                _ => std::thread::sleep(Duration::from_millis(5)),
+            }
+        }
+    }
+
+    if !was_raw {
+        let _ = disable_raw_mode();
+    }
+    parse_osc11(&String::from_utf8_lossy(&reply))
+}
+
+#[cfg(not(unix))]
+fn query_background() -> Option<Background> {
+    None
+}
+
+/// The 16 ANSI colors with their usual xterm RGB values.
+const ANSI: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Maps `color` onto the closest color the terminal can show.
+pub fn downgrade(color: Color, depth: ColorDepth) -> Color {
+    match (color, depth) {
+        (_, ColorDepth::TrueColor) => color,
+        (Color::Rgb(r, g, b), ColorDepth::Ansi256) => Color::Indexed(to_256(r, g, b)),
+        (Color::Rgb(r, g, b), ColorDepth::Ansi16) => nearest_ansi((r, g, b)),
+        (Color::Indexed(i), ColorDepth::Ansi16) if i >= 16 => nearest_ansi(indexed_rgb(i)),
+        (Color::Indexed(i), ColorDepth::Ansi16) => ANSI[i as usize].0,
+        _ => color,
+    }
+}
+
+fn to_256(r: u8, g: u8, b: u8) -> u8 {
+    let level = |v: u8| {
+        (0..CUBE.len())
+            .min_by_key(|&i| (CUBE[i] as i32 - v as i32).abs())
+            .unwrap_or(0) as u8
+    };
+    let cube = 16 + 36 * level(r) + 6 * level(g) + level(b);
+    let cube_rgb = indexed_rgb(cube);
+
+    // Greys are often closer on the 24-step ramp than in the cube.
+    let avg = (r as u16 + g as u16 + b as u16) / 3;
+    let gray = 232 + ((avg.saturating_sub(8)) / 10).min(23) as u8;
+    if distance((r, g, b), indexed_rgb(gray)) < distance((r, g, b), cube_rgb) {
+        gray
+    } else {
+        cube
+    }
+}
+
+fn indexed_rgb(i: u8) -> (u8, u8, u8) {
+    match i {
+        0..=15 => ANSI[i as usize].1,
+        16..=231 => {
+            let i = i - 16;
+            (CUBE[(i / 36) as usize], CUBE[(i / 6 % 6) as usize], CUBE[(i % 6) as usize])
+        }
+        _ => {
+            let v = 8 + 10 * (i - 232);
+            (v, v, v)
+        }
+    }
+}
+
+fn nearest_ansi(rgb: (u8, u8, u8)) -> Color {
+    ANSI.iter()
+        .min_by_key(|(_, ansi)| distance(rgb, *ansi))
+        .map_or(Color::Reset, |(color, _)| *color)
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ThemeConfig;
+
+    #[test]
+    fn test_background_detection_parsing() {
+        assert_eq!(parse_osc11("\x1b]11;rgb:ffff/ffff/ffff\x07"), Some(Background::Light));
+        assert_eq!(parse_osc11("\x1b]11;rgb:1e1e/1e1e/2e2e\x1b\\"), Some(Background::Dark));
+        assert_eq!(parse_osc11(""), None);
+        assert_eq!(parse_colorfgbg("0;15"), Some(Background::Light));
+        assert_eq!(parse_colorfgbg("15;default;0"), Some(Background::Dark));
+        assert_eq!(color_depth("truecolor", "xterm"), ColorDepth::TrueColor);
+        assert_eq!(color_depth("", "screen-256color"), ColorDepth::Ansi256);
+        assert_eq!(color_depth("", "xterm"), ColorDepth::Ansi16);
+    }
+
+    #[test]
+    fn test_downgrade() {
+        let red = Color::Rgb(255, 0, 0);
+        assert_eq!(downgrade(red, ColorDepth::TrueColor), red);
+        assert_eq!(downgrade(red, ColorDepth::Ansi256), Color::Indexed(196));
+        assert_eq!(downgrade(red, ColorDepth::Ansi16), Color::LightRed);
+        assert_eq!(downgrade(Color::Rgb(128, 128, 128), ColorDepth::Ansi256), Color::Indexed(244));
+        assert_eq!(downgrade(Color::Indexed(21), ColorDepth::Ansi16), Color::Blue);
+        assert_eq!(downgrade(Color::Blue, ColorDepth::Ansi16), Color::Blue);
+    }
+
+    #[test]
+    fn test_custom_theme_and_overrides() {
+        let mut config = Config {
+            style: "solar".to_string(),
+            ..Config::default()
+        };
+        config.themes.insert(
+            "solar".to_string(),
+            ThemeConfig {
+                base: Some("light".to_string()),
+                colors: Colors {
+                    head: Some("magenta".to_string()),
+                    ..Colors::default()
+                },
+            },
+        );
+        config.colors.tag = Some("blue".to_string());
+
+        let theme = Theme::resolve(&config, Background::Dark, ColorDepth::TrueColor);
+        assert_eq!(theme.head, Color::Magenta);
+        assert_eq!(theme.tag, Color::Blue);
+        assert_eq!(theme.lanes[0], Color::Rgb(0x09, 0x69, 0xda));
+    }
+}
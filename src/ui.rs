@@ -1,45 +1,329 @@
+use crate::colors::{Palette, RefKind};
 use crate::config::Config;
-use crate::git::{Commit, FilterOptions, Repository};
+use crate::editor;
+use crate::git::{
+    Applied, Commit, Conflict, FileChange, FilterOptions, MergeMode, MergePreview, Repository, ResetMode, ResetPreview,
+    StatusCounts,
+};
+use crate::glyphs::GlyphSet;
+use crate::graph::{self, Cell, NodeKind, Segment};
+use crate::keys::{Action, KeyChord, Keymap, Lookup};
+use crate::rebase::{Command, Plan};
+use crate::refs::{self, Decoration, Group, Ref, Sort, Tracking};
+use crate::revwalk::RefSelection;
+use crate::tags::Tag;
+use crate::theme::Theme;
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEventKind},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::Backend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph},
     Frame, Terminal,
 };
-use std::time::Duration;
+use std::collections::HashSet;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// The slice of the lane area drawn for the current viewport.
+struct LaneWindow {
+    /// Lanes kept after compression, in display order.
+    lanes: Vec<usize>,
+    /// First visible column of the compressed lane area.
+    start: usize,
+    /// Number of columns shown.
+    width: usize,
+    /// Whether lanes are cut off on either side.
+    clipped: bool,
+}
+
+/// The ref picker popup: every ref, checked when the walk starts from it.
+struct RefPicker {
+    refs: Vec<(String, bool)>,
+    selected: usize,
+}
+
+/// The ref sidebar: every ref grouped by kind. Keys go to it while it has
+/// focus; jumping to a ref hands focus back to the graph.
+struct Sidebar {
+    refs: Vec<Ref>,
+    selected: usize,
+    sort: Sort,
+    focused: bool,
+    /// A new name or upstream for the selected branch while it is typed.
+    prompt: Option<(Prompt, String)>,
+}
+
+/// A new tag being named, then given a message; an empty message makes a
+/// lightweight tag.
+struct TagPrompt {
+    commit: Commit,
+    name: String,
+    message: Option<String>,
+}
+
+/// The tag list: every tag with its tagger, date and message.
+struct TagList {
+    tags: Vec<Tag>,
+    selected: usize,
+}
+
+/// What the text typed in the sidebar is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prompt {
+    Rename,
+    Upstream,
+    /// Asks before `D` deletes an unmerged branch; the text is its name.
+    ForceDelete,
+}
+
+/// The working-tree pane: the changed files, the diff of the one under the
+/// cursor and the commit message while it is being typed.
+struct WorkTreePane {
+    files: Vec<FileChange>,
+    selected: usize,
+    diff: Vec<String>,
+    scroll: usize,
+    message: Option<String>,
+}
+
+/// The conflicts of a cherry-pick or revert that stopped: the conflicted
+/// files and the working copy of the one under the cursor, markers and all.
+struct ConflictPane {
+    conflicts: Vec<Conflict>,
+    selected: usize,
+    /// The file `lines` were read from.
+    shown: Option<String>,
+    lines: Vec<String>,
+    scroll: usize,
+}
+
+/// The reset menu: the commit to reset to, what resetting would lose, and
+/// the mode under the cursor.
+struct ResetMenu {
+    commit: Commit,
+    preview: ResetPreview,
+    mode: ResetMode,
+}
+
+/// The merge menu: the commit to merge or rebase onto, the conflicts each
+/// mode would run into, and the mode under the cursor.
+struct MergeMenu {
+    commit: Commit,
+    preview: MergePreview,
+    mode: MergeMode,
+}
+
+/// What a checkout switches to.
+#[derive(Clone)]
+enum Checkout {
+    /// A local branch by name.
+    Branch(String),
+    /// A new local branch tracking this remote one, e.g. `origin/topic`.
+    Track(String),
+    /// A commit, detaching HEAD.
+    Detach(String),
+}
+
+/// A checkout waiting on an answer in the status bar.
+enum CheckoutPrompt {
+    /// The commit has the remote branch `upstream` but no local one: `y`
+    /// creates `name` tracking it, `n` detaches HEAD.
+    Track { hash: String, upstream: String, name: String },
+    /// The checkout would overwrite local changes to `blockers`: `y`
+    /// stashes them first.
+    Stash { hash: String, checkout: Checkout, blockers: Vec<String> },
+}
+
+/// Commits being cherry-picked or reverted one per frame, in the order
+/// they are applied, so the status bar can show progress. A conflict pauses
+/// it until the conflict pane continues or skips that commit.
+struct Sequence {
+    action: Action,
+    commits: Vec<Commit>,
+    /// The parent merge commits are applied relative to.
+    mainline: u32,
+    done: usize,
+    skipped: usize,
+    paused: bool,
+}
+
+impl Sequence {
+    /// The verb for one commit and for the finished sequence.
+    fn verbs(&self) -> (&'static str, &'static str) {
+        if self.action == Action::Revert {
+            ("Revert", "Reverted")
+        } else {
+            ("Cherry-pick", "Cherry-picked")
+        }
+    }
+}
+
+/// The rebase planner: the todo list being edited, the history it would
+/// produce, and the new message while a commit is being reworded.
+struct Planner {
+    plan: Plan,
+    selected: usize,
+    preview: Vec<Commit>,
+    input: Option<String>,
+    /// The `fixup!` commit made for the plan, taken back when the rebase
+    /// is cancelled or fails to start.
+    fixup: Option<String>,
+}
+
+/// A message about the last operation, shown in the status bar for
+/// `MESSAGE_TIMEOUT`.
+struct Message {
+    text: String,
+    error: bool,
+    shown: Instant,
+}
+
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Width of the ref sidebar, borders included.
+const SIDEBAR_WIDTH: u16 = 40;
+
+/// The actions the reset and merge menus take before their mode letters.
+const MENU_ACTIONS: [Action; 4] = [Action::MoveUp, Action::MoveDown, Action::Open, Action::Quit];
+
+/// What a key means in a pane that has letter commands of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PaneKey {
+    /// A keymap action the pane handles; it wins over the pane's letters.
+    Action(Action),
+    /// One of the pane's letters, typed without ctrl or alt.
+    Letter(char),
+    Other,
+}
+
+/// A line of the graph view: either a commit or a folded branch segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Row {
+    Commit(usize),
+    Fold(usize),
+}
 
 pub struct App<'a> {
     repo: &'a Repository,
     config: Config,
     filter: FilterOptions,
     commits: Vec<Commit>,
+    head: Option<String>,
+    current_branch: Option<String>,
+    /// Upstream tracking of the local branches.
+    tracking: Vec<Tracking>,
+    status: StatusCounts,
+    /// The rebase, merge, ... in progress.
+    operation: Option<&'static str>,
+    message: Option<Message>,
+    /// A dangerous action waiting for `y` in the status bar.
+    confirm: Option<(Action, Commit)>,
+    checkout_prompt: Option<CheckoutPrompt>,
+    /// A cherry-pick or revert of a merge waiting for the parent number.
+    mainline: Option<(Action, Commit)>,
+    sequence: Option<Sequence>,
+    /// Hashes of the commits marked for cherry-pick or revert.
+    marked: HashSet<String>,
+    /// Where the range selection started, while there is one.
+    anchor: Option<String>,
+    palette: Palette,
+    segments: Vec<Segment>,
+    folded: HashSet<usize>,
+    rows: Vec<Row>,
     selected: usize,
     offset: usize,
     height: usize,
     width: usize,
-    unicode: bool,
+    pan: usize,
+    panned_for: Option<usize>,
+    compress: bool,
+    glyphs: GlyphSet,
+    keymap: Keymap,
+    /// Keys typed so far towards a multi-key binding.
+    pending: Vec<KeyChord>,
+    /// Text the rows are filtered by.
+    query: String,
+    /// The filter prompt while it is being edited.
+    input: Option<String>,
+    picker: Option<RefPicker>,
+    tag_prompt: Option<TagPrompt>,
+    tag_list: Option<TagList>,
+    reset_menu: Option<ResetMenu>,
+    merge_menu: Option<MergeMenu>,
+    sidebar: Option<Sidebar>,
+    work_tree: Option<WorkTreePane>,
+    conflicts: Option<ConflictPane>,
+    planner: Option<Planner>,
+    /// A commit to reword in the user's editor once the run loop has
+    /// handed it the terminal.
+    editing: Option<Commit>,
     show_help: bool,
     should_quit: bool,
 }
 
 impl<'a> App<'a> {
-    pub fn new(repo: &'a Repository, config: Config, filter: FilterOptions, commits: Vec<Commit>) -> Self {
-        let unicode = config.unicode;
+    pub fn new(repo: &'a Repository, config: Config, filter: FilterOptions, mut commits: Vec<Commit>) -> Self {
+        let glyphs = config.glyph_set();
+        let compress = config.graph.compress_lanes;
+        let keymap = Keymap::from_config(&config.keys).unwrap_or_default();
+        let palette = Palette::new(Theme::load(&config), !config.no_color);
+        let head = repo.head_hash().ok();
+        let status = repo.status_counts().unwrap_or_default();
+        add_work_tree(&mut commits, head.as_deref(), &status);
+        let segments = graph::layout(&mut commits);
+        if let Some(head) = &head {
+            graph::mark_head(&mut commits, head);
+        }
+        let rows = (0..commits.len()).map(Row::Commit).collect();
         Self {
             repo,
             config,
             filter,
             commits,
+            head,
+            current_branch: repo.get_current_branch().ok(),
+            tracking: repo.tracking().unwrap_or_default(),
+            status,
+            operation: repo.operation(),
+            message: None,
+            confirm: None,
+            checkout_prompt: None,
+            mainline: None,
+            sequence: None,
+            marked: HashSet::new(),
+            anchor: None,
+            palette,
+            segments,
+            folded: HashSet::new(),
+            rows,
             selected: 0,
             offset: 0,
             height: 0,
             width: 0,
-            unicode,
+            pan: 0,
+            panned_for: None,
+            compress,
+            glyphs,
+            keymap,
+            pending: Vec::new(),
+            query: String::new(),
+            input: None,
+            picker: None,
+            tag_prompt: None,
+            tag_list: None,
+            reset_menu: None,
+            merge_menu: None,
+            sidebar: None,
+            work_tree: None,
+            conflicts: None,
+            planner: None,
+            editing: None,
             show_help: false,
             should_quit: false,
         }
@@ -49,14 +333,22 @@ impl<'a> App<'a> {
         loop {
             terminal.draw(|f| self.ui(f))?;
 
-            if crossterm::event::poll(Duration::from_millis(100))? {
+            let running = self.sequence.as_ref().is_some_and(|sequence| !sequence.paused);
+            let timeout = if running { Duration::ZERO } else { Duration::from_millis(100) };
+            if crossterm::event::poll(timeout)? {
                 if let Event::Key(key) = event::read()? {
                     if key.kind == KeyEventKind::Press {
-                        self.handle_key_press(key.code)?;
+                        self.handle_key_press(key)?;
                     }
                 }
             }
+            if self.editing.is_some() {
+                self.run_editor(terminal)?;
+            }
 
+            if running {
+                self.advance()?;
+            }
             if self.should_quit {
                 break;
             }
@@ -65,7 +357,7 @@ impl<'a> App<'a> {
         Ok(())
     }
 
-    fn ui(&self, f: &mut Frame) {
+    fn ui(&mut self, f: &mut Frame) {
         if self.show_help {
             self.render_help(f);
             return;
@@ -75,265 +367,3158 @@ impl<'a> App<'a> {
             self.render_empty(f);
             return;
         }
+        if self.planner.is_some() {
+            self.render_planner(f);
+            return;
+        }
 
-        self.render_graph(f);
+        let mut area = f.size();
+        if self.sidebar.is_some() && area.width > SIDEBAR_WIDTH * 2 {
+            let chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Length(SIDEBAR_WIDTH), Constraint::Min(0)])
+                .split(area);
+            self.render_sidebar(f, chunks[0]);
+            area = chunks[1];
+        }
+        if self.conflicts.is_some() || self.work_tree.is_some() {
+            let chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(area);
+            if self.conflicts.is_some() {
+                self.render_conflicts(f, chunks[1]);
+            } else {
+                self.render_work_tree(f, chunks[1]);
+            }
+            area = chunks[0];
+        }
+        self.render_graph(f, area);
+        if self.picker.is_some() {
+            self.render_picker(f);
+        }
+        if self.tag_list.is_some() {
+            self.render_tag_list(f);
+        }
+        if self.reset_menu.is_some() {
+            self.render_reset_menu(f);
+        }
+        if self.merge_menu.is_some() {
+            self.render_merge_menu(f);
+        }
     }
 
-    fn render_graph(&self, f: &mut Frame) {
+    fn render_graph(&mut self, f: &mut Frame, area: Rect) {
+        let prompt = self.input.is_some() || self.tag_prompt.is_some() || !self.query.is_empty();
         let chunks = Layout::default()
             .direction(Direction::Vertical)
-            .constraints([Constraint::Min(0)])
-            .split(f.size());
+            .constraints([
+                Constraint::Min(0),
+                Constraint::Length(prompt as u16),
+                Constraint::Length(1),
+            ])
+            .split(area);
+
+        // Keep the selection inside the viewport.
+        self.height = chunks[0].height.saturating_sub(2) as usize;
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.height > 0 && self.selected >= self.offset + self.height {
+            self.offset = self.selected + 1 - self.height;
+        }
+
+        self.width = chunks[0].width.saturating_sub(2) as usize;
+        let window = self.lane_window();
+        let chosen: HashSet<usize> = self.chosen().into_iter().collect();
 
         let items: Vec<ListItem> = self
-            .commits
+            .rows
             .iter()
             .enumerate()
-            .map(|(i, commit)| {
+            .skip(self.offset)
+            .take(self.height)
+            .map(|(i, row)| {
                 let is_selected = i == self.selected;
                 let style = if is_selected {
-                    Style::default().add_modifier(Modifier::REVERSED)
+                    self.palette.selection()
                 } else {
                     Style::default()
                 };
 
-                let graph = self.render_graph_line(commit);
-                let info = format!(
-                    "{} {} {} {}",
+                let mut spans = self.render_graph_line(self.row_cells(*row), &window);
+                spans.push(Span::raw(" "));
+                match *row {
+                    Row::Commit(index) if self.commits[index].is_work_tree() => {
+                        let message = self.commits[index].message.clone();
+                        spans.push(Span::styled(message, Style::default().add_modifier(Modifier::ITALIC)));
+                    }
+                    Row::Commit(index) => {
+                        let commit = &self.commits[index];
+                        let is_head = self.head.as_deref() == Some(commit.hash.as_str());
+                        if chosen.contains(&index) {
+                            spans.push(Span::styled(
+                                format!("{} ", commit.short_hash),
+                                self.palette.head().add_modifier(Modifier::REVERSED),
+                            ));
+                        } else {
+                            spans.push(Span::raw(format!("{} ", commit.short_hash)));
+                        }
+                        let info = format!(
+                            "{} {} {}",
+                            commit.author,
+                            commit.date.format(&self.config.date_format),
+                            commit.message
+                        );
+                        spans.push(if is_head {
+                            Span::styled(info, self.palette.head())
+                        } else {
+                            Span::raw(info)
+                        });
+                        spans.extend(self.render_refs(&commit.decorations));
+                        spans.extend(self.render_forks(&commit.hash));
+                    }
+                    Row::Fold(segment) => {
+                        let summary = graph::segment_summary(&self.commits, &self.segments, segment);
+                        spans.push(Span::styled(summary, self.palette.muted().add_modifier(Modifier::ITALIC)));
+                    }
+                }
+                ListItem::new(Line::from(spans)).style(style)
+            })
+            .collect();
+
+        let mut title = "Git Graph".to_string();
+        if window.clipped {
+            let first = window.start / 2 + 1;
+            let last = ((window.start + window.width).div_ceil(2)).min(window.lanes.len());
+            title.push_str(&format!(" [lanes {}-{} of {}]", first, last, window.lanes.len()));
+        }
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title));
+
+        f.render_widget(list, chunks[0]);
+
+        if prompt {
+            let text = match (&self.tag_prompt, &self.input) {
+                (Some(TagPrompt { name, message: None, .. }), _) => format!("tag name: {}_", name),
+                (Some(TagPrompt { name, message: Some(message), .. }), _) => {
+                    format!("message for {} (empty: lightweight): {}_", name, message)
+                }
+                (None, Some(input)) => format!("/{}", input),
+                (None, None) => format!("filter: {} ({} to change)", self.query, self.keys_label(Action::Filter)),
+            };
+            f.render_widget(Paragraph::new(text), chunks[1]);
+        }
+        f.render_widget(Paragraph::new(self.status_line(chunks[2].width)), chunks[2]);
+    }
+
+    /// The status bar: a pending confirmation or the last operation's
+    /// message, otherwise the repository state and active filters; the
+    /// position of the selected commit sits on the right.
+    fn status_line(&self, width: u16) -> Line<'static> {
+        let mut spans = Vec::new();
+        let message = self
+            .message
+            .as_ref()
+            .filter(|message| message.shown.elapsed() < MESSAGE_TIMEOUT);
+        if let Some((action, commit)) = &self.confirm {
+            let verb = match action {
+                Action::Checkout => "Checkout",
+                _ => action.description(),
+            };
+            spans.push(Span::styled(
+                format!("{} {}? (y/N)", verb, commit.short_hash),
+                self.palette.error(),
+            ));
+        } else if let Some(prompt) = &self.checkout_prompt {
+            let text = match prompt {
+                CheckoutPrompt::Track { hash, upstream, name } => format!(
+                    "{} has no local branch: y creates {} tracking it, n detaches HEAD at {} (esc cancels)",
+                    upstream,
+                    name,
+                    &hash[..7.min(hash.len())]
+                ),
+                CheckoutPrompt::Stash { blockers, .. } => format!(
+                    "Checkout would overwrite local changes to {}; stash them first? (y/N)",
+                    blockers.join(", ")
+                ),
+            };
+            spans.push(Span::styled(text, self.palette.error()));
+        } else if let Some((action, commit)) = &self.mainline {
+            let verb = if *action == Action::Revert { "Revert" } else { "Cherry-pick" };
+            let parents: Vec<String> = commit
+                .parents
+                .iter()
+                .enumerate()
+                .map(|(i, parent)| format!("{} {}", i + 1, &parent[..7.min(parent.len())]))
+                .collect();
+            spans.push(Span::styled(
+                format!(
+                    "{} merge {} relative to parent: {} (esc cancels)",
+                    verb,
+                    commit.short_hash,
+                    parents.join(", ")
+                ),
+                self.palette.error(),
+            ));
+        } else if let Some(sequence) = self.sequence.as_ref().filter(|sequence| !sequence.paused) {
+            let (verb, _) = sequence.verbs();
+            if let Some(commit) = sequence.commits.get(sequence.done) {
+                spans.push(Span::raw(format!(
+                    "{} {}/{}: {} {}",
+                    verb,
+                    sequence.done + 1,
+                    sequence.commits.len(),
                     commit.short_hash,
-                    commit.author,
-                    commit.date.format(&self.config.date_format),
                     commit.message
-                );
-
-                let refs = if !commit.refs.is_empty() {
-                    format!(" ({})", commit.refs.join(", "))
-                } else {
-                    String::new()
+                )));
+            }
+        } else if let Some(message) = message {
+            let style = if message.error { self.palette.error() } else { Style::default() };
+            spans.push(Span::styled(message.text.clone(), style));
+        } else {
+            spans.extend(self.branch_status());
+            if let Some(operation) = self.operation {
+                let progress = match &self.sequence {
+                    Some(sequence) => format!(" ({}/{})", sequence.done + 1, sequence.commits.len()),
+                    None => String::new(),
                 };
+                spans.push(Span::styled(
+                    format!(" | {} in progress{}", operation, progress),
+                    self.palette.error(),
+                ));
+            }
+            let chosen = self.chosen().len();
+            if chosen > 0 {
+                spans.push(Span::styled(
+                    format!(
+                        " | {} selected ({}/{} to apply, {} clears)",
+                        chosen,
+                        self.keys_label(Action::CherryPick),
+                        self.keys_label(Action::Revert),
+                        self.keys_label(Action::Quit)
+                    ),
+                    self.palette.head(),
+                ));
+            }
+            spans.push(Span::raw(" | "));
+            if self.status.is_clean() {
+                spans.push(Span::styled("clean", self.palette.muted()));
+            } else {
+                spans.push(Span::raw(format!(
+                    "{} staged, {} unstaged, {} untracked",
+                    self.status.staged, self.status.unstaged, self.status.untracked
+                )));
+            }
+            let filters = self.filter.describe();
+            if !filters.is_empty() {
+                spans.push(Span::styled(format!(" | {}", filters), self.palette.muted()));
+            }
+        }
+
+        if let Some(index) = self.selected_commit_index() {
+            let position = format!("{}/{}", index + 1, self.commits.len());
+            let used = Line::from(spans.clone()).width();
+            let padding = (width as usize).saturating_sub(used + position.len()).max(1);
+            spans.push(Span::raw(" ".repeat(padding)));
+            spans.push(Span::raw(position));
+        }
+        Line::from(spans)
+    }
+
+    /// The checked-out branch and how it compares with its upstream.
+    fn branch_status(&self) -> Vec<Span<'static>> {
+        let Some(branch) = &self.current_branch else {
+            return Vec::new();
+        };
+        if branch == "HEAD" {
+            let at = self.head.as_deref().map(|head| &head[..7.min(head.len())]).unwrap_or("");
+            return vec![
+                Span::styled(format!("HEAD detached at {}", at), self.palette.error()),
+                Span::styled(" (no branch)", self.palette.muted()),
+            ];
+        }
+        let mut spans = vec![Span::styled(refs::short_name(branch), self.palette.head())];
+        match self.tracking.iter().find(|t| &t.branch == branch) {
+            Some(tracking) => {
+                let counts = tracking.counts();
+                let state = if counts.is_empty() { "up to date with".to_string() } else { counts };
+                spans.push(Span::raw(format!(" {} ", state)));
+                spans.push(Span::styled(
+                    refs::short_name(&tracking.upstream),
+                    self.palette.reference(RefKind::Remote),
+                ));
+            }
+            None => spans.push(Span::styled(" (no upstream)", self.palette.muted())),
+        }
+        spans
+    }
 
-                let line = format!("{} {}{}", graph, info, refs);
-                ListItem::new(Line::from(Span::raw(line))).style(style)
+    /// Marks the commit where a local branch and its upstream split.
+    fn render_forks(&self, hash: &str) -> Vec<Span<'static>> {
+        self.tracking
+            .iter()
+            .filter(|tracking| tracking.fork_point.as_deref() == Some(hash))
+            .map(|tracking| {
+                Span::styled(
+                    format!(
+                        " <- {} and {} split here",
+                        refs::short_name(&tracking.branch),
+                        refs::short_name(&tracking.upstream)
+                    ),
+                    self.palette.muted().add_modifier(Modifier::ITALIC),
+                )
             })
+            .collect()
+    }
+
+    /// Cells drawn for `row`; a fold reuses the row of its newest commit.
+    fn row_cells(&self, row: Row) -> &[Cell] {
+        match row {
+            Row::Commit(index) => &self.commits[index].graph,
+            Row::Fold(segment) => {
+                let first = graph::segment_rows(&self.segments, segment)[0];
+                &self.commits[first].graph
+            }
+        }
+    }
+
+    /// Works out which lanes are on screen, panning to keep the selected
+    /// commit's node visible whenever the selection moves.
+    fn lane_window(&mut self) -> LaneWindow {
+        let visible: Vec<&[Cell]> = self
+            .rows
+            .iter()
+            .skip(self.offset)
+            .take(self.height)
+            .map(|row| self.row_cells(*row))
             .collect();
+        let lanes = if self.compress {
+            graph::active_lanes(visible.iter().copied())
+        } else {
+            let widest = visible.iter().map(|cells| cells.len()).max().unwrap_or(0);
+            (0..widest.div_ceil(2)).collect()
+        };
 
-        let list = List::new(items)
-            .block(Block::default().borders(Borders::ALL).title("Git Graph"));
+        let total = graph::columns(lanes.len());
+        let mut limit = self.width / 2;
+        if self.config.graph.max_width > 0 {
+            limit = limit.min(self.config.graph.max_width);
+        }
+        let clipped = total > limit.max(3);
+        let width = if clipped { limit.max(3) - 2 } else { total };
+        let max_pan = total.saturating_sub(width).div_ceil(2);
 
-        f.render_widget(list, chunks[0]);
+        if clipped && self.panned_for != Some(self.selected) {
+            let node = self
+                .selected_commit()
+                .and_then(|commit| lanes.iter().position(|&lane| lane == commit.lane));
+            if let Some(node) = node {
+                if 2 * node < 2 * self.pan {
+                    self.pan = node;
+                } else if 2 * node >= 2 * self.pan + width {
+                    self.pan = (2 * node + 1 - width).div_ceil(2);
+                }
+            }
+        }
+        self.panned_for = Some(self.selected);
+        self.pan = self.pan.min(max_pan);
+
+        LaneWindow {
+            start: if clipped { 2 * self.pan } else { 0 },
+            lanes,
+            width,
+            clipped,
+        }
     }
 
-    fn render_graph_line(&self, commit: &Commit) -> String {
-        if commit.graph.is_empty() {
-            return "●".to_string();
+    /// Graph cells of one row, colored per lane with the HEAD node highlighted.
+    fn render_graph_line(&self, cells: &[Cell], window: &LaneWindow) -> Vec<Span<'static>> {
+        if cells.is_empty() {
+            return vec![Span::raw("●")];
         }
 
-        let mut chars = Vec::new();
-        for line in &commit.graph {
-            let char = match line.line_type {
-                crate::git::GraphLineType::Vertical => {
-                    if self.unicode { "│" } else { "|" }
-                }
-                crate::git::GraphLineType::Horizontal => {
-                    if self.unicode { "─" } else { "-" }
-                }
-                crate::git::GraphLineType::Corner => {
-                    if self.unicode { "└" } else { "\\" }
+        let cells = graph::compress(cells, &window.lanes);
+        let mut spans = Vec::new();
+        if window.clipped {
+            let hidden = cells.iter().take(window.start).any(|cell| !cell.is_empty());
+            spans.push(Span::raw(if hidden { "«" } else { " " }));
+        }
+        for col in window.start..window.start + window.width {
+            spans.push(match cells.get(col) {
+                Some(cell) if !cell.is_empty() => {
+                    let style = if cell.node == Some(NodeKind::Head) {
+                        self.palette.head()
+                    } else {
+                        self.palette.lane(cell.color)
+                    };
+                    Span::styled(self.glyphs.glyph(cell).to_string(), style)
                 }
-                crate::git::GraphLineType::Merge => "●",
-                crate::git::GraphLineType::None => " ",
-            };
-            chars.push(char);
+                _ => Span::raw(" "),
+            });
+        }
+        if window.clipped {
+            let hidden = cells.iter().skip(window.start + window.width).any(|cell| !cell.is_empty());
+            spans.push(Span::raw(if hidden { "»" } else { " " }));
         }
 
-        chars.join("")
+        spans
     }
 
-    fn render_help(&self, f: &mut Frame) {
-        let help_text = r#"
-gittree - GitHub-like Git Graph
-
-KEYBINDINGS:
-  ↑/k / ↓/j          Move selection
-  ←/h / →/l          Jump parents/children
-  PgUp / PgDn        Page
-  g / G              Top / Bottom
-  Enter              Open commit (details pane)
-  c                  Checkout selected
-  x                  Reset to selected
-  p                  Cherry-pick selected
-  r                  Revert selected
-  b                  New branch at selected
-  t                  New tag at selected
-  /                  Filter (author/msg/path)
-  f                  Toggle follow file
-  u                  Toggle Unicode lanes
-  ?                  Help
-  q                  Quit
-
-Press ? to close this help.
-"#;
-
-        let paragraph = Paragraph::new(help_text)
-            .block(Block::default().borders(Borders::ALL).title("Help"));
+    /// Ref badges after the commit text, colored by kind of ref; the
+    /// checked-out branch gets the HEAD color.
+    fn render_refs(&self, decorations: &[Decoration]) -> Vec<Span<'static>> {
+        if decorations.is_empty() {
+            return Vec::new();
+        }
 
-        f.render_widget(paragraph, f.size());
+        let mut spans = vec![Span::raw(" (")];
+        for (i, decoration) in decorations.iter().enumerate() {
+            if i > 0 {
+                spans.push(Span::raw(", "));
+            }
+            spans.push(Span::styled(
+                decoration.label.clone(),
+                self.palette.reference(decoration.kind()),
+            ));
+            if !decoration.tracking.is_empty() {
+                spans.push(Span::raw(format!(" {}", decoration.tracking)));
+            }
+            for remote in &decoration.remotes {
+                spans.push(Span::raw(" = "));
+                spans.push(Span::styled(remote.clone(), self.palette.reference(RefKind::Remote)));
+            }
+        }
+        spans.push(Span::raw(")"));
+        spans
     }
 
-    fn render_empty(&self, f: &mut Frame) {
-        let paragraph = Paragraph::new("No commits found")
-            .block(Block::default().borders(Borders::ALL).title("Git Graph"));
+    fn render_help(&self, f: &mut Frame) {
+        let bindings = self.keymap.help();
+        let key_width = bindings.iter().map(|(keys, _)| keys.chars().count()).max().unwrap_or(0);
+        let mut lines = vec![
+            Line::from("gittree - GitHub-like Git Graph"),
+            Line::from(""),
+            Line::from("KEYBINDINGS:"),
+        ];
+        lines.extend(
+            bindings
+                .iter()
+                .map(|(keys, description)| Line::from(format!("  {:width$}  {}", keys, description, width = key_width))),
+        );
+        lines.push(Line::from(""));
+        lines.push(Line::from("Press any key to close this help."));
+
+        let paragraph = Paragraph::new(lines)
+            .block(Block::default().borders(Borders::ALL).title("Help"));
 
         f.render_widget(paragraph, f.size());
     }
 
-    fn handle_key_press(&mut self, key: KeyCode) -> Result<(), Box<dyn std::error::Error>> {
-        match key {
-            KeyCode::Char('q') | KeyCode::Esc => {
-                self.should_quit = true;
-            }
-            KeyCode::Char('?') => {
-                self.show_help = !self.show_help;
-            }
-            KeyCode::Char('u') => {
-                self.unicode = !self.unicode;
-            }
-            KeyCode::Up | KeyCode::Char('k') => {
-                if self.selected > 0 {
-                    self.selected -= 1;
-                }
-            }
-            KeyCode::Down | KeyCode::Char('j') => {
-                if self.selected < self.commits.len().saturating_sub(1) {
-                    self.selected += 1;
-                }
+    /// Lists the refs under a header per group: name, ahead/behind counts
+    /// against the upstream and the date of the commit it points at.
+    fn render_sidebar(&self, f: &mut Frame, area: Rect) {
+        let Some(sidebar) = &self.sidebar else {
+            return;
+        };
+        let mut lines = Vec::new();
+        let mut selected_line = 0;
+        for group in Group::ALL {
+            let count = sidebar.refs.iter().filter(|r| r.group == group).count();
+            if count == 0 {
+                continue;
             }
-            KeyCode::Left | KeyCode::Char('h') => {
-                // Jump to parent
-                if self.selected < self.commits.len() && !self.commits[self.selected].parents.is_empty() {
-                    let parent_hash = &self.commits[self.selected].parents[0];
-                    for (i, commit) in self.commits.iter().enumerate() {
-                        if commit.hash == *parent_hash {
-                            self.selected = i;
-                            break;
-                        }
+            lines.push(ListItem::new(Line::from(Span::styled(
+                format!("{} ({})", group.title(), count),
+                Style::default().add_modifier(Modifier::BOLD),
+            ))));
+            for (i, entry) in sidebar.refs.iter().enumerate().filter(|(_, r)| r.group == group) {
+                let mut spans = vec![
+                    Span::raw(if entry.is_head { "* " } else { "  " }),
+                    Span::styled(entry.short.clone(), self.palette.reference(entry.kind())),
+                ];
+                if let Some((ahead, behind)) = entry.ahead_behind {
+                    if ahead > 0 {
+                        spans.push(Span::raw(format!(" ↑{}", ahead)));
                     }
-                }
-            }
-            KeyCode::Right | KeyCode::Char('l') => {
-                // Jump to child
-                if self.selected < self.commits.len() {
-                    let current_hash = &self.commits[self.selected].hash;
-                    for (i, commit) in self.commits.iter().enumerate() {
-                        for parent in &commit.parents {
-                            if parent == current_hash {
-                                self.selected = i;
-                                return Ok(());
-                            }
-                        }
+                    if behind > 0 {
+                        spans.push(Span::raw(format!(" ↓{}", behind)));
                     }
                 }
-            }
-            KeyCode::Char('g') => {
-                self.selected = 0;
-            }
-            KeyCode::Char('G') => {
-                self.selected = self.commits.len().saturating_sub(1);
-            }
-            KeyCode::PageUp => {
-                if self.selected > 10 {
-                    self.selected -= 10;
+                spans.push(Span::styled(
+                    format!(" {}", entry.date.format("%Y-%m-%d")),
+                    self.palette.muted(),
+                ));
+                let style = if i == sidebar.selected && sidebar.focused {
+                    self.palette.selection()
                 } else {
-                    self.selected = 0;
-                }
-            }
-            KeyCode::PageDown => {
-                if self.selected < self.commits.len().saturating_sub(10) {
-                    self.selected += 10;
-                } else {
-                    self.selected = self.commits.len().saturating_sub(1);
-                }
-            }
-            KeyCode::Enter => {
-                if self.selected < self.commits.len() {
-                    self.show_commit_details(&self.commits[self.selected])?;
-                }
-            }
-            KeyCode::Char('c') => {
-                if self.selected < self.commits.len() {
-                    self.checkout_commit(&self.commits[self.selected])?;
-                }
-            }
-            KeyCode::Char('x') => {
-                if self.selected < self.commits.len() {
-                    self.reset_to_commit(&self.commits[self.selected])?;
-                }
-            }
-            KeyCode::Char('p') => {
-                if self.selected < self.commits.len() {
-                    self.cherry_pick_commit(&self.commits[self.selected])?;
-                }
-            }
-            KeyCode::Char('r') => {
-                if self.selected < self.commits.len() {
-                    self.revert_commit(&self.commits[self.selected])?;
-                }
-            }
-            KeyCode::Char('b') => {
-                if self.selected < self.commits.len() {
-                    self.create_branch(&self.commits[self.selected])?;
-                }
-            }
-            KeyCode::Char('t') => {
-                if self.selected < self.commits.len() {
-                    self.create_tag(&self.commits[self.selected])?;
+                    Style::default()
+                };
+                if i == sidebar.selected {
+                    selected_line = lines.len();
                 }
+                lines.push(ListItem::new(Line::from(spans)).style(style));
             }
-            _ => {}
         }
 
-        Ok(())
+        let prompt_height = if sidebar.prompt.is_some() { 3 } else { 0 };
+        let visible = area.height.saturating_sub(2 + prompt_height) as usize;
+        let offset = (selected_line + 1).saturating_sub(visible);
+        let lines: Vec<ListItem> = lines.into_iter().skip(offset).collect();
+        let sort = match sidebar.sort {
+            Sort::Recency => "recent",
+            Sort::Name => "name",
+        };
+        let title = format!("Refs (by {}, s: sort)", sort);
+        let list = List::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+        let Some((prompt, text)) = &sidebar.prompt else {
+            f.render_widget(list, area);
+            return;
+        };
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(3)])
+            .split(area);
+        f.render_widget(list, chunks[0]);
+        let (title, line) = match prompt {
+            Prompt::Rename => ("New name (enter: save, esc: cancel)", format!("{}_", text)),
+            Prompt::Upstream => ("Upstream (empty: none)", format!("{}_", text)),
+            Prompt::ForceDelete => ("Delete even if unmerged? (y/N)", text.clone()),
+        };
+        let block = Block::default().borders(Borders::ALL).title(title);
+        f.render_widget(Paragraph::new(line).block(block), chunks[1]);
     }
 
-    fn show_commit_details(&self, commit: &Commit) -> Result<(), Box<dyn std::error::Error>> {
-        println!("Commit: {}", commit.hash);
-        println!("Author: {} <{}>", commit.author, commit.email);
-        println!("Date: {}", commit.date.format("%Y-%m-%d %H:%M:%S %Z"));
-        println!("Message: {}", commit.message);
-        println!("Parents: {}", commit.parents.join(", "));
-        println!("Files: {}", commit.files.join(", "));
-        Ok(())
-    }
+    /// The changed files with their `git status --short` letters, the diff
+    /// of the selected file and, while committing, the message being typed.
+    fn render_work_tree(&self, f: &mut Frame, area: Rect) {
+        let Some(pane) = &self.work_tree else {
+            return;
+        };
+        let files_height = (pane.files.len() as u16 + 2).min(area.height / 3).max(3);
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(files_height),
+                Constraint::Min(0),
+                Constraint::Length(if pane.message.is_some() { 3 } else { 0 }),
+            ])
+            .split(area);
+
+        let visible = files_height.saturating_sub(2) as usize;
+        let offset = (pane.selected + 1).saturating_sub(visible);
+        let letter = |c: Option<char>| c.unwrap_or(' ').to_string();
+        let files: Vec<ListItem> = pane
+            .files
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .map(|(i, file)| {
+                let line = Line::from(vec![
+                    Span::styled(letter(file.staged), self.palette.reference(RefKind::Local)),
+                    Span::styled(letter(file.unstaged), self.palette.error()),
+                    Span::raw(format!(" {}", file.path)),
+                ]);
+                let style = if i == pane.selected {
+                    self.palette.selection()
+                } else {
+                    Style::default()
+                };
+                ListItem::new(line).style(style)
+            })
+            .collect();
+        let title = "Uncommitted changes (space: stage/unstage, c: commit, esc: close)";
+        f.render_widget(
+            List::new(files).block(Block::default().borders(Borders::ALL).title(title)),
+            chunks[0],
+        );
+
+        let diff: Vec<Line> = pane
+            .diff
+            .iter()
+            .skip(pane.scroll)
+            .map(|line| {
+                let style = if line.starts_with("+++") || line.starts_with("---") || line.starts_with("diff ") {
+                    Style::default().add_modifier(Modifier::BOLD)
+                } else if line.starts_with('+') {
+                    self.palette.reference(RefKind::Local)
+                } else if line.starts_with('-') {
+                    self.palette.error()
+                } else if line.starts_with("@@") {
+                    self.palette.muted()
+                } else {
+                    Style::default()
+                };
+                Line::styled(line.clone(), style)
+            })
+            .collect();
+        f.render_widget(
+            Paragraph::new(diff).block(Block::default().borders(Borders::ALL).title("Diff")),
+            chunks[1],
+        );
 
-    fn checkout_commit(&self, commit: &Commit) -> Result<(), Box<dyn std::error::Error>> {
-        if self.config.confirm_dangerous {
-            println!("Checkout {}? (y/N): ", commit.short_hash);
+        if let Some(message) = &pane.message {
+            let block = Block::default()
+                .borders(Borders::ALL)
+                .title("Commit message (enter: commit, esc: cancel)");
+            f.render_widget(Paragraph::new(format!("{}_", message)).block(block), chunks[2]);
         }
-        self.repo.checkout(&commit.hash)
     }
 
-    fn reset_to_commit(&self, commit: &Commit) -> Result<(), Box<dyn std::error::Error>> {
-        if self.config.confirm_dangerous {
-            println!("Reset to {}? (y/N): ", commit.short_hash);
+    /// The conflicted files with how each side changed them, and the
+    /// working copy of the selected one with our and their hunks colored.
+    fn render_conflicts(&self, f: &mut Frame, area: Rect) {
+        let Some(pane) = &self.conflicts else {
+            return;
+        };
+        let files_height = (pane.conflicts.len().max(1) as u16 + 2).min(area.height / 3).max(3);
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(files_height), Constraint::Min(0)])
+            .split(area);
+
+        let visible = files_height.saturating_sub(2) as usize;
+        let offset = (pane.selected + 1).saturating_sub(visible);
+        let mut files: Vec<ListItem> = pane
+            .conflicts
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .map(|(i, conflict)| {
+                let line = Line::from(vec![
+                    Span::styled(format!("{:<16}", conflict.describe()), self.palette.error()),
+                    Span::raw(conflict.path.clone()),
+                ]);
+                let style = if i == pane.selected {
+                    self.palette.selection()
+                } else {
+                    Style::default()
+                };
+                ListItem::new(line).style(style)
+            })
+            .collect();
+        if files.is_empty() {
+            files.push(ListItem::new(Span::styled(
+                "All conflicts resolved; c commits",
+                self.palette.muted(),
+            )));
         }
-        self.repo.reset_hard(&commit.hash)
-    }
+        let title = format!(
+            "{} conflicts (o/t: take ours/theirs, space: resolved, c: continue, s: skip, a: abort)",
+            self.operation.unwrap_or("cherry-pick")
+        );
+        f.render_widget(
+            List::new(files).block(Block::default().borders(Borders::ALL).title(title)),
+            chunks[0],
+        );
 
-    fn cherry_pick_commit(&self, commit: &Commit) -> Result<(), Box<dyn std::error::Error>> {
-        self.repo.cherry_pick(&commit.hash)
+        // Which side of the markers each line is on.
+        let mut side = None;
+        let lines: Vec<Line> = pane
+            .lines
+            .iter()
+            .map(|line| {
+                let marker = ["<<<<<<<", "=======", ">>>>>>>"]
+                    .iter()
+                    .position(|marker| line.starts_with(marker));
+                let style = match marker {
+                    Some(marker) => {
+                        side = [Some(true), Some(false), None][marker];
+                        Style::default().add_modifier(Modifier::BOLD)
+                    }
+                    None => match side {
+                        Some(true) => self.palette.reference(RefKind::Local),
+                        Some(false) => self.palette.reference(RefKind::Remote),
+                        None => Style::default(),
+                    },
+                };
+                Line::styled(line.clone(), style)
+            })
+            .skip(pane.scroll)
+            .collect();
+        let title = "Working copy (ours: HEAD, theirs: the commit being applied)";
+        f.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+            chunks[1],
+        );
     }
 
-    fn revert_commit(&self, commit: &Commit) -> Result<(), Box<dyn std::error::Error>> {
-        self.repo.revert(&commit.hash)
-    }
+    /// The todo list on the left, oldest commit first as git lists it, and
+    /// the resulting history on the right.
+    fn render_planner(&self, f: &mut Frame) {
+        let Some(planner) = &self.planner else {
+            return;
+        };
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(0),
+                Constraint::Length(planner.input.is_some() as u16),
+            ])
+            .split(f.size());
+        let chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(rows[0]);
 
-    fn create_branch(&self, commit: &Commit) -> Result<(), Box<dyn std::error::Error>> {
-        let branch_name = format!("branch-{}", commit.short_hash);
-        self.repo.create_branch(&branch_name, &commit.hash)
+        let visible = chunks[0].height.saturating_sub(2) as usize;
+        let offset = (planner.selected + 1).saturating_sub(visible);
+        let steps: Vec<ListItem> = planner
+            .plan
+            .steps
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .map(|(i, step)| {
+                let style = match step.command {
+                    Command::Pick => Style::default(),
+                    Command::Drop => self.palette.muted().add_modifier(Modifier::CROSSED_OUT),
+                    Command::Squash | Command::Fixup => self.palette.reference(RefKind::Remote),
+                    Command::Reword | Command::Edit => self.palette.reference(RefKind::Tag),
+                };
+                let subject = match &step.message {
+                    Some(message) if step.command == Command::Reword => message.lines().next().unwrap_or(""),
+                    _ => step.subject.as_str(),
+                };
+                let line = Line::from(vec![
+                    Span::styled(format!("{:<6}", step.command.name()), style),
+                    Span::raw(format!(" {} {}", &step.hash[..7], subject)),
+                ]);
+                let style = if i == planner.selected {
+                    self.palette.selection()
+                } else {
+                    Style::default()
+                };
+                ListItem::new(line).style(style)
+            })
+            .collect();
+        let base = &planner.plan.base[..7];
+        let title = format!("Rebase onto {} (p/r/e/s/f/d, J/K: move, enter: run, esc: cancel)", base);
+        f.render_widget(
+            List::new(steps).block(Block::default().borders(Borders::ALL).title(title)),
+            chunks[0],
+        );
+
+        let width = planner.preview.iter().map(|commit| commit.graph.len()).max().unwrap_or(0);
+        let window = LaneWindow {
+            lanes: (0..width.div_ceil(2)).collect(),
+            start: 0,
+            width,
+            clipped: false,
+        };
+        let preview: Vec<ListItem> = planner
+            .preview
+            .iter()
+            .map(|commit| {
+                let mut spans = self.render_graph_line(&commit.graph, &window);
+                spans.push(Span::raw(format!(" {} {}", commit.short_hash, commit.message)));
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+        let title = match planner.plan.validate() {
+            Ok(()) => "Result".to_string(),
+            Err(err) => format!("Result: {}", err),
+        };
+        f.render_widget(
+            List::new(preview).block(Block::default().borders(Borders::ALL).title(title)),
+            chunks[1],
+        );
+
+        if let Some(input) = &planner.input {
+            f.render_widget(Paragraph::new(format!("reword: {}_", input)), rows[1]);
+        }
     }
 
-    fn create_tag(&self, commit: &Commit) -> Result<(), Box<dyn std::error::Error>> {
-        let tag_name = format!("tag-{}", commit.short_hash);
-        self.repo.create_tag(&tag_name, &commit.hash)
+    fn render_picker(&self, f: &mut Frame) {
+        let Some(picker) = &self.picker else {
+            return;
+        };
+        let size = f.size();
+        let width = 60.min(size.width.saturating_sub(4));
+        let height = (picker.refs.len() as u16 + 2).clamp(3, size.height.saturating_sub(4).max(3));
+        let area = Rect::new(
+            size.x + (size.width - width) / 2,
+            size.y + (size.height.saturating_sub(height)) / 2,
+            width,
+            height,
+        );
+
+        let visible = height.saturating_sub(2) as usize;
+        let offset = (picker.selected + 1).saturating_sub(visible);
+        let items: Vec<ListItem> = picker
+            .refs
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .take(visible)
+            .map(|(i, (name, checked))| {
+                let mark = if *checked { "[x] " } else { "[ ] " };
+                let line = Line::from(vec![
+                    Span::raw(mark),
+                    Span::styled(name.clone(), self.palette.reference(RefKind::of(name))),
+                ]);
+                let style = if i == picker.selected {
+                    self.palette.selection()
+                } else {
+                    Style::default()
+                };
+                ListItem::new(line).style(style)
+            })
+            .collect();
+
+        let title = "Refs (space: toggle, a: all/none, esc: close)";
+        let list = List::new(items).block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(Clear, area);
+        f.render_widget(list, area);
     }
-}
\ No newline at end of file
+
+    /// The reset modes with what the one under the cursor would lose:
+    /// commits no ref reaches any more and uncommitted changes.
+    /// The tags, newest first, with the selected one's message below.
+    fn render_tag_list(&self, f: &mut Frame) {
+        let Some(list) = &self.tag_list else {
+            return;
+        };
+        let size = f.size();
+        let width = 100.min(size.width.saturating_sub(4));
+        let height = size.height.saturating_sub(4).max(3);
+        let area = Rect::new(
+            size.x + (size.width - width) / 2,
+            size.y + (size.height.saturating_sub(height)) / 2,
+            width,
+            height,
+        );
+        let message: Vec<&str> = list
+            .tags
+            .get(list.selected)
+            .and_then(|tag| tag.annotation.as_ref())
+            .map(|annotation| annotation.message.lines().collect())
+            .unwrap_or_default();
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(3),
+                Constraint::Length(if message.is_empty() { 0 } else { (message.len() as u16 + 2).min(8) }),
+            ])
+            .split(area);
+
+        let name_width = list.tags.iter().map(|tag| tag.name.chars().count()).max().unwrap_or(0);
+        let visible = chunks[0].height.saturating_sub(2) as usize;
+        let offset = (list.selected + 1).saturating_sub(visible);
+        let items: Vec<ListItem> = list
+            .tags
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .take(visible)
+            .map(|(i, tag)| {
+                let mut spans = vec![
+                    Span::styled(
+                        format!("{:width$}", tag.name, width = name_width),
+                        self.palette.reference(RefKind::Tag),
+                    ),
+                    Span::raw(format!(" {} ", &tag.target[..7])),
+                    Span::styled(tag.date.format("%Y-%m-%d").to_string(), self.palette.muted()),
+                ];
+                match &tag.annotation {
+                    Some(annotation) => {
+                        spans.push(Span::raw(format!(" {}", annotation.tagger)));
+                        if let Some(signature) = annotation.signature {
+                            let label = format!(" [{} signed (unverified)]", signature.name());
+                            spans.push(Span::styled(label, self.palette.muted()));
+                        }
+                        spans.push(Span::raw(format!(" {}", annotation.message.lines().next().unwrap_or(""))));
+                    }
+                    None => spans.push(Span::styled(" lightweight", self.palette.muted())),
+                }
+                let style = if i == list.selected {
+                    self.palette.selection()
+                } else {
+                    Style::default()
+                };
+                ListItem::new(Line::from(spans)).style(style)
+            })
+            .collect();
+
+        let title = format!("Tags ({}; enter: jump, d: delete, f: move here, esc: close)", list.tags.len());
+        f.render_widget(Clear, area);
+        f.render_widget(List::new(items).block(Block::default().borders(Borders::ALL).title(title)), chunks[0]);
+        if !message.is_empty() {
+            let block = Block::default().borders(Borders::ALL).title("Message");
+            f.render_widget(Paragraph::new(message.join("\n")).block(block), chunks[1]);
+        }
+    }
+
+    /// The merge modes, then what the one under the cursor would do: the
+    /// commits involved and the conflicts found by merging in memory.
+    fn render_merge_menu(&self, f: &mut Frame) {
+        let Some(menu) = &self.merge_menu else {
+            return;
+        };
+        let preview = &menu.preview;
+        let mut lines: Vec<Line> = MergeMode::ALL
+            .iter()
+            .map(|&mode| {
+                let mut line = Line::from(vec![
+                    Span::styled(format!(" {:<8}", mode.name()), Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(mode.description()),
+                ]);
+                if mode == menu.mode {
+                    line.patch_style(self.palette.selection());
+                }
+                line
+            })
+            .collect();
+        lines.push(Line::raw(""));
+
+        let conflicts = |conflicts: &[Conflict]| {
+            conflicts.iter().map(|conflict| format!("  {}: {}", conflict.path, conflict.describe())).collect::<Vec<_>>()
+        };
+        if preview.incoming == 0 {
+            lines.push(Line::styled("Already up to date", self.palette.muted()));
+        } else {
+            match menu.mode {
+                MergeMode::Rebase => {
+                    lines.push(Line::raw(format!(
+                        "{} commits of HEAD are replayed onto {}",
+                        preview.replayed, menu.commit.short_hash
+                    )));
+                    match &preview.rebase_stop {
+                        None => lines.push(Line::raw("No conflicts")),
+                        Some(stop) => {
+                            lines.push(Line::styled(format!("Stops at {} with conflicts:", stop.commit), self.palette.error()));
+                            lines.extend(conflicts(&stop.conflicts).into_iter().map(Line::raw));
+                        }
+                    }
+                }
+                mode => {
+                    lines.push(Line::raw(format!("{} commits come in", preview.incoming)));
+                    if mode == MergeMode::FastForward && !preview.fast_forward() {
+                        lines.push(Line::styled(
+                            format!("Refused: HEAD has {} commits the target lacks", preview.replayed),
+                            self.palette.error(),
+                        ));
+                    } else if mode == MergeMode::FastForward || preview.conflicts.is_empty() {
+                        lines.push(Line::raw("No conflicts"));
+                    } else {
+                        let text = if mode == MergeMode::Squash { "Refused: conflicts in" } else { "Conflicts to resolve:" };
+                        lines.push(Line::styled(text, self.palette.error()));
+                        lines.extend(conflicts(&preview.conflicts).into_iter().map(Line::raw));
+                    }
+                }
+            }
+        }
+
+        let size = f.size();
+        let width = 72.min(size.width.saturating_sub(4));
+        let height = (lines.len() as u16 + 2).min(size.height);
+        let area = Rect::new(
+            size.x + (size.width - width) / 2,
+            size.y + (size.height - height) / 2,
+            width,
+            height,
+        );
+        let branch = match self.current_branch.as_deref() {
+            Some("HEAD") | None => "HEAD".to_string(),
+            Some(branch) => refs::short_name(branch),
+        };
+        let names = MergeMode::ALL.map(|mode| mode.name());
+        let title = format!(
+            "Merge {} into {} ({}, enter: run, esc: cancel)",
+            menu.commit.short_hash,
+            branch,
+            self.mode_letters(&names)
+        );
+        f.render_widget(Clear, area);
+        f.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+            area,
+        );
+    }
+
+    fn render_reset_menu(&self, f: &mut Frame) {
+        let Some(menu) = &self.reset_menu else {
+            return;
+        };
+        let preview = &menu.preview;
+        let mut lines: Vec<Line> = ResetMode::ALL
+            .iter()
+            .map(|&mode| {
+                let mut line = Line::from(vec![
+                    Span::styled(format!(" {:<6}", mode.name()), Style::default().add_modifier(Modifier::BOLD)),
+                    Span::raw(mode.description()),
+                ]);
+                if mode == menu.mode {
+                    line.patch_style(self.palette.selection());
+                }
+                line
+            })
+            .collect();
+        lines.push(Line::raw(""));
+
+        if preview.lost.is_empty() {
+            lines.push(Line::styled("No commits will be lost", self.palette.muted()));
+        } else {
+            let backup = if menu.mode == ResetMode::Hard { " (a backup ref keeps them)" } else { "" };
+            lines.push(Line::styled(
+                format!("{} commits will no longer be on any ref{}:", preview.lost.len(), backup),
+                self.palette.error(),
+            ));
+            for (hash, subject) in preview.lost.iter().take(5) {
+                lines.push(Line::raw(format!("  {} {}", hash, subject)));
+            }
+            if preview.lost.len() > 5 {
+                lines.push(Line::raw(format!("  ... and {} more", preview.lost.len() - 5)));
+            }
+        }
+        let changes = preview.changes.len();
+        match menu.mode {
+            _ if changes == 0 => lines.push(Line::styled("No uncommitted changes", self.palette.muted())),
+            ResetMode::Soft => lines.push(Line::raw(format!("{} changed files are kept as they are", changes))),
+            ResetMode::Mixed => lines.push(Line::raw(format!("{} changed files are kept, unstaged", changes))),
+            ResetMode::Hard => {
+                lines.push(Line::styled(
+                    format!("{} changed files will be discarded (stashed first):", changes),
+                    self.palette.error(),
+                ));
+                for change in preview.changes.iter().take(5) {
+                    lines.push(Line::raw(format!("  {}", change.path)));
+                }
+                if changes > 5 {
+                    lines.push(Line::raw(format!("  ... and {} more", changes - 5)));
+                }
+            }
+            ResetMode::Keep => {
+                let blockers = preview.keep_blockers();
+                if blockers.is_empty() {
+                    lines.push(Line::raw(format!("{} changed files are kept", changes)));
+                } else {
+                    lines.push(Line::styled(
+                        format!("Refused: the target changes {} too", blockers.join(", ")),
+                        self.palette.error(),
+                    ));
+                }
+            }
+        }
+
+        let size = f.size();
+        let width = 72.min(size.width.saturating_sub(4));
+        let height = (lines.len() as u16 + 2).min(size.height);
+        let area = Rect::new(
+            size.x + (size.width - width) / 2,
+            size.y + (size.height - height) / 2,
+            width,
+            height,
+        );
+        let branch = match self.current_branch.as_deref() {
+            Some("HEAD") | None => "HEAD".to_string(),
+            Some(branch) => refs::short_name(branch),
+        };
+        let names = ResetMode::ALL.map(|mode| mode.name());
+        let title = format!(
+            "Reset {} to {} ({}, enter: reset, esc: cancel)",
+            branch,
+            menu.commit.short_hash,
+            self.mode_letters(&names)
+        );
+        f.render_widget(Clear, area);
+        f.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+            area,
+        );
+    }
+
+    fn render_empty(&self, f: &mut Frame) {
+        let paragraph = Paragraph::new("No commits found")
+            .block(Block::default().borders(Borders::ALL).title("Git Graph"));
+
+        f.render_widget(paragraph, f.size());
+    }
+
+    fn handle_key_press(&mut self, key: KeyEvent) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some((action, commit)) = self.confirm.take() {
+            return match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => self.operate(action, &commit),
+                _ => {
+                    self.notify("Cancelled");
+                    Ok(())
+                }
+            };
+        }
+        if let Some(prompt) = self.checkout_prompt.take() {
+            return match (prompt, key.code) {
+                (CheckoutPrompt::Track { hash, upstream, .. }, KeyCode::Char('y' | 'Y')) => {
+                    self.checkout(hash, Checkout::Track(upstream), false)
+                }
+                (CheckoutPrompt::Track { hash, .. }, KeyCode::Char('n' | 'N')) => {
+                    self.checkout(hash.clone(), Checkout::Detach(hash), false)
+                }
+                (CheckoutPrompt::Stash { hash, checkout, .. }, KeyCode::Char('y' | 'Y')) => {
+                    self.checkout(hash, checkout, true)
+                }
+                _ => {
+                    self.notify("Cancelled");
+                    Ok(())
+                }
+            };
+        }
+        if let Some((action, commit)) = self.mainline.take() {
+            let parent = match key.code {
+                KeyCode::Char(c) => c.to_digit(10).filter(|&n| n >= 1 && n as usize <= commit.parents.len()),
+                _ => None,
+            };
+            return match parent {
+                Some(parent) => self.apply(action, &commit, parent),
+                None => {
+                    self.notify("Cancelled");
+                    Ok(())
+                }
+            };
+        }
+        if self.show_help {
+            self.show_help = false;
+            return Ok(());
+        }
+        if self.tag_prompt.is_some() {
+            return self.tag_prompt_key(key.code);
+        }
+        if self.input.is_some() {
+            self.edit_filter(key.code);
+            return Ok(());
+        }
+        if self.picker.is_some() {
+            return self.picker_key(key);
+        }
+        if self.tag_list.is_some() {
+            return self.tag_list_key(key);
+        }
+        if self.reset_menu.is_some() {
+            return self.reset_key(key);
+        }
+        if self.merge_menu.is_some() {
+            return self.merge_key(key);
+        }
+        if self.planner.is_some() {
+            return self.planner_key(key);
+        }
+        if self.conflicts.is_some() {
+            return self.conflicts_key(key);
+        }
+        if self.work_tree.is_some() {
+            return self.work_tree_key(key);
+        }
+        if self.sidebar.as_ref().is_some_and(|sidebar| sidebar.focused) {
+            return self.sidebar_key(key);
+        }
+
+        self.pending.push(KeyChord::from(key));
+        let mut lookup = self.keymap.lookup(&self.pending);
+        if lookup == Lookup::None && self.pending.len() > 1 {
+            // A broken sequence: start over from the key just pressed.
+            self.pending = vec![KeyChord::from(key)];
+            lookup = self.keymap.lookup(&self.pending);
+        }
+        match lookup {
+            Lookup::Action(action) => {
+                self.pending.clear();
+                self.perform(action)
+            }
+            Lookup::Pending => Ok(()),
+            Lookup::None => {
+                self.pending.clear();
+                Ok(())
+            }
+        }
+    }
+
+    fn perform(&mut self, action: Action) -> Result<(), Box<dyn std::error::Error>> {
+        match action {
+            Action::Quit if !self.marked.is_empty() || self.anchor.is_some() => {
+                self.marked.clear();
+                self.anchor = None;
+            }
+            Action::Quit => {
+                self.should_quit = true;
+            }
+            Action::Help => {
+                self.show_help = true;
+            }
+            Action::CycleGlyphs => {
+                self.glyphs = self.glyphs.next();
+            }
+            Action::MoveUp => {
+                self.selected = self.selected.saturating_sub(1);
+            }
+            Action::MoveDown => {
+                self.selected = (self.selected + 1).min(self.rows.len().saturating_sub(1));
+            }
+            Action::Parent => {
+                let parent = self
+                    .selected_commit()
+                    .and_then(|commit| commit.parents.first())
+                    .and_then(|hash| self.commits.iter().position(|c| c.hash == *hash));
+                if let Some(index) = parent {
+                    self.select_commit(index);
+                }
+            }
+            Action::Child => {
+                let child = self.selected_commit().and_then(|current| {
+                    self.commits
+                        .iter()
+                        .position(|c| c.parents.contains(&current.hash))
+                });
+                if let Some(index) = child {
+                    self.select_commit(index);
+                }
+            }
+            Action::Top => {
+                self.selected = 0;
+            }
+            Action::Bottom => {
+                self.selected = self.rows.len().saturating_sub(1);
+            }
+            Action::PageUp => {
+                self.selected = self.selected.saturating_sub(10);
+            }
+            Action::PageDown => {
+                self.selected = (self.selected + 10).min(self.rows.len().saturating_sub(1));
+            }
+            Action::Open => match self.rows.get(self.selected) {
+                Some(Row::Fold(segment)) => self.unfold(*segment),
+                Some(Row::Commit(index))
+                    if self.commits[*index].is_work_tree()
+                        && matches!(self.operation, Some("cherry-pick" | "revert" | "merge" | "rebase")) =>
+                {
+                    self.open_conflicts()?;
+                }
+                Some(Row::Commit(index)) if self.commits[*index].is_work_tree() => {
+                    self.work_tree = Some(WorkTreePane {
+                        files: Vec::new(),
+                        selected: 0,
+                        diff: Vec::new(),
+                        scroll: 0,
+                        message: None,
+                    });
+                    self.refresh_work_tree()?;
+                }
+                Some(Row::Commit(index)) => self.show_commit_details(*index),
+                None => {}
+            },
+            Action::Fold => {
+                self.toggle_fold();
+            }
+            Action::FoldAll => {
+                self.toggle_fold_all();
+            }
+            Action::PanLeft => {
+                self.pan = self.pan.saturating_sub(1);
+            }
+            Action::PanRight => {
+                self.pan += 1;
+            }
+            Action::CompressLanes => {
+                self.compress = !self.compress;
+            }
+            Action::Filter => {
+                self.input = Some(self.query.clone());
+            }
+            Action::Refs => {
+                self.open_picker()?;
+            }
+            Action::Rebase => match self.selected_commit().filter(|commit| !commit.is_work_tree()) {
+                Some(commit) => match self.repo.rebase_plan(&commit.hash) {
+                    Ok(plan) => self.open_planner(plan, 0),
+                    Err(err) => self.fail(err.to_string()),
+                },
+                None => self.fail("Select the commit to rebase onto"),
+            },
+            Action::Reword | Action::Fixup => match self.selected_commit().filter(|commit| !commit.is_work_tree()).cloned() {
+                Some(commit) => self.rewrite(action, commit)?,
+                None => self.fail("Select the commit to change"),
+            },
+            Action::Sidebar => match &mut self.sidebar {
+                Some(sidebar) => sidebar.focused = true,
+                None => {
+                    let sort = Sort::Recency;
+                    self.sidebar = Some(Sidebar {
+                        refs: self.repo.refs(sort)?,
+                        selected: 0,
+                        sort,
+                        focused: true,
+                        prompt: None,
+                    });
+                }
+            },
+            Action::Mark => {
+                if let Some(commit) = self.selected_commit().filter(|commit| !commit.is_work_tree()) {
+                    let hash = commit.hash.clone();
+                    if !self.marked.remove(&hash) {
+                        self.marked.insert(hash);
+                    }
+                }
+                self.selected = (self.selected + 1).min(self.rows.len().saturating_sub(1));
+            }
+            Action::Visual => match self.anchor.take() {
+                Some(_) => {
+                    // Keep the range as marks so another one can be added.
+                    let hashes: Vec<String> = self.chosen().into_iter().map(|i| self.commits[i].hash.clone()).collect();
+                    self.marked.extend(hashes);
+                }
+                None => self.anchor = self.selected_commit().map(|commit| commit.hash.clone()),
+            },
+            Action::SelectUp | Action::SelectDown => {
+                if self.anchor.is_none() {
+                    self.anchor = self.selected_commit().map(|commit| commit.hash.clone());
+                }
+                self.selected = if action == Action::SelectUp {
+                    self.selected.saturating_sub(1)
+                } else {
+                    (self.selected + 1).min(self.rows.len().saturating_sub(1))
+                };
+            }
+            Action::CherryPick | Action::Revert if !self.chosen().is_empty() => {
+                let mut commits: Vec<Commit> = self.chosen().into_iter().map(|i| self.commits[i].clone()).collect();
+                let merge = commits
+                    .iter()
+                    .find(|commit| self.repo.parents(&commit.hash).is_ok_and(|parents| parents.len() > 1));
+                if let Some(merge) = merge {
+                    self.fail(format!("{} is a merge; apply it on its own", merge.short_hash));
+                    return Ok(());
+                }
+                if action == Action::CherryPick {
+                    // Oldest first, as git applies a range; reverts go newest first.
+                    commits.reverse();
+                }
+                self.marked.clear();
+                self.anchor = None;
+                self.start_sequence(action, commits, 0);
+            }
+            Action::Undo | Action::Redo => {
+                let result = if action == Action::Undo { self.repo.undo() } else { self.repo.redo() };
+                match result {
+                    Ok(message) => self.notify(message),
+                    Err(err) => self.fail(err.to_string()),
+                }
+                self.reload()?;
+            }
+            Action::Merge => match self.selected_commit().filter(|commit| !commit.is_work_tree()).cloned() {
+                Some(commit) => match self.repo.merge_preview(&commit.hash) {
+                    Ok(preview) => {
+                        let mode = if preview.fast_forward() { MergeMode::FastForward } else { MergeMode::NoFastForward };
+                        self.merge_menu = Some(MergeMenu { commit, preview, mode });
+                    }
+                    Err(err) => self.fail(err.to_string()),
+                },
+                None => self.fail("Select the commit to merge"),
+            },
+            Action::Reset => match self.selected_commit().filter(|commit| !commit.is_work_tree()).cloned() {
+                Some(commit) => match self.repo.reset_preview(&commit.hash) {
+                    Ok(preview) => {
+                        self.reset_menu = Some(ResetMenu {
+                            commit,
+                            preview,
+                            mode: ResetMode::Mixed,
+                        })
+                    }
+                    Err(err) => self.fail(err.to_string()),
+                },
+                None => self.fail("Select the commit to reset to"),
+            },
+            Action::Tag => match self.selected_commit().filter(|commit| !commit.is_work_tree()).cloned() {
+                Some(commit) => {
+                    self.tag_prompt = Some(TagPrompt {
+                        commit,
+                        name: String::new(),
+                        message: None,
+                    })
+                }
+                None => self.fail("Select the commit to tag"),
+            },
+            Action::Tags => {
+                self.tag_list = Some(TagList {
+                    tags: self.repo.tags()?,
+                    selected: 0,
+                });
+            }
+            Action::Checkout
+            | Action::CherryPick
+            | Action::Revert
+            | Action::Branch => {
+                let Some(commit) = self.selected_commit().cloned() else {
+                    return Ok(());
+                };
+                if commit.is_work_tree() {
+                    self.fail("Select a commit first");
+                    return Ok(());
+                }
+                let dangerous = action == Action::Checkout;
+                if dangerous && self.config.confirm_dangerous {
+                    self.confirm = Some((action, commit));
+                } else {
+                    self.operate(action, &commit)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs a git operation on `commit`, reports how it went in the status
+    /// bar and reloads the graph.
+    fn operate(&mut self, action: Action, commit: &Commit) -> Result<(), Box<dyn std::error::Error>> {
+        let result = match action {
+            Action::Checkout => return self.start_checkout(&commit.hash, &commit.refs),
+            Action::CherryPick | Action::Revert if self.repo.parents(&commit.hash)?.len() > 1 => {
+                let mut commit = commit.clone();
+                commit.parents = self.repo.parents(&commit.hash)?;
+                self.mainline = Some((action, commit));
+                return Ok(());
+            }
+            Action::CherryPick | Action::Revert => return self.apply(action, commit, 0),
+            Action::Branch => self.create_branch(commit),
+            _ => return Ok(()),
+        };
+        match result {
+            Ok(text) => self.notify(text),
+            Err(err) => self.fail(err.to_string()),
+        }
+        self.reload()
+    }
+
+    /// Keys in the merge menu: the movement keys or a mode's letter pick it,
+    /// `open` runs it and `quit` cancels. Conflicts open the conflict pane.
+    fn merge_key(&mut self, key: KeyEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let letters: String = MergeMode::ALL.iter().filter_map(|mode| mode.name().chars().next()).collect();
+        let pressed = self.pane_key(key, &MENU_ACTIONS, &letters);
+        let Some(menu) = self.merge_menu.as_mut() else {
+            return Ok(());
+        };
+        let index = MergeMode::ALL.iter().position(|&mode| mode == menu.mode).unwrap_or(0);
+        match pressed {
+            PaneKey::Action(Action::MoveUp) => menu.mode = MergeMode::ALL[index.saturating_sub(1)],
+            PaneKey::Action(Action::MoveDown) => menu.mode = MergeMode::ALL[(index + 1).min(MergeMode::ALL.len() - 1)],
+            PaneKey::Action(Action::Open) => {
+                let Some(menu) = self.merge_menu.take() else {
+                    return Ok(());
+                };
+                let short = &menu.commit.short_hash;
+                let result = match menu.mode {
+                    MergeMode::Squash => self.repo.squash(&menu.commit.hash).map(|()| None),
+                    MergeMode::Rebase => self.repo.rebase_onto(&menu.commit.hash).map(Some),
+                    mode => self.repo.merge(&menu.commit.hash, mode == MergeMode::FastForward).map(Some),
+                };
+                match result {
+                    Ok(None) => self.notify(format!("Squashed {}; the changes are staged for a commit", short)),
+                    Ok(Some(Applied::Committed(hash))) => self.notify(match menu.mode {
+                        MergeMode::FastForward => format!("Fast-forwarded to {}", short),
+                        MergeMode::Rebase => format!("Rebased onto {}", short),
+                        _ => format!("Merged {} as {}", short, &hash[..7]),
+                    }),
+                    Ok(Some(Applied::Conflicts(conflicts))) => {
+                        let paths: Vec<&str> = conflicts.iter().map(|conflict| conflict.path.as_str()).collect();
+                        let operation = if menu.mode == MergeMode::Rebase { "rebase" } else { "merge" };
+                        self.fail(format!("The {} stopped with conflicts in {}", operation, paths.join(", ")));
+                        self.reload()?;
+                        return self.open_conflicts();
+                    }
+                    Err(err) => self.fail(err.to_string()),
+                }
+                return self.reload();
+            }
+            PaneKey::Action(Action::Quit) => self.merge_menu = None,
+            PaneKey::Letter(c) => {
+                if let Some(mode) = MergeMode::ALL.into_iter().find(|mode| mode.name().starts_with(c)) {
+                    menu.mode = mode;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Keys in the reset menu: the movement keys or a mode's letter pick
+    /// it, `open` resets and `quit` cancels. A letter bound to one of
+    /// those actions (`k` for keep by default) does the action instead.
+    fn reset_key(&mut self, key: KeyEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let letters: String = ResetMode::ALL.iter().filter_map(|mode| mode.name().chars().next()).collect();
+        let pressed = self.pane_key(key, &MENU_ACTIONS, &letters);
+        let Some(menu) = self.reset_menu.as_mut() else {
+            return Ok(());
+        };
+        let index = ResetMode::ALL.iter().position(|&mode| mode == menu.mode).unwrap_or(0);
+        match pressed {
+            PaneKey::Action(Action::MoveUp) => menu.mode = ResetMode::ALL[index.saturating_sub(1)],
+            PaneKey::Action(Action::MoveDown) => menu.mode = ResetMode::ALL[(index + 1).min(ResetMode::ALL.len() - 1)],
+            PaneKey::Action(Action::Open) => {
+                let Some(menu) = self.reset_menu.take() else {
+                    return Ok(());
+                };
+                match self.repo.reset(&menu.commit.hash, menu.mode) {
+                    Ok(backup) => {
+                        let mut text = format!("Reset --{} to {}", menu.mode.name(), menu.commit.short_hash);
+                        if let Some(reference) = backup.reference {
+                            text.push_str(&format!("; old HEAD kept as {}", reference));
+                        }
+                        if backup.stashed {
+                            text.push_str("; changes stashed");
+                        }
+                        self.notify(text);
+                    }
+                    Err(err) => self.fail(err.to_string()),
+                }
+                return self.reload();
+            }
+            PaneKey::Action(Action::Quit) => self.reset_menu = None,
+            PaneKey::Letter(c) => {
+                if let Some(mode) = ResetMode::ALL.into_iter().find(|mode| mode.name().starts_with(c)) {
+                    menu.mode = mode;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Cherry-picks or reverts `commit`.
+    fn apply(&mut self, action: Action, commit: &Commit, mainline: u32) -> Result<(), Box<dyn std::error::Error>> {
+        self.start_sequence(action, vec![commit.clone()], mainline);
+        self.advance()
+    }
+
+    /// Queues `commits` to be cherry-picked or reverted in this order.
+    fn start_sequence(&mut self, action: Action, commits: Vec<Commit>, mainline: u32) {
+        if let Some(sequence) = &self.sequence {
+            let (verb, _) = sequence.verbs();
+            self.fail(format!("A {} is still in progress", verb.to_lowercase()));
+            return;
+        }
+        self.sequence = Some(Sequence {
+            action,
+            commits,
+            mainline,
+            done: 0,
+            skipped: 0,
+            paused: false,
+        });
+    }
+
+    /// Applies the next commit of the sequence, pausing it and opening the
+    /// conflict pane on conflicts and dropping it on errors.
+    fn advance(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(sequence) = self.sequence.as_mut() else {
+            return Ok(());
+        };
+        if sequence.paused {
+            return Ok(());
+        }
+        let (verb, done) = sequence.verbs();
+        let count = sequence.commits.len();
+        let Some(commit) = sequence.commits.get(sequence.done).cloned() else {
+            let skipped = sequence.skipped;
+            self.sequence = None;
+            if count > 1 && skipped > 0 {
+                self.notify(format!("{} {} commits, skipped {}", done, count - skipped, skipped));
+            } else if count > 1 {
+                self.notify(format!("{} {} commits", done, count));
+            }
+            return Ok(());
+        };
+        let step = if count > 1 {
+            format!("{} {}/{} ({})", verb, sequence.done + 1, count, commit.short_hash)
+        } else {
+            format!("{} of {}", verb, commit.short_hash)
+        };
+        let result = if sequence.action == Action::Revert {
+            self.repo.revert(&commit.hash, sequence.mainline)
+        } else {
+            self.repo.cherry_pick(&commit.hash, sequence.mainline)
+        };
+        match result {
+            Ok(Applied::Committed(hash)) => {
+                sequence.done += 1;
+                if count == 1 {
+                    self.sequence = None;
+                    self.notify(format!("{} committed as {}", step, &hash[..7]));
+                }
+            }
+            Ok(Applied::Conflicts(conflicts)) => {
+                sequence.paused = true;
+                let paths: Vec<&str> = conflicts.iter().map(|conflict| conflict.path.as_str()).collect();
+                self.fail(format!("{} stopped with conflicts in {}", step, paths.join(", ")));
+                self.reload()?;
+                return self.open_conflicts();
+            }
+            Err(err) => {
+                self.sequence = None;
+                self.fail(format!("{} failed: {}", step, err));
+            }
+        }
+        self.reload()
+    }
+
+    fn notify(&mut self, text: impl Into<String>) {
+        self.message = Some(Message {
+            text: text.into(),
+            error: false,
+            shown: Instant::now(),
+        });
+    }
+
+    fn fail(&mut self, text: impl Into<String>) {
+        self.message = Some(Message {
+            text: text.into().trim_end().replace('\n', " "),
+            error: true,
+            shown: Instant::now(),
+        });
+    }
+
+    /// Edits the filter prompt; the rows follow the text as it is typed.
+    fn edit_filter(&mut self, key: KeyCode) {
+        let Some(input) = self.input.as_mut() else {
+            return;
+        };
+        match key {
+            KeyCode::Enter => {
+                self.input = None;
+                return;
+            }
+            KeyCode::Esc => {
+                self.input = None;
+                self.query.clear();
+            }
+            KeyCode::Backspace => {
+                input.pop();
+                self.query = input.clone();
+            }
+            KeyCode::Char(c) => {
+                input.push(c);
+                self.query = input.clone();
+            }
+            _ => return,
+        }
+        let index = self.selected_commit_index();
+        self.rebuild_rows();
+        if let Some(index) = index {
+            self.select_commit(index);
+        }
+    }
+
+    /// Edits the new tag's name, then its message; enter moves on and
+    /// finally creates the tag, esc cancels.
+    fn tag_prompt_key(&mut self, key: KeyCode) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(prompt) = self.tag_prompt.as_mut() else {
+            return Ok(());
+        };
+        let text = prompt.message.as_mut().unwrap_or(&mut prompt.name);
+        match key {
+            KeyCode::Esc => self.tag_prompt = None,
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            KeyCode::Char(c) => text.push(c),
+            KeyCode::Enter if prompt.message.is_none() && !prompt.name.trim().is_empty() => {
+                prompt.message = Some(String::new());
+            }
+            KeyCode::Enter if prompt.message.is_none() => {}
+            KeyCode::Enter => {
+                let Some(prompt) = self.tag_prompt.take() else {
+                    return Ok(());
+                };
+                let name = prompt.name.trim();
+                let message = prompt.message.as_deref().map(str::trim).filter(|message| !message.is_empty());
+                match self.repo.create_tag(name, &prompt.commit.hash, message) {
+                    Ok(()) => {
+                        let kind = if message.is_some() { "annotated tag" } else { "tag" };
+                        self.notify(format!("Created {} {} at {}", kind, name, prompt.commit.short_hash));
+                    }
+                    Err(err) => self.fail(err.to_string()),
+                }
+                self.reload()?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Keys in the tag list: the usual movement keys, `open` to jump to the
+    /// tag's commit, `d` to delete it, `f` to move it to the commit selected
+    /// in the graph and `quit` to close the list. Keymap actions win over
+    /// the letters.
+    fn tag_list_key(&mut self, key: KeyEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let actions = [
+            Action::MoveUp,
+            Action::MoveDown,
+            Action::PageUp,
+            Action::PageDown,
+            Action::Top,
+            Action::Bottom,
+            Action::Open,
+            Action::Tags,
+            Action::Quit,
+        ];
+        let pressed = self.pane_key(key, &actions, "df");
+        let Some(list) = self.tag_list.as_mut() else {
+            return Ok(());
+        };
+        let last = list.tags.len().saturating_sub(1);
+        let Some(tag) = list.tags.get(list.selected).cloned() else {
+            if let PaneKey::Action(Action::Quit | Action::Tags) = pressed {
+                self.tag_list = None;
+            }
+            return Ok(());
+        };
+
+        match pressed {
+            PaneKey::Action(Action::MoveUp) => list.selected = list.selected.saturating_sub(1),
+            PaneKey::Action(Action::MoveDown) => list.selected = (list.selected + 1).min(last),
+            PaneKey::Action(Action::PageUp) => list.selected = list.selected.saturating_sub(10),
+            PaneKey::Action(Action::PageDown) => list.selected = (list.selected + 10).min(last),
+            PaneKey::Action(Action::Top) => list.selected = 0,
+            PaneKey::Action(Action::Bottom) => list.selected = last,
+            PaneKey::Action(Action::Open) => {
+                self.tag_list = None;
+                let entry = Ref {
+                    name: format!("refs/tags/{}", tag.name),
+                    short: tag.name.clone(),
+                    group: Group::Tags,
+                    target: tag.target.clone(),
+                    date: tag.date,
+                    upstream: None,
+                    ahead_behind: None,
+                    is_head: false,
+                };
+                self.jump_to(&entry)?;
+            }
+            PaneKey::Action(Action::Quit | Action::Tags) => self.tag_list = None,
+            PaneKey::Letter('d') => {
+                let result = self.repo.delete_tag(&tag.name).map(|()| format!("Deleted tag {}", tag.name));
+                self.finish(result)?;
+            }
+            PaneKey::Letter('f') => {
+                let result = match self.selected_commit().filter(|commit| !commit.is_work_tree()).cloned() {
+                    Some(commit) => self
+                        .repo
+                        .move_tag(&tag.name, &commit.hash)
+                        .map(|()| format!("Moved tag {} to {}", tag.name, commit.short_hash)),
+                    None => Err("Select the commit to move it to".into()),
+                };
+                self.finish(result)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Lists every ref, checking the ones the current walk starts from.
+    fn open_picker(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let walks_head = self.filter.refs.is_empty() && self.filter.range.is_none();
+        let refs = self
+            .repo
+            .reference_names()?
+            .into_iter()
+            .map(|name| {
+                let checked = if walks_head {
+                    self.current_branch.as_deref() == Some(name.as_str())
+                } else {
+                    self.filter.refs.selects(&name)
+                };
+                (name, checked)
+            })
+            .collect();
+        self.picker = Some(RefPicker { refs, selected: 0 });
+        Ok(())
+    }
+
+    /// Keys in the ref picker: the usual movement keys, space or `open` to
+    /// toggle a ref, `a` to toggle them all; every toggle re-walks history.
+    fn picker_key(&mut self, key: KeyEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let action = match self.keymap.lookup(&[KeyChord::from(key)]) {
+            Lookup::Action(action) => Some(action),
+            _ => None,
+        };
+        let Some(picker) = self.picker.as_mut() else {
+            return Ok(());
+        };
+        let last = picker.refs.len().saturating_sub(1);
+
+        match (key.code, action) {
+            (KeyCode::Char(' '), _) | (_, Some(Action::Open)) => {
+                if let Some(entry) = picker.refs.get_mut(picker.selected) {
+                    entry.1 = !entry.1;
+                }
+            }
+            (KeyCode::Char('a'), _) => {
+                let check = !picker.refs.iter().all(|(_, checked)| *checked);
+                picker.refs.iter_mut().for_each(|entry| entry.1 = check);
+            }
+            (_, Some(Action::MoveUp)) => picker.selected = picker.selected.saturating_sub(1),
+            (_, Some(Action::MoveDown)) => picker.selected = (picker.selected + 1).min(last),
+            (_, Some(Action::PageUp)) => picker.selected = picker.selected.saturating_sub(10),
+            (_, Some(Action::PageDown)) => picker.selected = (picker.selected + 10).min(last),
+            (_, Some(Action::Top)) => picker.selected = 0,
+            (_, Some(Action::Bottom)) => picker.selected = last,
+            (_, Some(Action::Quit | Action::Refs)) => {
+                self.picker = None;
+                return Ok(());
+            }
+            _ => return Ok(()),
+        }
+
+        let Some(picker) = &self.picker else {
+            return Ok(());
+        };
+        let names = picker
+            .refs
+            .iter()
+            .filter(|(_, checked)| *checked)
+            .map(|(name, _)| name.clone())
+            .collect();
+        let refs = RefSelection {
+            names,
+            ..RefSelection::default()
+        };
+        if refs != self.filter.refs {
+            self.filter.refs = refs;
+            self.reload()?;
+        }
+        Ok(())
+    }
+
+    /// Keys in the focused sidebar: the usual movement keys, `open` to jump
+    /// to the selected ref, `s` to switch between recency and name order,
+    /// `sidebar` to give focus back to the graph and `quit` to close it.
+    /// On a remote branch, `checkout` offers a local branch tracking it.
+    /// On a local branch, `checkout` checks it out, `d` deletes it (`D` even
+    /// when unmerged, asking first under `confirm_dangerous`), `m` renames
+    /// it, `f` moves it to the commit selected in the graph and `u` sets its
+    /// upstream. Keymap actions win over these letters.
+    fn sidebar_key(&mut self, key: KeyEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let actions = [
+            Action::MoveUp,
+            Action::MoveDown,
+            Action::PageUp,
+            Action::PageDown,
+            Action::Top,
+            Action::Bottom,
+            Action::Open,
+            Action::Checkout,
+            Action::Sidebar,
+            Action::Quit,
+        ];
+        let pressed = self.pane_key(key, &actions, "dDmfus");
+        let Some(sidebar) = self.sidebar.as_mut() else {
+            return Ok(());
+        };
+        if let Some((Prompt::ForceDelete, name)) = sidebar.prompt.clone() {
+            sidebar.prompt = None;
+            if matches!(key.code, KeyCode::Char('y' | 'Y')) {
+                let result = self.repo.delete_branch(&name, true).map(|()| format!("Deleted branch {}", name));
+                self.finish(result)?;
+            }
+            return Ok(());
+        }
+        if let Some((_, text)) = sidebar.prompt.as_mut() {
+            match key.code {
+                KeyCode::Esc => sidebar.prompt = None,
+                KeyCode::Enter => {
+                    if let (Some((prompt, text)), Some(entry)) = (sidebar.prompt.take(), sidebar.refs.get(sidebar.selected)) {
+                        let name = entry.short.clone();
+                        let text = text.trim();
+                        let result = match prompt {
+                            Prompt::Rename => self
+                                .repo
+                                .rename_branch(&name, text)
+                                .map(|()| format!("Renamed {} to {}", name, text)),
+                            Prompt::Upstream if text.is_empty() => self
+                                .repo
+                                .set_upstream(&name, None)
+                                .map(|()| format!("{} no longer tracks a branch", name)),
+                            Prompt::Upstream => self
+                                .repo
+                                .set_upstream(&name, Some(text))
+                                .map(|()| format!("{} now tracks {}", name, text)),
+                            Prompt::ForceDelete => return Ok(()),
+                        };
+                        self.finish(result)?;
+                    }
+                }
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Char(c) => text.push(c),
+                _ => {}
+            }
+            return Ok(());
+        }
+        let last = sidebar.refs.len().saturating_sub(1);
+        let branch = sidebar
+            .refs
+            .get(sidebar.selected)
+            .filter(|entry| entry.group == Group::Branches)
+            .cloned();
+        if pressed == PaneKey::Action(Action::Checkout) {
+            let remote = sidebar.refs.get(sidebar.selected).filter(|entry| entry.group == Group::Remotes);
+            if let Some(entry) = remote.cloned() {
+                return self.start_checkout(&entry.target, &[entry.name]);
+            }
+        }
+
+        match pressed {
+            PaneKey::Letter('d' | 'D' | 'm' | 'f' | 'u') | PaneKey::Action(Action::Checkout) if branch.is_none() => {
+                self.fail("Select a local branch");
+            }
+            PaneKey::Letter('D') if self.config.confirm_dangerous => {
+                sidebar.prompt = branch.map(|entry| (Prompt::ForceDelete, entry.short));
+            }
+            PaneKey::Letter(c @ ('d' | 'D')) => {
+                let name = branch.map(|entry| entry.short).unwrap_or_default();
+                let result = self.repo.delete_branch(&name, c == 'D').map(|()| format!("Deleted branch {}", name));
+                match result {
+                    Err(err) if c == 'd' && err.to_string().contains("not merged") => {
+                        self.fail(format!("{}; D deletes it anyway", err));
+                    }
+                    result => self.finish(result)?,
+                }
+            }
+            PaneKey::Letter('m') => {
+                sidebar.prompt = branch.map(|entry| (Prompt::Rename, entry.short));
+            }
+            PaneKey::Letter('u') => {
+                sidebar.prompt = branch.map(|entry| (Prompt::Upstream, entry.upstream.unwrap_or_default()));
+            }
+            PaneKey::Letter('f') => {
+                let name = branch.map(|entry| entry.short).unwrap_or_default();
+                let result = match self.selected_commit().filter(|commit| !commit.is_work_tree()).cloned() {
+                    Some(commit) => self
+                        .repo
+                        .move_branch(&name, &commit.hash)
+                        .map(|()| format!("Moved {} to {}", name, commit.short_hash)),
+                    None => Err("Select the commit to move it to".into()),
+                };
+                self.finish(result)?;
+            }
+            PaneKey::Action(Action::Checkout) => {
+                if let Some(entry) = branch {
+                    self.checkout(entry.target, Checkout::Branch(entry.short), false)?;
+                }
+            }
+            PaneKey::Letter('s') => {
+                sidebar.sort = match sidebar.sort {
+                    Sort::Recency => Sort::Name,
+                    Sort::Name => Sort::Recency,
+                };
+                let name = sidebar.refs.get(sidebar.selected).map(|r| r.name.clone());
+                sidebar.refs = self.repo.refs(sidebar.sort)?;
+                sidebar.selected = name
+                    .and_then(|name| sidebar.refs.iter().position(|r| r.name == name))
+                    .unwrap_or(0);
+            }
+            PaneKey::Action(Action::MoveUp) => sidebar.selected = sidebar.selected.saturating_sub(1),
+            PaneKey::Action(Action::MoveDown) => sidebar.selected = (sidebar.selected + 1).min(last),
+            PaneKey::Action(Action::PageUp) => sidebar.selected = sidebar.selected.saturating_sub(10),
+            PaneKey::Action(Action::PageDown) => sidebar.selected = (sidebar.selected + 10).min(last),
+            PaneKey::Action(Action::Top) => sidebar.selected = 0,
+            PaneKey::Action(Action::Bottom) => sidebar.selected = last,
+            PaneKey::Action(Action::Open) => {
+                if let Some(entry) = sidebar.refs.get(sidebar.selected).cloned() {
+                    sidebar.focused = false;
+                    self.jump_to(&entry)?;
+                }
+            }
+            PaneKey::Action(Action::Sidebar) => sidebar.focused = false,
+            PaneKey::Action(Action::Quit) => self.sidebar = None,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Reports the outcome of a branch or tag operation and reloads.
+    fn finish(&mut self, result: Result<String, Box<dyn std::error::Error>>) -> Result<(), Box<dyn std::error::Error>> {
+        match result {
+            Ok(text) => self.notify(text),
+            Err(err) => self.fail(err.to_string()),
+        }
+        self.reload()
+    }
+
+    /// Selects the commit `entry` points at, adding the ref to the walk when
+    /// the commit isn't shown yet and clearing a filter that hides it.
+    fn jump_to(&mut self, entry: &Ref) -> Result<(), Box<dyn std::error::Error>> {
+        let find = |commits: &[Commit]| commits.iter().position(|commit| commit.hash == entry.target);
+        let mut index = find(&self.commits);
+        if index.is_none() && entry.group != Group::Stashes {
+            let mut refs = self.filter.refs.clone();
+            if refs.is_empty() && self.filter.range.is_none() {
+                refs.names.push("HEAD".to_string());
+            }
+            refs.names.push(entry.name.clone());
+            self.filter.refs = refs;
+            self.reload()?;
+            index = find(&self.commits);
+        }
+        let Some(index) = index else {
+            return Ok(());
+        };
+        if !self.matches_query(index) {
+            self.query.clear();
+            self.rebuild_rows();
+        }
+        self.select_commit(index);
+        Ok(())
+    }
+
+    /// Keys in the rebase planner: movement picks a step, the todo letters
+    /// set its command (`r` asks for the new message), `J`/`K` move it down
+    /// or up, `open` runs the rebase and `quit` cancels.
+    fn planner_key(&mut self, key: KeyEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(mut planner) = self.planner.take() else {
+            return Ok(());
+        };
+        if let Some(input) = planner.input.as_mut() {
+            match key.code {
+                KeyCode::Esc => planner.input = None,
+                KeyCode::Enter if !input.trim().is_empty() => {
+                    let step = &mut planner.plan.steps[planner.selected];
+                    step.command = Command::Reword;
+                    step.message = planner.input.take();
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            }
+            self.update_preview(&mut planner);
+            self.planner = Some(planner);
+            return Ok(());
+        }
+
+        let last = planner.plan.steps.len().saturating_sub(1);
+        let actions = [Action::MoveUp, Action::MoveDown, Action::Top, Action::Bottom, Action::Open, Action::Quit];
+        match self.pane_key(key, &actions, "pesfdrKJ") {
+            PaneKey::Action(Action::MoveUp) => planner.selected = planner.selected.saturating_sub(1),
+            PaneKey::Action(Action::MoveDown) => planner.selected = (planner.selected + 1).min(last),
+            PaneKey::Action(Action::Top) => planner.selected = 0,
+            PaneKey::Action(Action::Bottom) => planner.selected = last,
+            PaneKey::Action(Action::Open) => {
+                if let Err(err) = planner.plan.validate() {
+                    self.fail(err);
+                    self.planner = Some(planner);
+                    return Ok(());
+                }
+                match self.repo.rebase(&planner.plan) {
+                    Ok(()) if self.repo.operation() == Some("rebase") => {
+                        self.notify("Rebase stopped; finish it in a shell with git rebase --continue")
+                    }
+                    Ok(()) => self.notify(format!("Rebased onto {}", &planner.plan.base[..7])),
+                    Err(err) => match self.take_back_fixup(planner.fixup.as_deref()) {
+                        Ok(true) => self.fail(format!("{}; the fixup's changes are staged again", err)),
+                        Ok(false) => self.fail(err.to_string()),
+                        Err(undo) => self.fail(format!("{}; taking back the fixup commit failed: {}", err, undo)),
+                    },
+                }
+                return self.reload();
+            }
+            PaneKey::Action(Action::Quit) => {
+                return match self.take_back_fixup(planner.fixup.as_deref()) {
+                    Ok(false) => Ok(()),
+                    result => self.finish(result.map(|_| "Cancelled; the fixup's changes are staged again".to_string())),
+                };
+            }
+            PaneKey::Letter('r') => {
+                let step = &planner.plan.steps[planner.selected];
+                planner.input = Some(step.message.clone().unwrap_or_else(|| step.subject.clone()));
+            }
+            PaneKey::Letter('K') => planner.selected = planner.plan.move_step(planner.selected, true),
+            PaneKey::Letter('J') => planner.selected = planner.plan.move_step(planner.selected, false),
+            PaneKey::Letter(c) => {
+                if let Some(command) = Command::from_key(c) {
+                    planner.plan.steps[planner.selected].command = command;
+                }
+            }
+            _ => {}
+        }
+        self.update_preview(&mut planner);
+        self.planner = Some(planner);
+        Ok(())
+    }
+
+    fn open_planner(&mut self, plan: Plan, selected: usize) {
+        let mut planner = Planner {
+            plan,
+            selected,
+            preview: Vec::new(),
+            input: None,
+            fixup: None,
+        };
+        self.update_preview(&mut planner);
+        self.planner = Some(planner);
+    }
+
+    /// Rewords `commit` or fixes it up with the staged changes. HEAD is
+    /// amended directly; an older commit opens the rebase planner with the
+    /// reword or the fixup arranged, so the new history is previewed before
+    /// it runs. Cancelling the planner takes the `fixup!` commit back.
+    fn rewrite(&mut self, action: Action, commit: Commit) -> Result<(), Box<dyn std::error::Error>> {
+        let is_head = self.head.as_deref() == Some(commit.hash.as_str());
+        if action == Action::Fixup && self.status.staged == 0 {
+            self.fail(format!("Stage the changes to fold into {} first", commit.short_hash));
+            return Ok(());
+        }
+        if !is_head {
+            if let Err(err) = self.rewrite_plan(&commit) {
+                self.fail(err.to_string());
+                return Ok(());
+            }
+        }
+        if action == Action::Reword {
+            self.editing = Some(commit);
+            return Ok(());
+        }
+        if is_head {
+            let result = self
+                .repo
+                .amend()
+                .map(|_| format!("Amended {} with the staged changes", commit.short_hash));
+            return self.finish(result);
+        }
+        if self.status.unstaged > 0 {
+            self.fail("Commit or stash the unstaged changes first; the rebase needs a clean tree");
+            return Ok(());
+        }
+        let subject = commit.message.lines().next().unwrap_or("");
+        let fixup = match self.repo.commit(&format!("fixup! {}", subject)) {
+            Ok(fixup) => fixup,
+            Err(err) => {
+                self.fail(err.to_string());
+                return Ok(());
+            }
+        };
+        self.reload()?;
+        let mut plan = match self.rewrite_plan(&commit) {
+            Ok(plan) => plan,
+            Err(err) => {
+                let result = self.take_back_fixup(Some(&fixup)).and(Err(err));
+                return self.finish(result);
+            }
+        };
+        plan.autosquash();
+        let selected = plan.steps.iter().position(|step| step.hash == commit.hash).unwrap_or(0);
+        self.open_planner(plan, selected);
+        if let Some(planner) = self.planner.as_mut() {
+            planner.fixup = Some(fixup);
+        }
+        Ok(())
+    }
+
+    /// Takes back the planner's `fixup!` commit, leaving its changes staged,
+    /// while it is still HEAD. Returns whether there was one to take back.
+    fn take_back_fixup(&self, fixup: Option<&str>) -> Result<bool, Box<dyn std::error::Error>> {
+        match fixup {
+            Some(fixup) if self.repo.head_hash().ok().as_deref() == Some(fixup) => {
+                self.repo.uncommit()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// The rebase plan from `commit`'s parent up to HEAD, which rewriting
+    /// `commit` takes.
+    fn rewrite_plan(&self, commit: &Commit) -> Result<Plan, Box<dyn std::error::Error>> {
+        if let Some(operation) = self.operation {
+            return Err(format!("Finish the {} first", operation).into());
+        }
+        let parents = self.repo.parents(&commit.hash)?;
+        let parent = parents
+            .first()
+            .ok_or("The root commit can only be rewritten with git rebase -i --root")?;
+        let plan = self.repo.rebase_plan(parent)?;
+        if !plan.steps.iter().any(|step| step.hash == commit.hash) {
+            return Err(format!("{} isn't on the checked-out branch", commit.short_hash).into());
+        }
+        Ok(plan)
+    }
+
+    /// Hands the terminal to the user's editor to reword the pending
+    /// commit, then amends HEAD or plans the rebase with the new message.
+    fn run_editor<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(commit) = self.editing.take() else {
+            return Ok(());
+        };
+        let original = self.repo.get_commit_details(&commit.hash)?.message;
+        let help = format!(
+            "Enter the new message for {}. Lines starting with '#' are\nignored, and an empty message cancels.",
+            commit.short_hash
+        );
+        disable_raw_mode()?;
+        execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
+        let message = editor::edit(self.repo.workdir()?, &self.repo.message_path(), &original, &help);
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+        terminal.clear()?;
+
+        let message = match message {
+            Ok(message) if message.is_empty() => {
+                self.notify("Empty message; nothing reworded");
+                return Ok(());
+            }
+            Ok(message) if message == editor::cleanup(&original) => {
+                self.notify("Message unchanged");
+                return Ok(());
+            }
+            Ok(message) => message,
+            Err(err) => {
+                self.fail(err.to_string());
+                return Ok(());
+            }
+        };
+        if self.head.as_deref() == Some(commit.hash.as_str()) {
+            let result = self
+                .repo
+                .reword_head(&message)
+                .map(|_| format!("Reworded {}", commit.short_hash));
+            return self.finish(result);
+        }
+        match self.rewrite_plan(&commit) {
+            Ok(mut plan) => {
+                let selected = plan.steps.iter().position(|step| step.hash == commit.hash).unwrap_or(0);
+                plan.steps[selected].command = Command::Reword;
+                plan.steps[selected].message = Some(message);
+                self.open_planner(plan, selected);
+            }
+            Err(err) => self.fail(err.to_string()),
+        }
+        Ok(())
+    }
+
+    /// Lays out the history `planner`'s plan would produce.
+    fn update_preview(&self, planner: &mut Planner) {
+        let base = self.commits.iter().find(|commit| commit.hash == planner.plan.base);
+        let mut preview = planner.plan.preview(base);
+        graph::layout(&mut preview);
+        planner.preview = preview;
+    }
+
+    /// Keys in the working-tree pane: movement picks a file, page keys
+    /// scroll its diff, space stages or unstages it and `c` starts a commit;
+    /// while the message is typed, enter commits and esc cancels.
+    fn work_tree_key(&mut self, key: KeyEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let actions = [Action::MoveUp, Action::MoveDown, Action::PageUp, Action::PageDown, Action::Open, Action::Quit];
+        let pressed = self.pane_key(key, &actions, " c");
+        let Some(pane) = self.work_tree.as_mut() else {
+            return Ok(());
+        };
+        if let Some(message) = pane.message.as_mut() {
+            match key.code {
+                KeyCode::Esc => pane.message = None,
+                KeyCode::Enter if !message.trim().is_empty() => {
+                    let message = pane.message.take().unwrap_or_default();
+                    match self.repo.commit(&message) {
+                        Ok(hash) => self.notify(format!("Committed {}", &hash[..7])),
+                        Err(err) => self.fail(err.to_string()),
+                    }
+                    return self.reload();
+                }
+                KeyCode::Backspace => {
+                    message.pop();
+                }
+                KeyCode::Char(c) => message.push(c),
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        let last = pane.files.len().saturating_sub(1);
+        let selected = pane.selected;
+        match pressed {
+            PaneKey::Action(Action::MoveUp) => pane.selected = selected.saturating_sub(1),
+            PaneKey::Action(Action::MoveDown) => pane.selected = (selected + 1).min(last),
+            PaneKey::Action(Action::PageUp) => pane.scroll = pane.scroll.saturating_sub(10),
+            PaneKey::Action(Action::PageDown) => {
+                pane.scroll = (pane.scroll + 10).min(pane.diff.len().saturating_sub(1));
+            }
+            PaneKey::Action(Action::Quit | Action::Open) => {
+                self.work_tree = None;
+                return Ok(());
+            }
+            PaneKey::Letter(' ') => {
+                let Some(file) = pane.files.get(selected).cloned() else {
+                    return Ok(());
+                };
+                let result = if file.unstaged.is_some() {
+                    self.repo.stage(&file.path)
+                } else {
+                    self.repo.unstage(&file.path)
+                };
+                if let Err(err) = result {
+                    self.fail(err.to_string());
+                }
+                return self.reload();
+            }
+            PaneKey::Letter('c') => {
+                if pane.files.iter().any(|file| file.staged.is_some()) {
+                    pane.message = Some(String::new());
+                } else {
+                    self.fail("Nothing staged to commit");
+                }
+                return Ok(());
+            }
+            _ => return Ok(()),
+        }
+        if self.work_tree.as_ref().is_some_and(|pane| pane.selected != selected) {
+            self.refresh_work_tree()?;
+        }
+        Ok(())
+    }
+
+    /// Reads the changed files again, keeping the selected one, and loads
+    /// its staged and unstaged diff; closes the pane once the tree is clean.
+    fn refresh_work_tree(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let files = self.repo.changed_files()?;
+        let Some(pane) = self.work_tree.as_mut() else {
+            return Ok(());
+        };
+        if files.is_empty() {
+            self.work_tree = None;
+            return Ok(());
+        }
+        let path = pane.files.get(pane.selected).map(|file| file.path.clone());
+        if let Some(index) = path.and_then(|path| files.iter().position(|file| file.path == path)) {
+            pane.selected = index;
+        }
+        pane.selected = pane.selected.min(files.len() - 1);
+        pane.files = files;
+
+        let file = &pane.files[pane.selected];
+        let mut diff = Vec::new();
+        if file.staged.is_some() {
+            diff.extend(self.repo.diff_file(&file.path, true)?);
+        }
+        if file.unstaged.is_some() {
+            diff.extend(self.repo.diff_file(&file.path, false)?);
+        }
+        pane.diff = diff;
+        pane.scroll = 0;
+        Ok(())
+    }
+
+    fn open_conflicts(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.work_tree = None;
+        self.conflicts = Some(ConflictPane {
+            conflicts: Vec::new(),
+            selected: 0,
+            shown: None,
+            lines: Vec::new(),
+            scroll: 0,
+        });
+        self.refresh_conflicts()
+    }
+
+    /// Keys in the conflict pane: movement picks a file, page keys scroll
+    /// it, `o`/`t` resolve it with our or their version, space marks it
+    /// resolved as it is, and `c`, `s` and `a` continue, skip or abort the
+    /// cherry-pick or revert.
+    fn conflicts_key(&mut self, key: KeyEvent) -> Result<(), Box<dyn std::error::Error>> {
+        let actions = [Action::MoveUp, Action::MoveDown, Action::PageUp, Action::PageDown, Action::Open, Action::Quit];
+        let pressed = self.pane_key(key, &actions, "ot csa");
+        let Some(pane) = self.conflicts.as_mut() else {
+            return Ok(());
+        };
+        let last = pane.conflicts.len().saturating_sub(1);
+        let selected = pane.selected;
+        let path = pane.conflicts.get(selected).map(|conflict| conflict.path.clone());
+        let result = match pressed {
+            PaneKey::Action(Action::MoveUp) => {
+                pane.selected = selected.saturating_sub(1);
+                return self.refresh_conflicts();
+            }
+            PaneKey::Action(Action::MoveDown) => {
+                pane.selected = (selected + 1).min(last);
+                return self.refresh_conflicts();
+            }
+            PaneKey::Action(Action::PageUp) => {
+                pane.scroll = pane.scroll.saturating_sub(10);
+                return Ok(());
+            }
+            PaneKey::Action(Action::PageDown) => {
+                pane.scroll = (pane.scroll + 10).min(pane.lines.len().saturating_sub(1));
+                return Ok(());
+            }
+            PaneKey::Action(Action::Quit | Action::Open) => {
+                self.conflicts = None;
+                return Ok(());
+            }
+            PaneKey::Letter(c @ ('o' | 't' | ' ')) => {
+                let Some(path) = path else {
+                    return Ok(());
+                };
+                match c {
+                    ' ' => self.repo.stage(&path),
+                    c => self.repo.take_side(&path, c == 'o'),
+                }
+                .map(|()| format!("Resolved {}", path))
+            }
+            PaneKey::Letter('c') => self.repo.continue_operation().map(|hash| {
+                self.resume_sequence();
+                format!("Committed {}", &hash[..7])
+            }),
+            PaneKey::Letter('s') => {
+                let text = match self.operation {
+                    Some("rebase") => "Skipped the commit".to_string(),
+                    operation => format!("Skipped the {}", operation.unwrap_or("operation")),
+                };
+                self.repo.skip_operation().map(|()| {
+                    if let Some(sequence) = self.sequence.as_mut().filter(|sequence| sequence.paused) {
+                        sequence.skipped += 1;
+                    }
+                    self.resume_sequence();
+                    text
+                })
+            }
+            PaneKey::Letter('a') => {
+                let mut text = format!("Aborted the {}", self.operation.unwrap_or("operation"));
+                if let Some(sequence) = self.sequence.as_ref().filter(|sequence| sequence.commits.len() > 1) {
+                    text.push_str(&format!(" after {} of {} commits", sequence.done, sequence.commits.len()));
+                }
+                self.repo.abort_operation().map(|()| {
+                    self.sequence = None;
+                    text
+                })
+            }
+            _ => return Ok(()),
+        };
+        match result {
+            Ok(text) => self.notify(text),
+            Err(err) => self.fail(err.to_string()),
+        }
+        self.reload()
+    }
+
+    /// Moves a paused sequence on to its next commit.
+    fn resume_sequence(&mut self) {
+        if let Some(sequence) = self.sequence.as_mut().filter(|sequence| sequence.paused) {
+            sequence.done += 1;
+            sequence.paused = false;
+        }
+    }
+
+    /// The commits marked or inside the range selection, as indices into
+    /// `commits` (newest first).
+    fn chosen(&self) -> Vec<usize> {
+        let anchor = self
+            .anchor
+            .as_ref()
+            .and_then(|hash| self.commits.iter().position(|commit| &commit.hash == hash));
+        let range = anchor
+            .zip(self.selected_commit_index())
+            .map(|(anchor, selected)| anchor.min(selected)..=anchor.max(selected));
+        (0..self.commits.len())
+            .filter(|&i| {
+                let commit = &self.commits[i];
+                !commit.is_work_tree()
+                    && (self.marked.contains(&commit.hash) || range.as_ref().is_some_and(|range| range.contains(&i)))
+            })
+            .collect()
+    }
+
+    /// Reads the conflicts again, keeping the selected file, and loads its
+    /// working copy scrolled to the first marker; closes the pane once the
+    /// cherry-pick or revert is over.
+    fn refresh_conflicts(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if !matches!(self.repo.operation(), Some("cherry-pick" | "revert" | "merge" | "rebase")) {
+            self.conflicts = None;
+            return Ok(());
+        }
+        let conflicts = self.repo.conflicts()?;
+        let workdir = self.repo.workdir()?.to_path_buf();
+        let Some(pane) = self.conflicts.as_mut() else {
+            return Ok(());
+        };
+        let path = pane.conflicts.get(pane.selected).map(|conflict| conflict.path.clone());
+        if let Some(index) = path.and_then(|path| conflicts.iter().position(|conflict| conflict.path == path)) {
+            pane.selected = index;
+        }
+        pane.selected = pane.selected.min(conflicts.len().saturating_sub(1));
+        pane.conflicts = conflicts;
+
+        let Some(conflict) = pane.conflicts.get(pane.selected) else {
+            pane.lines.clear();
+            return Ok(());
+        };
+        pane.lines = match std::fs::read(workdir.join(&conflict.path)) {
+            Ok(content) => String::from_utf8_lossy(&content).lines().map(str::to_string).collect(),
+            Err(_) => vec!["(not in the working tree)".to_string()],
+        };
+        if pane.shown.as_ref() != Some(&conflict.path) || pane.scroll >= pane.lines.len() {
+            let marker = pane.lines.iter().position(|line| line.starts_with("<<<<<<<"));
+            pane.scroll = marker.unwrap_or(0).saturating_sub(2);
+        }
+        pane.shown = Some(conflict.path.clone());
+        Ok(())
+    }
+
+    /// Reads the repository state again, walks history for the current
+    /// filter and lays it out, keeping the selected commit when it's still
+    /// shown.
+    fn reload(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let keep = self
+            .selected_commit_index()
+            .map(|index| self.commits[index].hash.clone());
+        self.head = self.repo.head_hash().ok();
+        self.current_branch = self.repo.get_current_branch().ok();
+        self.status = self.repo.status_counts()?;
+        self.operation = self.repo.operation();
+        if let Some(sidebar) = &mut self.sidebar {
+            sidebar.refs = self.repo.refs(sidebar.sort)?;
+            sidebar.selected = sidebar.selected.min(sidebar.refs.len().saturating_sub(1));
+        }
+        if let Some(list) = &mut self.tag_list {
+            list.tags = self.repo.tags()?;
+            list.selected = list.selected.min(list.tags.len().saturating_sub(1));
+        }
+
+        let mut commits = self.repo.get_commits(&self.filter)?;
+        add_work_tree(&mut commits, self.head.as_deref(), &self.status);
+        self.segments = graph::layout(&mut commits);
+        if let Some(head) = &self.head {
+            graph::mark_head(&mut commits, head);
+        }
+        self.commits = commits;
+        self.tracking = self.repo.tracking()?;
+        self.folded.clear();
+        self.panned_for = None;
+        self.rebuild_rows();
+        let index = keep.and_then(|hash| self.commits.iter().position(|commit| commit.hash == hash));
+        match index {
+            Some(index) => self.select_commit(index),
+            None => self.selected = 0,
+        }
+        if self.work_tree.is_some() {
+            self.refresh_work_tree()?;
+        }
+        if self.conflicts.is_some() {
+            self.refresh_conflicts()?;
+        }
+        Ok(())
+    }
+
+    fn keys_label(&self, action: Action) -> String {
+        self.keymap.keys_for(action).join("/")
+    }
+
+    /// Resolves `key` in a pane: the keymap first, for the `actions` the
+    /// pane handles, then the pane's own `letters`. A binding such as
+    /// `move_down: d` thus moves instead of running the pane's `d`.
+    fn pane_key(&self, key: KeyEvent, actions: &[Action], letters: &str) -> PaneKey {
+        if let Lookup::Action(action) = self.keymap.lookup(&[KeyChord::from(key)]) {
+            if actions.contains(&action) {
+                return PaneKey::Action(action);
+            }
+        }
+        // Shift is part of a letter; ctrl and alt chords never are.
+        let plain = key.modifiers.difference(KeyModifiers::SHIFT).is_empty();
+        match key.code {
+            KeyCode::Char(c) if plain && letters.contains(c) => PaneKey::Letter(c),
+            _ => PaneKey::Other,
+        }
+    }
+
+    /// The first letters of a menu's modes, leaving out those the keymap
+    /// binds to moving, opening or quitting.
+    fn mode_letters(&self, names: &[&str]) -> String {
+        let letters: Vec<String> = names
+            .iter()
+            .filter_map(|name| name.chars().next())
+            .filter(|&c| {
+                let chord = KeyChord::new(KeyCode::Char(c), KeyModifiers::NONE);
+                !matches!(self.keymap.lookup(&[chord]), Lookup::Action(action) if MENU_ACTIONS.contains(&action))
+            })
+            .map(String::from)
+            .collect();
+        letters.join("/")
+    }
+
+    /// The commit under the cursor, or `None` on a folded segment.
+    fn selected_commit(&self) -> Option<&Commit> {
+        match self.rows.get(self.selected) {
+            Some(Row::Commit(index)) => self.commits.get(*index),
+            _ => None,
+        }
+    }
+
+    /// Moves the cursor to the commit at `index`, or to the fold hiding it.
+    fn select_commit(&mut self, index: usize) {
+        let position = self.rows.iter().position(|row| match *row {
+            Row::Commit(i) => i == index,
+            Row::Fold(segment) => graph::segment_rows(&self.segments, segment).contains(&index),
+        });
+        if let Some(position) = position {
+            self.selected = position;
+        }
+    }
+
+    /// Folds the branch merged by the selected commit, or the branch it sits
+    /// on; expands the segment when a fold is selected.
+    fn toggle_fold(&mut self) {
+        let index = match self.rows.get(self.selected) {
+            Some(Row::Fold(segment)) => {
+                self.unfold(*segment);
+                return;
+            }
+            Some(Row::Commit(index)) => *index,
+            None => return,
+        };
+
+        let segment = self
+            .segments
+            .iter()
+            .find(|s| s.merge_row == index && !s.rows.is_empty())
+            .or_else(|| self.segments.iter().find(|s| s.rows.contains(&index)))
+            .map(|s| s.id);
+        if let Some(segment) = segment {
+            self.folded.insert(segment);
+            self.rebuild_rows();
+            self.select_commit(index);
+        }
+    }
+
+    fn toggle_fold_all(&mut self) {
+        let index = self.selected_commit_index();
+        if self.folded.is_empty() {
+            self.folded = self
+                .segments
+                .iter()
+                .filter(|s| !s.rows.is_empty())
+                .map(|s| s.id)
+                .collect();
+        } else {
+            self.folded.clear();
+        }
+        self.rebuild_rows();
+        if let Some(index) = index {
+            self.select_commit(index);
+        }
+    }
+
+    fn unfold(&mut self, segment: usize) {
+        self.folded.remove(&segment);
+        // Nested folds inside the segment open with it.
+        let segments = &self.segments;
+        self.folded.retain(|&s| !graph::is_within(segments, s, segment));
+        self.rebuild_rows();
+        if let Some(&first) = self.segments[segment].rows.first() {
+            self.select_commit(first);
+        }
+    }
+
+    fn selected_commit_index(&self) -> Option<usize> {
+        match self.rows.get(self.selected) {
+            Some(Row::Commit(index)) => Some(*index),
+            Some(Row::Fold(segment)) => graph::segment_rows(&self.segments, *segment).first().copied(),
+            None => None,
+        }
+    }
+
+    /// Recomputes the visible rows, collapsing each folded segment into a
+    /// single summary row placed where its newest commit would be.
+    fn rebuild_rows(&mut self) {
+        let mut hidden = vec![None; self.commits.len()];
+        // An outer fold already hides everything nested inside it.
+        let outermost = self.folded.iter().copied().filter(|&segment| {
+            !self
+                .folded
+                .iter()
+                .any(|&other| other != segment && graph::is_within(&self.segments, segment, other))
+        });
+        for segment in outermost {
+            for row in graph::segment_rows(&self.segments, segment) {
+                hidden[row] = Some(segment);
+            }
+        }
+
+        let mut emitted = HashSet::new();
+        self.rows = (0..self.commits.len())
+            .filter(|&index| self.matches_query(index))
+            .filter_map(|index| match hidden[index] {
+                Some(segment) if emitted.insert(segment) => Some(Row::Fold(segment)),
+                Some(_) => None,
+                None => Some(Row::Commit(index)),
+            })
+            .collect();
+        self.selected = self.selected.min(self.rows.len().saturating_sub(1));
+    }
+
+    /// Whether the commit at `index` passes the filter: the query appears in
+    /// its subject, author or hash, ignoring case.
+    fn matches_query(&self, index: usize) -> bool {
+        if self.query.is_empty() {
+            return true;
+        }
+        let query = self.query.to_lowercase();
+        let commit = &self.commits[index];
+        [&commit.message, &commit.author, &commit.hash]
+            .iter()
+            .any(|field| field.to_lowercase().contains(&query))
+    }
+
+    fn show_commit_details(&mut self, index: usize) {
+        let commit = &self.commits[index];
+        let parents: Vec<&str> = commit.parents.iter().map(|p| &p[..p.len().min(8)]).collect();
+        let text = format!(
+            "{} {} <{}> {} parents: {}",
+            commit.short_hash,
+            commit.author,
+            commit.email,
+            commit.date.format("%Y-%m-%d %H:%M:%S %Z"),
+            if parents.is_empty() { "none".to_string() } else { parents.join(", ") }
+        );
+        self.notify(text);
+    }
+
+    /// Checks out the commit `hash` with `refs` on it: its local branch when
+    /// it has one, otherwise asking whether to create a local branch for a
+    /// remote one, detaching HEAD at commits with neither.
+    fn start_checkout(&mut self, hash: &str, refs: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(branch) = refs.iter().find_map(|name| name.strip_prefix("refs/heads/")) {
+            return self.checkout(hash.to_string(), Checkout::Branch(branch.to_string()), false);
+        }
+        let track = refs
+            .iter()
+            .filter_map(|name| name.strip_prefix("refs/remotes/"))
+            .filter(|upstream| !upstream.ends_with("/HEAD"))
+            .find_map(|upstream| Some((upstream.to_string(), self.repo.track_name(upstream).ok()??)));
+        match track {
+            Some((upstream, name)) => {
+                self.checkout_prompt = Some(CheckoutPrompt::Track {
+                    hash: hash.to_string(),
+                    upstream,
+                    name,
+                });
+                Ok(())
+            }
+            None => self.checkout(hash.to_string(), Checkout::Detach(hash.to_string()), false),
+        }
+    }
+
+    /// Runs a checkout of the commit `hash`. When it would overwrite local
+    /// changes it asks to stash them first, unless `stash` already says so.
+    fn checkout(&mut self, hash: String, checkout: Checkout, stash: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let blockers = match self.repo.checkout_blockers(&hash) {
+            Ok(blockers) => blockers,
+            Err(err) => return self.finish(Err(err)),
+        };
+        if !blockers.is_empty() && !stash {
+            self.checkout_prompt = Some(CheckoutPrompt::Stash { hash, checkout, blockers });
+            return Ok(());
+        }
+        let stashed = !blockers.is_empty();
+        if stashed {
+            if let Err(err) = self.repo.stash_for(&hash) {
+                return self.finish(Err(err));
+            }
+        }
+        let result = match self.run_checkout(&checkout) {
+            Ok(text) if stashed => Ok(format!("{}; your changes are stashed (git stash pop)", text)),
+            Err(err) if stashed => Err(format!("{}; your changes are stashed (git stash pop)", err).into()),
+            result => result,
+        };
+        self.finish(result)
+    }
+
+    fn run_checkout(&self, checkout: &Checkout) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(match checkout {
+            Checkout::Branch(name) => {
+                self.repo.checkout(name)?;
+                format!("Checked out {}", name)
+            }
+            Checkout::Track(upstream) => {
+                let name = self.repo.track(upstream)?;
+                format!("Checked out {}, tracking {}", name, upstream)
+            }
+            Checkout::Detach(hash) => {
+                self.repo.checkout(hash)?;
+                format!("Checked out {}; HEAD is detached", &hash[..7.min(hash.len())])
+            }
+        })
+    }
+
+    fn create_branch(&self, commit: &Commit) -> Result<String, Box<dyn std::error::Error>> {
+        let branch_name = format!("branch-{}", commit.short_hash);
+        self.repo.create_branch(&branch_name, &commit.hash)?;
+        Ok(format!("Created branch {}", branch_name))
+    }
+}
+
+/// Puts the uncommitted-changes row on top when the tree is dirty and HEAD
+/// is among `commits`.
+fn add_work_tree(commits: &mut Vec<Commit>, head: Option<&str>, status: &StatusCounts) {
+    let Some(head) = head else {
+        return;
+    };
+    if !status.is_clean() && commits.iter().any(|commit| commit.hash == head) {
+        commits.insert(0, Commit::work_tree(head, status));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::KeyList;
+    use git2::Repository as Git2Repository;
+    use std::path::PathBuf;
+
+    /// A repository at a fresh temporary directory with one commit per
+    /// subject, oldest first.
+    fn repo_with(name: &str, subjects: &[&str]) -> (PathBuf, Repository) {
+        let dir = std::env::temp_dir().join(format!("gittree-ui-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let git = Git2Repository::init(&dir).unwrap();
+        let mut config = git.config().unwrap();
+        config.set_str("user.name", "Alice").unwrap();
+        config.set_str("user.email", "alice@example.com").unwrap();
+        let repo = Repository::new(dir.to_str().unwrap()).unwrap();
+        for subject in subjects {
+            std::fs::write(dir.join(subject), subject).unwrap();
+            repo.stage(subject).unwrap();
+            repo.commit(subject).unwrap();
+        }
+        (dir, repo)
+    }
+
+    fn new_app<'a>(repo: &'a Repository, preset: &str) -> App<'a> {
+        bound_app(repo, preset, &[])
+    }
+
+    /// An app whose keymap also binds each `(action, key)` pair.
+    fn bound_app<'a>(repo: &'a Repository, preset: &str, bindings: &[(&str, &str)]) -> App<'a> {
+        let mut config = Config {
+            confirm_dangerous: false,
+            ..Config::default()
+        };
+        config.keys.preset = Some(preset.to_string());
+        for (action, key) in bindings {
+            config.keys.bindings.insert(action.to_string(), KeyList::One(key.to_string()));
+        }
+        let mut filter = FilterOptions::default();
+        filter.refs.all = true;
+        let commits = repo.get_commits(&filter).unwrap();
+        App::new(repo, config, filter, commits)
+    }
+
+    fn press(app: &mut App, code: KeyCode, modifiers: KeyModifiers) {
+        app.handle_key_press(KeyEvent::new(code, modifiers)).unwrap();
+    }
+
+    /// The subjects of HEAD's history, newest first.
+    fn history(repo: &Repository) -> Vec<String> {
+        let commits = repo.get_commits(&FilterOptions::default()).unwrap();
+        commits.iter().map(|commit| commit.message.lines().next().unwrap().to_string()).collect()
+    }
+
+    /// Runs the sequence until it ends or pauses on a conflict.
+    fn run_sequence(app: &mut App) {
+        while app.sequence.as_ref().is_some_and(|sequence| !sequence.paused) {
+            app.advance().unwrap();
+        }
+    }
+
+    fn select(app: &mut App, subject: &str) {
+        let index = app.commits.iter().position(|commit| commit.message.starts_with(subject)).unwrap();
+        app.select_commit(index);
+    }
+
+    #[test]
+    fn test_chosen_commits_apply_in_order() {
+        let (dir, repo) = repo_with("sequence", &["base", "one", "two", "three"]);
+        let three = repo.head_hash().unwrap();
+        repo.create_branch("side", &three).unwrap();
+        let base = repo.get_commits(&FilterOptions::default()).unwrap()[3].hash.clone();
+        repo.reset(&base, ResetMode::Hard).unwrap();
+
+        // A range from three down to one is picked oldest first
+        let mut app = new_app(&repo, "default");
+        select(&mut app, "three");
+        press(&mut app, KeyCode::Char('v'), KeyModifiers::NONE);
+        press(&mut app, KeyCode::Char('j'), KeyModifiers::NONE);
+        press(&mut app, KeyCode::Char('j'), KeyModifiers::NONE);
+        assert_eq!(app.chosen().len(), 3);
+        press(&mut app, KeyCode::Char('p'), KeyModifiers::NONE);
+        let sequence = app.sequence.as_ref().unwrap();
+        let order: Vec<&str> = sequence.commits.iter().map(|commit| commit.message.as_str()).collect();
+        assert_eq!(order, ["one", "two", "three"]);
+        run_sequence(&mut app);
+        assert!(app.sequence.is_none());
+        assert_eq!(history(&repo), ["three", "two", "one", "base"]);
+        assert!(app.chosen().is_empty());
+
+        // Marked commits are reverted newest first
+        let mut app = new_app(&repo, "default");
+        let picked = repo.get_commits(&FilterOptions::default()).unwrap();
+        app.marked = picked[..3].iter().filter(|commit| commit.message != "two").map(|commit| commit.hash.clone()).collect();
+        press(&mut app, KeyCode::Char('r'), KeyModifiers::NONE);
+        let order: Vec<&str> = app.sequence.as_ref().unwrap().commits.iter().map(|commit| commit.message.as_str()).collect();
+        assert_eq!(order, ["three", "one"]);
+        run_sequence(&mut app);
+        assert_eq!(history(&repo)[..2], ["Revert \"one\"", "Revert \"three\""]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sequence_stops_on_conflict() {
+        let (dir, repo) = repo_with("stop", &[]);
+        let commit = |path: &str, content: &str| {
+            std::fs::write(dir.join(path), content).unwrap();
+            repo.stage(path).unwrap();
+            repo.commit(content).unwrap()
+        };
+        let base = commit("a", "base");
+        commit("x", "x");
+        commit("a", "theirs");
+        let y = commit("y", "y");
+        repo.create_branch("side", &y).unwrap();
+        repo.reset(&base, ResetMode::Hard).unwrap();
+        commit("a", "ours");
+
+        let mut app = new_app(&repo, "default");
+        let side = app.commits.iter().filter(|commit| ["x", "theirs", "y"].contains(&commit.message.as_str()));
+        app.marked = side.map(|commit| commit.hash.clone()).collect();
+        press(&mut app, KeyCode::Char('p'), KeyModifiers::NONE);
+        run_sequence(&mut app);
+        let sequence = app.sequence.as_ref().unwrap();
+        assert!(sequence.paused);
+        assert_eq!(sequence.done, 1);
+        assert!(app.conflicts.is_some());
+        assert_eq!(repo.operation(), Some("cherry-pick"));
+        assert_eq!(history(&repo), ["x", "ours", "base"]);
+
+        // Resolving and committing goes on with the rest
+        press(&mut app, KeyCode::Char('t'), KeyModifiers::NONE);
+        press(&mut app, KeyCode::Char('c'), KeyModifiers::NONE);
+        run_sequence(&mut app);
+        assert!(app.sequence.is_none());
+        assert_eq!(history(&repo), ["y", "theirs", "x", "ours", "base"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reset_menu_moves_before_letters() {
+        let (dir, repo) = repo_with("reset-menu", &["one", "two"]);
+        let mut app = new_app(&repo, "default");
+        select(&mut app, "one");
+        press(&mut app, KeyCode::Char('x'), KeyModifiers::NONE);
+        press(&mut app, KeyCode::Char('h'), KeyModifiers::NONE);
+        assert_eq!(app.reset_menu.as_ref().unwrap().mode, ResetMode::Hard);
+        press(&mut app, KeyCode::Char('k'), KeyModifiers::NONE);
+        assert_eq!(app.reset_menu.as_ref().unwrap().mode, ResetMode::Mixed);
+        press(&mut app, KeyCode::Char('s'), KeyModifiers::CONTROL);
+        assert_eq!(app.reset_menu.as_ref().unwrap().mode, ResetMode::Mixed);
+        assert_eq!(app.mode_letters(&ResetMode::ALL.map(|mode| mode.name())), "s/m/h");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sidebar_chords_never_change_branches() {
+        let (dir, repo) = repo_with("sidebar", &["one", "two"]);
+        let one = repo.get_commits(&FilterOptions::default()).unwrap()[1].hash.clone();
+        repo.create_branch("feature", &one).unwrap();
+        let mut app = new_app(&repo, "vim");
+        select(&mut app, "two");
+        press(&mut app, KeyCode::Tab, KeyModifiers::NONE);
+        let sidebar = app.sidebar.as_mut().unwrap();
+        sidebar.selected = sidebar.refs.iter().position(|entry| entry.short == "feature").unwrap();
+        for c in ['d', 'f', 'D', 'u', 'm'] {
+            press(&mut app, KeyCode::Char(c), KeyModifiers::CONTROL);
+        }
+        assert!(app.sidebar.as_ref().unwrap().prompt.is_none());
+        let refs = repo.refs(Sort::Name).unwrap();
+        let feature = refs.iter().find(|entry| entry.short == "feature").unwrap();
+        assert_eq!(feature.target, one);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sidebar_bindings_win_and_force_delete_asks() {
+        let (dir, repo) = repo_with("sidebar-bound", &["one", "two"]);
+        let one = repo.get_commits(&FilterOptions::default()).unwrap()[1].hash.clone();
+        repo.create_branch("feature", &one).unwrap();
+        let mut app = bound_app(&repo, "default", &[("move_down", "d")]);
+        app.config.confirm_dangerous = true;
+        press(&mut app, KeyCode::Tab, KeyModifiers::NONE);
+        let feature = |app: &App| {
+            let sidebar = app.sidebar.as_ref().unwrap();
+            sidebar.refs.iter().position(|entry| entry.short == "feature")
+        };
+        let at = feature(&app).unwrap();
+        app.sidebar.as_mut().unwrap().selected = at;
+        press(&mut app, KeyCode::Char('d'), KeyModifiers::NONE);
+        assert_eq!(app.sidebar.as_ref().unwrap().selected, at + 1);
+        assert!(feature(&app).is_some());
+
+        app.sidebar.as_mut().unwrap().selected = at;
+        press(&mut app, KeyCode::Char('D'), KeyModifiers::SHIFT);
+        press(&mut app, KeyCode::Char('n'), KeyModifiers::NONE);
+        assert!(app.sidebar.as_ref().unwrap().prompt.is_none());
+        assert!(feature(&app).is_some());
+        press(&mut app, KeyCode::Char('D'), KeyModifiers::SHIFT);
+        press(&mut app, KeyCode::Char('y'), KeyModifiers::NONE);
+        assert!(feature(&app).is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_tag_list_bindings_win_over_letters() {
+        let (dir, repo) = repo_with("tag-list-bound", &["one", "two"]);
+        let one = repo.get_commits(&FilterOptions::default()).unwrap()[1].hash.clone();
+        repo.create_tag("v1", &one, None).unwrap();
+        repo.create_tag("v2", &one, None).unwrap();
+        let mut app = bound_app(&repo, "default", &[("move_down", "d"), ("top", "f")]);
+        select(&mut app, "two");
+        press(&mut app, KeyCode::Char('T'), KeyModifiers::SHIFT);
+        press(&mut app, KeyCode::Char('d'), KeyModifiers::NONE);
+        assert_eq!(app.tag_list.as_ref().unwrap().selected, 1);
+        press(&mut app, KeyCode::Char('f'), KeyModifiers::NONE);
+        assert_eq!(app.tag_list.as_ref().unwrap().selected, 0);
+        let tags = repo.tags().unwrap();
+        assert_eq!(tags.len(), 2);
+        assert!(tags.iter().all(|tag| tag.target == one));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_tag_list_chords_never_change_tags() {
+        let (dir, repo) = repo_with("tag-list", &["one", "two"]);
+        let one = repo.get_commits(&FilterOptions::default()).unwrap()[1].hash.clone();
+        repo.create_tag("v1", &one, None).unwrap();
+        let mut app = new_app(&repo, "vim");
+        select(&mut app, "two");
+        press(&mut app, KeyCode::Char('T'), KeyModifiers::SHIFT);
+        for c in ['d', 'f'] {
+            press(&mut app, KeyCode::Char(c), KeyModifiers::CONTROL);
+        }
+        let tags = repo.tags().unwrap();
+        assert_eq!((tags[0].name.as_str(), tags[0].target.as_str()), ("v1", one.as_str()));
+
+        press(&mut app, KeyCode::Char('f'), KeyModifiers::NONE);
+        assert_ne!(repo.tags().unwrap()[0].target, one);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_cancelled_fixup_is_taken_back() {
+        let (dir, repo) = repo_with("fixup", &["one", "two", "three"]);
+        let three = repo.head_hash().unwrap();
+        std::fs::write(dir.join("two"), "fixed").unwrap();
+        repo.stage("two").unwrap();
+
+        let mut app = new_app(&repo, "default");
+        select(&mut app, "two");
+        press(&mut app, KeyCode::Char('F'), KeyModifiers::SHIFT);
+        assert_ne!(repo.head_hash().unwrap(), three);
+        let planner = app.planner.as_ref().unwrap();
+        let commands: Vec<Command> = planner.plan.steps.iter().map(|step| step.command).collect();
+        assert_eq!(commands, [Command::Pick, Command::Fixup, Command::Pick]);
+
+        press(&mut app, KeyCode::Char('q'), KeyModifiers::NONE);
+        assert!(app.planner.is_none());
+        assert_eq!(repo.head_hash().unwrap(), three);
+        assert_eq!(repo.status_counts().unwrap().staged, 1);
+        assert!(repo.undo().unwrap().starts_with("Undid commit \"three\""));
+
+        repo.redo().unwrap();
+        assert_eq!(repo.status_counts().unwrap().staged, 1);
+
+        // Unstaged changes would stop the rebase, so nothing is committed
+        std::fs::write(dir.join("three"), "unstaged").unwrap();
+        let mut app = new_app(&repo, "default");
+        select(&mut app, "two");
+        press(&mut app, KeyCode::Char('F'), KeyModifiers::SHIFT);
+        assert!(app.planner.is_none());
+        assert!(app.message.as_ref().unwrap().text.contains("unstaged changes"));
+        assert_eq!(repo.head_hash().unwrap(), three);
+
+        std::fs::write(dir.join("three"), "three").unwrap();
+        let mut app = new_app(&repo, "default");
+        select(&mut app, "two");
+        press(&mut app, KeyCode::Char('F'), KeyModifiers::SHIFT);
+        press(&mut app, KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(history(&repo), ["three", "two", "one"]);
+        assert!(repo.status_counts().unwrap().is_clean());
+        assert_eq!(std::fs::read_to_string(dir.join("two")).unwrap(), "fixed");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// A repository stopped in a cherry-pick that conflicts on `a`.
+    fn conflicted(name: &str) -> (PathBuf, Repository) {
+        let (dir, repo) = repo_with(name, &[]);
+        let commit = |content: &str| {
+            std::fs::write(dir.join("a"), content).unwrap();
+            repo.stage("a").unwrap();
+            repo.commit(content).unwrap()
+        };
+        let base = commit("base");
+        let theirs = commit("theirs");
+        repo.reset(&base, ResetMode::Hard).unwrap();
+        commit("ours");
+        repo.cherry_pick(&theirs, 0).unwrap();
+        (dir, repo)
+    }
+
+    #[test]
+    fn test_conflict_letters_ignore_chords() {
+        let (dir, repo) = conflicted("conflicts");
+        let mut app = new_app(&repo, "emacs");
+        app.open_conflicts().unwrap();
+        for c in ['s', 'a', 'c', 'o', 't'] {
+            press(&mut app, KeyCode::Char(c), KeyModifiers::CONTROL);
+        }
+        assert_eq!(repo.operation(), Some("cherry-pick"));
+        assert!(std::fs::read_to_string(dir.join("a")).unwrap().contains("<<<<<<<"));
+
+        press(&mut app, KeyCode::Char('s'), KeyModifiers::NONE);
+        assert_eq!(repo.operation(), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_planner_letters_ignore_chords() {
+        let (dir, repo) = repo_with("planner", &["one", "two", "three"]);
+        for preset in ["vim", "emacs"] {
+            let mut app = new_app(&repo, preset);
+            app.select_commit(2);
+            press(&mut app, KeyCode::Char('i'), KeyModifiers::NONE);
+            press(&mut app, KeyCode::Char('e'), KeyModifiers::NONE);
+            for c in ['d', 'f', 'p', 's'] {
+                press(&mut app, KeyCode::Char(c), KeyModifiers::CONTROL);
+            }
+            let planner = app.planner.as_ref().unwrap();
+            let commands: Vec<Command> = planner.plan.steps.iter().map(|step| step.command).collect();
+            assert_eq!(commands, [Command::Edit, Command::Pick], "{}", preset);
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_planner_bindings_win_over_letters() {
+        let (dir, repo) = repo_with("planner-bound", &["one", "two", "three"]);
+        let mut app = bound_app(&repo, "default", &[("move_down", "d")]);
+        app.select_commit(2);
+        press(&mut app, KeyCode::Char('i'), KeyModifiers::NONE);
+        press(&mut app, KeyCode::Char('d'), KeyModifiers::NONE);
+        let planner = app.planner.as_ref().unwrap();
+        assert_eq!(planner.selected, 1);
+        let commands: Vec<Command> = planner.plan.steps.iter().map(|step| step.command).collect();
+        assert_eq!(commands, [Command::Pick, Command::Pick]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}